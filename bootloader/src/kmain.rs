@@ -3,6 +3,7 @@
 extern crate pi;
 extern crate xmodem;
 
+use pi::gpio::GpioBank;
 use pi::timer;
 use pi::uart::MiniUart;
 use std::fmt::Write;
@@ -34,12 +35,13 @@ fn jump_to(addr: *mut u8) -> ! {
 
 #[no_mangle]
 pub extern "C" fn kmain() {
+    let mut bank = GpioBank::new();
+    let mut uart = MiniUart::new(&mut bank);
     let err;
     loop {
-        let mut uart = MiniUart::new();
         uart.read_byte();
         let mut binary = unsafe { slice::from_raw_parts_mut(BINARY_START, MAX_BINARY_SIZE) };
-        let n = match Xmodem::receive(uart, &mut binary) {
+        let n = match Xmodem::receive(&mut uart, &mut binary) {
             Ok(n) => n,
             Err(error) => match error.kind() {
                 ErrorKind::TimedOut => continue,
@@ -55,7 +57,6 @@ pub extern "C" fn kmain() {
         }
     }
 
-    let mut uart = MiniUart::new();
     loop {
         timer::spin_sleep_ms(1000);
         write!(&mut uart, "{}\n", err).unwrap();
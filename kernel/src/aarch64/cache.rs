@@ -0,0 +1,195 @@
+use aarch64::{self, isb, SCTLR_C, SCTLR_I};
+
+/// Returns the size in bytes of the smallest data cache line.
+pub fn dcache_line_size() -> usize {
+    4 << ((ctr() >> 16) & 0xF)
+}
+
+/// Returns the size in bytes of the smallest instruction cache line.
+pub fn icache_line_size() -> usize {
+    4 << (ctr() & 0xF)
+}
+
+fn ctr() -> u64 {
+    let ctr: u64;
+    unsafe { asm!("mrs $0, ctr_el0" : "=r"(ctr)) }
+    ctr
+}
+
+/// Applies `op` to the address of every data cache line overlapping
+/// `[start, start + len)` and waits for the operations to complete.
+unsafe fn for_each_line(start: usize, len: usize, op: unsafe fn(usize)) {
+    let line = dcache_line_size();
+    let mut addr = start & !(line - 1);
+    while addr < start + len {
+        op(addr);
+        addr += line;
+    }
+
+    asm!("dsb sy" ::: "memory" : "volatile");
+}
+
+unsafe fn dc_cvac(addr: usize) {
+    asm!("dc cvac, $0" :: "r"(addr) : "memory" : "volatile");
+}
+
+unsafe fn dc_ivac(addr: usize) {
+    asm!("dc ivac, $0" :: "r"(addr) : "memory" : "volatile");
+}
+
+unsafe fn dc_civac(addr: usize) {
+    asm!("dc civac, $0" :: "r"(addr) : "memory" : "volatile");
+}
+
+unsafe fn dc_cvau(addr: usize) {
+    asm!("dc cvau, $0" :: "r"(addr) : "memory" : "volatile");
+}
+
+/// Writes any dirty data in `[start, start + len)` back to main memory. Call
+/// this before a DMA engine reads the range.
+///
+/// # Safety
+///
+/// The range must be mapped.
+pub unsafe fn clean_range(start: usize, len: usize) {
+    for_each_line(start, len, dc_cvac);
+}
+
+/// Discards any cached data in `[start, start + len)` so that the next read
+/// comes from main memory. Call this before reading data that a DMA engine
+/// wrote to the range.
+///
+/// # Safety
+///
+/// The range must be mapped. Dirty data in lines that only partially overlap
+/// the range is lost along with the data in the range.
+pub unsafe fn invalidate_range(start: usize, len: usize) {
+    for_each_line(start, len, dc_ivac);
+}
+
+/// Writes any dirty data in `[start, start + len)` back to main memory and
+/// then discards it from the caches.
+///
+/// # Safety
+///
+/// The range must be mapped.
+pub unsafe fn clean_invalidate_range(start: usize, len: usize) {
+    for_each_line(start, len, dc_civac);
+}
+
+/// Makes code written to `[start, start + len)` visible to instruction fetches
+/// on every core.
+///
+/// The range is cleaned to the point of unification and then the entire
+/// instruction cache is invalidated: the Cortex-A53's instruction cache is
+/// VIPT and may hold the code under an alias of a different virtual address,
+/// such as a user mapping of a page written through the kernel's.
+///
+/// # Safety
+///
+/// The range must be mapped.
+#[cfg(not(test))]
+pub unsafe fn sync_icache(start: usize, len: usize) {
+    for_each_line(start, len, dc_cvau);
+    invalidate_icache();
+}
+
+/// Host tests load code they never execute.
+#[cfg(test)]
+pub unsafe fn sync_icache(_start: usize, _len: usize) {}
+
+/// Invalidates the instruction caches of every core in the inner shareable
+/// domain.
+pub fn invalidate_icache() {
+    unsafe {
+        asm!("dsb ish
+              ic ialluis
+              dsb ish
+              isb"
+             ::: "memory" : "volatile");
+    }
+}
+
+/// Applies `op` to every set and way of each data or unified cache level below
+/// `levels` and waits for the operations to complete.
+unsafe fn for_each_set_way(levels: u64, op: unsafe fn(u64)) {
+    let clidr: u64;
+    asm!("mrs $0, clidr_el1" : "=r"(clidr));
+
+    for level in 0..levels {
+        // Cache type 0b010 and above have a data or unified cache.
+        if (clidr >> (level * 3)) & 0b111 < 0b010 {
+            continue;
+        }
+
+        let ccsidr: u64;
+        asm!("msr csselr_el1, $0" :: "r"(level << 1) :: "volatile");
+        isb();
+        asm!("mrs $0, ccsidr_el1" : "=r"(ccsidr) ::: "volatile");
+
+        let line_shift = (ccsidr & 0b111) + 4;
+        let ways = ((ccsidr >> 3) & 0x3FF) + 1;
+        let sets = ((ccsidr >> 13) & 0x7FFF) + 1;
+        let way_shift = ((ways - 1) as u32).leading_zeros() as u64;
+        for way in 0..ways {
+            for set in 0..sets {
+                let way_bits = if way == 0 { 0 } else { way << way_shift };
+                op(way_bits | (set << line_shift) | (level << 1));
+            }
+        }
+    }
+
+    asm!("dsb sy" ::: "memory" : "volatile");
+}
+
+unsafe fn dc_isw(set_way: u64) {
+    asm!("dc isw, $0" :: "r"(set_way) : "memory" : "volatile");
+}
+
+unsafe fn dc_cisw(set_way: u64) {
+    asm!("dc cisw, $0" :: "r"(set_way) : "memory" : "volatile");
+}
+
+/// Cleans and invalidates every data cache level up to the point of coherence
+/// by set/way, writing all dirty data held by the calling core back to main
+/// memory.
+///
+/// This is needed before an agent that bypasses the caches, such as a core
+/// with its MMU off, reads memory written by the calling core.
+pub fn clean_invalidate_all() {
+    let clidr: u64;
+    unsafe {
+        asm!("mrs $0, clidr_el1" : "=r"(clidr));
+        for_each_set_way((clidr >> 24) & 0b111, dc_cisw);
+    }
+}
+
+/// Invalidates the calling core's private data cache levels, those below the
+/// level of unification for the inner shareable domain, and its instruction
+/// cache.
+///
+/// # Safety
+///
+/// Any dirty data in the invalidated levels is lost. This should only be
+/// called while the calling core's caches are disabled.
+pub unsafe fn invalidate_local() {
+    let clidr: u64;
+    asm!("mrs $0, clidr_el1" : "=r"(clidr));
+    for_each_set_way((clidr >> 21) & 0b111, dc_isw);
+    asm!("ic iallu
+          dsb nsh
+          isb"
+         ::: "memory" : "volatile");
+}
+
+/// Invalidates the calling core's private caches and then enables its data
+/// and instruction caches.
+///
+/// # Safety
+///
+/// Must be called with the MMU enabled and the caches disabled.
+pub unsafe fn enable() {
+    invalidate_local();
+    aarch64::set_sctlr(aarch64::sctlr() | SCTLR_C | SCTLR_I);
+    isb();
+}
@@ -0,0 +1,235 @@
+pub mod cache;
+
+/// Returns the current stack pointer.
+#[inline(always)]
+pub fn sp() -> *const u8 {
+    let ptr: usize;
+    unsafe {
+        asm!("mov $0, sp" : "=r"(ptr));
+    }
+
+    ptr as *const u8
+}
+
+/// Returns the current exception level.
+///
+/// # Safety
+/// This function should only be called when EL is >= 1.
+#[inline(always)]
+pub unsafe fn current_el() -> u8 {
+    let el_reg: u64;
+    asm!("mrs $0, CurrentEL" : "=r"(el_reg));
+    ((el_reg & 0b1100) >> 2) as u8
+}
+
+/// Returns the SPSel value.
+#[inline(always)]
+pub fn sp_sel() -> u8 {
+    let ptr: u32;
+    unsafe {
+        asm!("mrs $0, SPSel" : "=r"(ptr));
+    }
+
+    (ptr & 1) as u8
+}
+
+/// Returns the core currently executing.
+///
+/// # Safety
+///
+/// This function should only be called when EL is >= 1.
+pub unsafe fn affinity() -> usize {
+    let x: usize;
+    asm!("mrs     $0, mpidr_el1
+          and     $0, $0, #3"
+          : "=r"(x));
+
+    x
+}
+
+/// A NOOP that won't be optimized out.
+pub fn nop() {
+    unsafe {
+        asm!("nop" :::: "volatile");
+    }
+}
+
+// wait for interrupt
+pub fn wfi() {
+    unsafe {
+        asm!("wfi" :::: "volatile");
+    }
+}
+
+/// Wait for event.
+pub fn wfe() {
+    unsafe {
+        asm!("wfe" :::: "volatile");
+    }
+}
+
+/// Masks IRQs and FIQs on the calling core and returns the previous value of
+/// `DAIF`, to be passed to `restore_interrupts()`.
+#[inline(always)]
+pub fn mask_interrupts() -> u64 {
+    let daif: u64;
+    unsafe {
+        asm!("mrs $0, daif
+              msr daifset, #0b0011"
+             : "=r"(daif) ::: "volatile");
+    }
+
+    daif
+}
+
+/// Unmasks IRQs and FIQs on the calling core and returns the previous value
+/// of `DAIF`, to be passed to `restore_interrupts()`.
+#[inline(always)]
+pub fn unmask_interrupts() -> u64 {
+    let daif: u64;
+    unsafe {
+        asm!("mrs $0, daif
+              msr daifclr, #0b0011"
+             : "=r"(daif) :: "memory" : "volatile");
+    }
+
+    daif
+}
+
+/// Restores the calling core's interrupt masks to `daif`, a value returned by
+/// `mask_interrupts()` or `unmask_interrupts()`.
+#[inline(always)]
+pub fn restore_interrupts(daif: u64) {
+    unsafe { asm!("msr daif, $0" :: "r"(daif) :: "volatile") }
+}
+
+/// Sends an event to every core, waking those waiting in `wfe`.
+pub fn sev() {
+    unsafe {
+        asm!("dsb sy
+              sev" ::: "memory" : "volatile");
+    }
+}
+
+/// Enables the generic timer's event stream on the calling core so that a
+/// `wfe` returns at least every 2^(`bit` + 1) counter ticks even if no core
+/// executes `sev`.
+///
+/// # Safety
+///
+/// This function should only be called when EL is >= 1.
+pub unsafe fn enable_event_stream(bit: u8) {
+    let mut cntkctl: u64;
+    asm!("mrs $0, cntkctl_el1" : "=r"(cntkctl));
+    cntkctl &= !(0b1111 << 4);
+    cntkctl |= (1 << 2) | (((bit & 0b1111) as u64) << 4);
+    asm!("msr cntkctl_el1, $0" :: "r"(cntkctl) :: "volatile");
+}
+
+/// Disables the generic timer's event stream on the calling core.
+///
+/// # Safety
+///
+/// This function should only be called when EL is >= 1.
+pub unsafe fn disable_event_stream() {
+    let mut cntkctl: u64;
+    asm!("mrs $0, cntkctl_el1" : "=r"(cntkctl));
+    cntkctl &= !(1 << 2);
+    asm!("msr cntkctl_el1, $0" :: "r"(cntkctl) :: "volatile");
+}
+
+pub fn sctlr() -> u64 {
+    let sctlr_reg: u64;
+    unsafe { asm!("mrs $0, sctlr_el1" : "=r"(sctlr_reg)) }
+    sctlr_reg
+}
+
+/// `SPSR_EL1` value returning to EL0 with all interrupts unmasked.
+pub const SPSR_EL0T: u64 = 0b0000;
+
+/// `SPSR_EL1` value returning to EL1, using `SP_EL0`, with all interrupts
+/// unmasked.
+pub const SPSR_EL1T: u64 = 0b0100;
+
+/// `SCTLR_EL1.M`: enables stage 1 address translation.
+pub const SCTLR_M: u64 = 1 << 0;
+
+/// `SCTLR_EL1.C`: enables data and unified caches.
+pub const SCTLR_C: u64 = 1 << 2;
+
+/// `SCTLR_EL1.I`: enables instruction caches.
+pub const SCTLR_I: u64 = 1 << 12;
+
+/// Writes `val` to `SCTLR_EL1`.
+///
+/// # Safety
+///
+/// Changing the system control register can change the translation regime
+/// and memory attributes of the executing code.
+#[inline(always)]
+pub unsafe fn set_sctlr(val: u64) {
+    asm!("msr sctlr_el1, $0" :: "r"(val) :: "volatile");
+}
+
+/// Returns the value of `ID_AA64MMFR0_EL1`.
+pub fn id_aa64mmfr0() -> u64 {
+    let val: u64;
+    unsafe { asm!("mrs $0, id_aa64mmfr0_el1" : "=r"(val)) }
+    val
+}
+
+/// Writes `val` to `MAIR_EL1`.
+#[inline(always)]
+pub unsafe fn set_mair(val: u64) {
+    asm!("msr mair_el1, $0" :: "r"(val) :: "volatile");
+}
+
+/// Writes `val` to `TCR_EL1`.
+#[inline(always)]
+pub unsafe fn set_tcr(val: u64) {
+    asm!("msr tcr_el1, $0" :: "r"(val) :: "volatile");
+}
+
+/// Returns the value of `FAR_EL1`, the address that caused the last data or
+/// instruction abort.
+pub fn far() -> u64 {
+    let val: u64;
+    unsafe { asm!("mrs $0, far_el1" : "=r"(val) ::: "volatile") }
+    val
+}
+
+/// Returns the value of `TTBR0_EL1`.
+pub fn ttbr0() -> u64 {
+    let val: u64;
+    unsafe { asm!("mrs $0, ttbr0_el1" : "=r"(val) ::: "volatile") }
+    val
+}
+
+/// Writes `val` to `TTBR0_EL1`.
+#[inline(always)]
+pub unsafe fn set_ttbr0(val: u64) {
+    asm!("msr ttbr0_el1, $0" :: "r"(val) :: "volatile");
+}
+
+/// Instruction synchronization barrier.
+#[inline(always)]
+pub fn isb() {
+    unsafe { asm!("isb" ::: "memory" : "volatile") }
+}
+
+/// Full system data synchronization barrier.
+#[inline(always)]
+pub fn dsb() {
+    unsafe { asm!("dsb sy" ::: "memory" : "volatile") }
+}
+
+/// Invalidates all stage 1 EL1&0 TLB entries in the inner shareable domain
+/// and waits for the invalidation to complete.
+#[inline(always)]
+pub unsafe fn tlb_invalidate_all() {
+    asm!("dsb ishst
+          tlbi vmalle1is
+          dsb ish
+          isb"
+         ::: "memory" : "volatile");
+}
@@ -0,0 +1,81 @@
+use pi::timer::{allocate_channel, release_channel, Channel, Timer};
+
+use mutex::IrqSafeMutex;
+use traps::{IrqHandler, TrapFrame};
+use IRQ;
+
+/// A function called when an alarm goes off. It is called in interrupt
+/// context and must not block.
+pub type AlarmHandler = fn();
+
+#[derive(Copy, Clone)]
+struct Alarm {
+    handler: AlarmHandler,
+    /// The period of a periodic alarm in microseconds.
+    period: Option<u32>,
+}
+
+/// The armed alarms, indexed by `index()` of their compare channel.
+static ALARMS: IrqSafeMutex<[Option<Alarm>; 2]> = IrqSafeMutex::new([None; 2]);
+
+fn index(channel: Channel) -> usize {
+    match channel {
+        Channel::One => 0,
+        Channel::Three => 1,
+    }
+}
+
+/// Arms an alarm on a free system timer compare channel that calls `handler`
+/// `us` microseconds from now and, if `period` is given, every `period`
+/// microseconds after that. Returns the channel, which identifies the alarm,
+/// or `None` if both channels are taken.
+pub fn set_alarm(us: u32, period: Option<u32>, handler: AlarmHandler) -> Option<Channel> {
+    let channel = allocate_channel()?;
+    ALARMS.lock()[index(channel)] = Some(Alarm { handler, period });
+
+    let irq_handler: IrqHandler = match channel {
+        Channel::One => handle_channel_1,
+        Channel::Three => handle_channel_3,
+    };
+    IRQ.register(channel.interrupt(), irq_handler);
+    Timer::new().arm(channel, us);
+    Some(channel)
+}
+
+/// Disarms the alarm on `channel` and frees the channel. Does nothing if
+/// there is no alarm on `channel`.
+pub fn cancel_alarm(channel: Channel) {
+    if ALARMS.lock()[index(channel)].take().is_none() {
+        return;
+    }
+
+    IRQ.unregister(channel.interrupt());
+    Timer::new().clear(channel);
+    release_channel(channel);
+}
+
+fn handle_channel_1(_: &mut TrapFrame) {
+    fire(Channel::One);
+}
+
+fn handle_channel_3(_: &mut TrapFrame) {
+    fire(Channel::Three);
+}
+
+/// Handles a match on `channel`: rearms a periodic alarm, or frees the
+/// channel of a one-shot one, and calls the alarm's handler.
+fn fire(channel: Channel) {
+    let alarm = ALARMS.lock()[index(channel)];
+    let mut timer = Timer::new();
+    match alarm {
+        Some(Alarm { handler, period: Some(period) }) => {
+            timer.rearm(channel, period);
+            handler();
+        }
+        Some(Alarm { handler, period: None }) => {
+            cancel_alarm(channel);
+            handler();
+        }
+        None => timer.clear(channel),
+    }
+}
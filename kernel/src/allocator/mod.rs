@@ -7,14 +7,16 @@ mod imp;
 #[cfg(test)]
 mod tests;
 
+#[cfg(test)]
+use alloc::heap::Heap;
 use alloc::heap::{Alloc, AllocErr, Layout};
-use mutex::Mutex;
+use mutex::IrqSafeMutex;
 use pi::atags::{Atag, Atags};
 use std::cmp::max;
 
 /// Thread-safe (locking) wrapper around a particular memory allocator.
 #[derive(Debug)]
-pub struct Allocator(Mutex<Option<imp::Allocator>>);
+pub struct Allocator(IrqSafeMutex<Option<imp::Allocator>>);
 
 impl Allocator {
     /// Returns an uninitialized `Allocator`.
@@ -22,7 +24,7 @@ impl Allocator {
     /// The allocator must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        Allocator(Mutex::new(None))
+        Allocator(IrqSafeMutex::new(None))
     }
 
     /// Initializes the memory allocator.
@@ -87,6 +89,23 @@ unsafe impl<'a> Alloc for &'a Allocator {
     }
 }
 
+/// The allocator behind `ALLOCATOR` in host tests, where the kernel's heap
+/// does not exist: allocations come from the host's heap.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct HostAllocator;
+
+#[cfg(test)]
+unsafe impl<'a> Alloc for &'a HostAllocator {
+    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        Heap.alloc(layout)
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        Heap.dealloc(ptr, layout)
+    }
+}
+
 extern "C" {
     static _end: u8;
 }
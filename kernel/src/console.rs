@@ -1,33 +1,190 @@
 use std::fmt;
 use std::io;
 
+use pi::atags::Atags;
+use pi::interrupt::Interrupt;
+use pi::pl011;
 use pi::uart::MiniUart;
 
-use mutex::Mutex;
+use gpio::GPIO_BANK;
+use mutex::IrqSafeMutex;
+use traps::TrapFrame;
+use {IRQ, SCHEDULER};
+
+/// The number of bytes buffered in each direction once the console is
+/// interrupt driven.
+const RING_SIZE: usize = 1024;
+
+/// A fixed-size FIFO of bytes.
+struct RingBuffer {
+    buf: [u8; RING_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    const fn new() -> RingBuffer {
+        RingBuffer {
+            buf: [0; RING_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == RING_SIZE
+    }
+
+    /// Appends `byte`. Returns `false`, dropping the byte, if the buffer is
+    /// full.
+    fn push(&mut self, byte: u8) -> bool {
+        if self.is_full() {
+            return false;
+        }
+
+        self.buf[(self.head + self.len) % RING_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % RING_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+/// The UART behind the console.
+enum Device {
+    MiniUart(MiniUart),
+    Pl011(pl011::Uart),
+}
+
+impl Device {
+    /// Initializes the UART named by `console=<mini|pl011>` on the kernel
+    /// command line, the mini UART by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the UART's pins were taken by another driver.
+    fn from_cmdline() -> Device {
+        let (gpio14, gpio15) = {
+            let mut bank = GPIO_BANK.lock();
+            (bank.take(14), bank.take(15))
+        };
+
+        let gpio14 = gpio14.expect("console: GPIO pin 14 taken");
+        let gpio15 = gpio15.expect("console: GPIO pin 15 taken");
+        match Atags::cmdline_arg("console") {
+            Some("pl011") => {
+                let config = pl011::Config::default();
+                Device::Pl011(pl011::Uart::with_pins(config, gpio14, gpio15))
+            }
+            _ => Device::MiniUart(MiniUart::with_pins(gpio14, gpio15)),
+        }
+    }
+
+    /// Returns the interrupt the UART raises.
+    fn interrupt(&self) -> Interrupt {
+        match *self {
+            Device::MiniUart(_) => Interrupt::Aux,
+            Device::Pl011(_) => Interrupt::Uart,
+        }
+    }
+
+    fn has_byte(&self) -> bool {
+        match *self {
+            Device::MiniUart(ref uart) => uart.has_byte(),
+            Device::Pl011(ref uart) => uart.has_byte(),
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        match *self {
+            Device::MiniUart(ref mut uart) => uart.read_byte(),
+            Device::Pl011(ref mut uart) => uart.read_byte(),
+        }
+    }
+
+    fn can_write(&self) -> bool {
+        match *self {
+            Device::MiniUart(ref uart) => uart.can_write(),
+            Device::Pl011(ref uart) => uart.can_write(),
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        match *self {
+            Device::MiniUart(ref mut uart) => uart.write_byte(byte),
+            Device::Pl011(ref mut uart) => uart.write_byte(byte),
+        }
+    }
+
+    fn set_rx_interrupt(&mut self, enabled: bool) {
+        match *self {
+            Device::MiniUart(ref mut uart) => uart.set_rx_interrupt(enabled),
+            Device::Pl011(ref mut uart) => uart.set_rx_interrupt(enabled),
+        }
+    }
+
+    fn set_tx_interrupt(&mut self, enabled: bool) {
+        match *self {
+            Device::MiniUart(ref mut uart) => uart.set_tx_interrupt(enabled),
+            Device::Pl011(ref mut uart) => uart.set_tx_interrupt(enabled),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Device::MiniUart(ref mut uart) => io::Write::flush(uart),
+            Device::Pl011(ref mut uart) => io::Write::flush(uart),
+        }
+    }
+}
 
 /// A global singleton allowing read/write access to the console.
+///
+/// Until `enable_interrupts()` is called, the console polls the UART. After
+/// that, received bytes are buffered by `handle_interrupt()` and written
+/// bytes are buffered until the UART has room for them.
 pub struct Console {
-    inner: Option<MiniUart>,
+    inner: Option<Device>,
+    rx: RingBuffer,
+    tx: RingBuffer,
+    interrupts: bool,
 }
 
 impl Console {
     /// Creates a new instance of `Console`.
     const fn new() -> Console {
-        Console { inner: None }
+        Console {
+            inner: None,
+            rx: RingBuffer::new(),
+            tx: RingBuffer::new(),
+            interrupts: false,
+        }
     }
 
     /// Initializes the console if it's not already initialized.
     #[inline]
     fn initialize(&mut self) {
         match self.inner {
-            None => self.inner = Some(MiniUart::new()),
+            None => self.inner = Some(Device::from_cmdline()),
             Some(_) => {}
         }
     }
 
-    /// Returns a mutable borrow to the inner `MiniUart`, initializing it as
-    /// needed.
-    fn inner(&mut self) -> &mut MiniUart {
+    /// Returns a mutable borrow to the inner UART, initializing it as needed.
+    fn inner(&mut self) -> &mut Device {
         self.initialize();
         if let Some(ref mut inner) = self.inner {
             inner
@@ -36,41 +193,154 @@ impl Console {
         }
     }
 
-    /// Reads a byte from the UART device, blocking until a byte is available.
-    pub fn read_byte(&mut self) -> u8 {
-        self.inner().read_byte()
+    /// Enables the UART's receive interrupt and registers its handler.
+    pub fn enable_interrupts(&mut self) {
+        self.inner().set_rx_interrupt(true);
+        self.interrupts = true;
+        let interrupt = self.inner().interrupt();
+        IRQ.register(interrupt, handle_irq);
+    }
+
+    /// Services a UART interrupt: buffers the received bytes and refills the
+    /// output FIFO. Returns `true` if any bytes were received.
+    pub fn handle_interrupt(&mut self) -> bool {
+        let mut received = false;
+        while self.inner().has_byte() {
+            let byte = self.inner().read_byte();
+            self.rx.push(byte);
+            received = true;
+        }
+
+        self.drain_tx();
+        received
+    }
+
+    /// Moves buffered output to the UART until its FIFO is full. The transmit
+    /// interrupt stays enabled while output remains buffered.
+    fn drain_tx(&mut self) {
+        while !self.tx.is_empty() && self.inner().can_write() {
+            let byte = self.tx.pop().unwrap();
+            self.inner().write_byte(byte);
+        }
+
+        let pending = !self.tx.is_empty();
+        self.inner().set_tx_interrupt(pending);
+    }
+
+    /// Reads a byte from the UART device without blocking. Returns `None` if
+    /// no byte is available. Processes wait for input by blocking on
+    /// `input_key()` rather than by spinning with the console locked.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        if let Some(byte) = self.rx.pop() {
+            return Some(byte);
+        }
+
+        if self.inner().has_byte() {
+            Some(self.inner().read_byte())
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if a byte is available to be read without blocking.
+    pub fn has_byte(&mut self) -> bool {
+        !self.rx.is_empty() || self.inner().has_byte()
     }
 
-    /// Writes the byte `byte` to the UART device.
+    /// Writes the byte `byte` to the UART device. Once interrupts are
+    /// enabled, this blocks only if the output buffer is full.
     pub fn write_byte(&mut self, byte: u8) {
-        self.inner().write_byte(byte)
+        if !self.interrupts {
+            return self.inner().write_byte(byte);
+        }
+
+        self.drain_tx();
+        if self.tx.is_empty() && self.inner().can_write() {
+            return self.inner().write_byte(byte);
+        }
+
+        if self.tx.is_full() {
+            let oldest = self.tx.pop().unwrap();
+            self.inner().write_byte(oldest);
+        }
+
+        self.tx.push(byte);
+        self.inner().set_tx_interrupt(true);
+    }
+}
+
+/// Services the console UART's interrupt and wakes the processes waiting for
+/// input if any arrived.
+fn handle_irq(_: &mut TrapFrame) {
+    // The console is unlocked before waking readers, which lock it again
+    // under the scheduler's lock.
+    let received = CONSOLE.lock().handle_interrupt();
+    if received {
+        SCHEDULER.wake(input_key(), usize::max_value());
     }
 }
 
+/// Returns the key that processes waiting for console input block on.
+pub fn input_key() -> u64 {
+    &CONSOLE as *const _ as u64
+}
+
+/// Reads the bytes available without blocking. Fails with `WouldBlock` if
+/// there are none.
 impl io::Read for Console {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.inner().read(buf)
+        let mut n = 0;
+        while n < buf.len() {
+            match self.read_byte() {
+                Some(byte) => buf[n] = byte,
+                None => break,
+            }
+            n += 1;
+        }
+
+        if n == 0 && buf.len() > 0 {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        Ok(n)
     }
 }
 
 impl io::Write for Console {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner().write(buf)
+        for &byte in buf {
+            self.write_byte(byte);
+        }
+
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        while let Some(byte) = self.tx.pop() {
+            self.inner().write_byte(byte);
+        }
+
+        if self.interrupts {
+            self.inner().set_tx_interrupt(false);
+        }
         self.inner().flush()
     }
 }
 
 impl fmt::Write for Console {
     fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.inner().write_str(s)
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+
+        Ok(())
     }
 }
 
 /// Global `Console` singleton.
-pub static CONSOLE: Mutex<Console> = Mutex::new(Console::new());
+pub static CONSOLE: IrqSafeMutex<Console> = IrqSafeMutex::new(Console::new());
 
 /// Internal function called by the `kprint[ln]!` macros.
 #[doc(hidden)]
@@ -99,3 +369,27 @@ macro_rules! kprint {
         _print(format_args!($($arg)*))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_wraps() {
+        let mut ring = RingBuffer::new();
+        for i in 0..RING_SIZE {
+            assert!(ring.push(i as u8));
+        }
+        assert!(ring.is_full());
+        assert!(!ring.push(0));
+
+        assert_eq!(ring.pop(), Some(0));
+        assert_eq!(ring.pop(), Some(1));
+        assert!(ring.push(42));
+        for i in 2..RING_SIZE {
+            assert_eq!(ring.pop(), Some(i as u8));
+        }
+        assert_eq!(ring.pop(), Some(42));
+        assert_eq!(ring.pop(), None);
+    }
+}
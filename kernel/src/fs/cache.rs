@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+use std::io;
+use std::sync::Arc;
+
+use fat32::traits::BlockDevice;
+use mutex::Mutex;
+
+/// Hit, miss and write-back counters for a `CachedDevice`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Accesses served from the cache.
+    pub hits: u64,
+    /// Accesses that had to read the sector from the device.
+    pub misses: u64,
+    /// Dirty sectors written back to the device.
+    pub writebacks: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    sector: u64,
+    data: Vec<u8>,
+    dirty: bool,
+}
+
+struct Cache {
+    device: Box<BlockDevice + Send>,
+    capacity: usize,
+    /// Cached sectors, least recently used first.
+    entries: VecDeque<CacheEntry>,
+    stats: CacheStats,
+}
+
+impl Cache {
+    /// Moves the entry for `sector` to the most recently used position and
+    /// returns it, reading the sector from the device if it isn't cached.
+    /// When `fill` is `false` a missing sector is not read; its contents are
+    /// left zeroed because the caller is about to overwrite them.
+    fn get(&mut self, sector: u64, fill: bool) -> io::Result<&mut CacheEntry> {
+        let entry = match self.entries.iter().position(|e| e.sector == sector) {
+            Some(index) => {
+                self.stats.hits += 1;
+                self.entries.remove(index).unwrap()
+            }
+            None => {
+                self.stats.misses += 1;
+                let mut data = vec![0; self.device.sector_size() as usize];
+                if fill {
+                    self.device.read_sector(sector, &mut data)?;
+                }
+
+                if self.entries.len() >= self.capacity {
+                    self.evict()?;
+                }
+
+                CacheEntry {
+                    sector,
+                    data,
+                    dirty: false,
+                }
+            }
+        };
+
+        self.entries.push_back(entry);
+        Ok(self.entries.back_mut().unwrap())
+    }
+
+    /// Removes the least recently used entry, writing it back if it's dirty.
+    fn evict(&mut self) -> io::Result<()> {
+        if let Some(entry) = self.entries.pop_front() {
+            if entry.dirty {
+                if let Err(e) = self.device.write_sector(entry.sector, &entry.data) {
+                    self.entries.push_front(entry);
+                    return Err(e);
+                }
+
+                self.stats.writebacks += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes every dirty entry back to the device.
+    fn sync(&mut self) -> io::Result<()> {
+        for entry in self.entries.iter_mut().filter(|e| e.dirty) {
+            self.device.write_sector(entry.sector, &entry.data)?;
+            entry.dirty = false;
+            self.stats.writebacks += 1;
+        }
+
+        Ok(())
+    }
+}
+
+/// A write-back cache of a bounded number of sectors in front of a
+/// `BlockDevice`, evicting the least recently used sector when full.
+///
+/// `CachedDevice` is a cheaply cloneable handle: all clones share one cache,
+/// so one handle can be given to a file system while another is kept to
+/// `sync()` or to read the statistics.
+#[derive(Clone)]
+pub struct CachedDevice(Arc<Mutex<Cache>>);
+
+impl CachedDevice {
+    /// Returns a cache of at most `capacity` sectors in front of `device`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new<T: BlockDevice + Send + 'static>(device: T, capacity: usize) -> CachedDevice {
+        assert!(capacity > 0, "CachedDevice::new(): zero capacity");
+        CachedDevice(Arc::new(Mutex::new(Cache {
+            device: Box::new(device),
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            stats: CacheStats::default(),
+        })))
+    }
+
+    /// Writes every dirty sector back to the device.
+    pub fn sync(&self) -> io::Result<()> {
+        self.0.lock().sync()
+    }
+
+    /// Returns the cache's hit, miss and write-back counters.
+    pub fn stats(&self) -> CacheStats {
+        self.0.lock().stats
+    }
+}
+
+impl BlockDevice for CachedDevice {
+    fn sector_size(&self) -> u64 {
+        self.0.lock().device.sector_size()
+    }
+
+    /// Reads sector `n` into `buf`, from the cache if possible. On success,
+    /// the number of bytes read is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf` is shorter
+    /// than a sector. Errors from the device are passed through.
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut cache = self.0.lock();
+        if (buf.len() as u64) < cache.device.sector_size() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let entry = cache.get(n, true)?;
+        buf[..entry.data.len()].copy_from_slice(&entry.data);
+        Ok(entry.data.len())
+    }
+
+    /// Writes a sector's worth of `buf` to sector `n` in the cache. The sector
+    /// reaches the device when it is evicted or on `sync()`. On success, the
+    /// number of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf` is shorter
+    /// than a sector. Errors from writing back an evicted sector are passed
+    /// through.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let mut cache = self.0.lock();
+        if (buf.len() as u64) < cache.device.sector_size() {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let entry = cache.get(n, false)?;
+        let len = entry.data.len();
+        entry.data.copy_from_slice(&buf[..len]);
+        entry.dirty = true;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fs::mem::MemDevice;
+
+    fn sector(byte: u8) -> Vec<u8> {
+        vec![byte; 512]
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let mut image = vec![0; 4 * 512];
+        image[512..1024].copy_from_slice(&sector(1));
+        let mut cache = CachedDevice::new(MemDevice::from_image(image, 512), 2);
+
+        let mut buf = [0u8; 512];
+        cache.read_sector(1, &mut buf).unwrap();
+        assert_eq!(&buf[..], &sector(1)[..]);
+        cache.read_sector(1, &mut buf).unwrap();
+        cache.read_sector(0, &mut buf).unwrap();
+        assert_eq!(&buf[..], &sector(0)[..]);
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.writebacks), (1, 2, 0));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let device = MemDevice::new(4, 512);
+        let mut cache = CachedDevice::new(device, 2);
+        let mut buf = [0u8; 512];
+
+        cache.write_sector(0, &sector(0xaa)).unwrap();
+        cache.read_sector(1, &mut buf).unwrap();
+        cache.read_sector(0, &mut buf).unwrap();
+        // sector 1 is now the least recently used and is evicted cleanly.
+        cache.read_sector(2, &mut buf).unwrap();
+        assert_eq!(cache.stats().writebacks, 0);
+        // sector 0 is evicted and written back.
+        cache.read_sector(3, &mut buf).unwrap();
+        assert_eq!(cache.stats().writebacks, 1);
+
+        cache.read_sector(0, &mut buf).unwrap();
+        assert_eq!(&buf[..], &sector(0xaa)[..]);
+    }
+
+    #[test]
+    fn sync_writes_back_dirty_sectors() {
+        let device = MemDevice::new(4, 512);
+        let mut cache = CachedDevice::new(device, 4);
+
+        cache.write_sector(2, &sector(7)).unwrap();
+        cache.write_sector(3, &sector(8)).unwrap();
+        cache.sync().unwrap();
+        assert_eq!(cache.stats().writebacks, 2);
+
+        cache.sync().unwrap();
+        assert_eq!(cache.stats().writebacks, 2);
+
+        let mut small = [0u8; 100];
+        let err = cache.read_sector(0, &mut small).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,118 @@
+use std::io;
+
+use fat32::traits::BlockDevice;
+
+/// An in-memory block device backed by a disk image.
+///
+/// Used as a RAM disk and to exercise the file system on the host without an
+/// SD card.
+#[derive(Debug, Clone)]
+pub struct MemDevice {
+    image: Vec<u8>,
+    sector_size: u64,
+}
+
+impl MemDevice {
+    /// Returns a zeroed device of `sectors` sectors of `sector_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is zero.
+    pub fn new(sectors: u64, sector_size: u64) -> MemDevice {
+        assert!(sector_size > 0, "MemDevice::new(): zero sector size");
+        MemDevice::from_image(vec![0; (sectors * sector_size) as usize], sector_size)
+    }
+
+    /// Returns a device backed by the disk image `image`. A trailing partial
+    /// sector is not addressable.
+    pub fn from_image(image: Vec<u8>, sector_size: u64) -> MemDevice {
+        MemDevice { image, sector_size }
+    }
+
+    /// Returns the disk image backing this device.
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// Returns the number of addressable sectors.
+    pub fn sectors(&self) -> u64 {
+        self.image.len() as u64 / self.sector_size
+    }
+
+    /// Returns the byte range of sector `n` in the image, checking that both
+    /// the sector and a buffer of `buf_len` bytes are valid.
+    fn range(&self, n: u64, buf_len: usize) -> io::Result<(usize, usize)> {
+        if n >= self.sectors() || (buf_len as u64) < self.sector_size {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        let start = (n * self.sector_size) as usize;
+        Ok((start, start + self.sector_size as usize))
+    }
+}
+
+impl BlockDevice for MemDevice {
+    fn sector_size(&self) -> u64 {
+        self.sector_size
+    }
+
+    /// Reads sector `n` into `buf`. On success, the number of bytes read is
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `n` is past the end
+    /// of the device or `buf` is shorter than a sector.
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let (start, end) = self.range(n, buf.len())?;
+        buf[..end - start].copy_from_slice(&self.image[start..end]);
+        Ok(end - start)
+    }
+
+    /// Writes a sector's worth of `buf` to sector `n`. On success, the number
+    /// of bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `n` is past the end
+    /// of the device or `buf` is shorter than a sector.
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let (start, end) = self.range(n, buf.len())?;
+        self.image[start..end].copy_from_slice(&buf[..end - start]);
+        Ok(end - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_write_round_trip() {
+        let mut device = MemDevice::new(4, 512);
+        let data: Vec<u8> = (0..512).map(|i| i as u8).collect();
+        assert_eq!(device.write_sector(2, &data).unwrap(), 512);
+
+        let mut buf = [0u8; 512];
+        assert_eq!(device.read_sector(2, &mut buf).unwrap(), 512);
+        assert_eq!(&buf[..], &data[..]);
+
+        device.read_sector(1, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == 0));
+        assert_eq!(&device.image()[1024..1536], &data[..]);
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let mut device = MemDevice::new(2, 512);
+        let mut small = [0u8; 256];
+        let mut buf = [0u8; 512];
+
+        let err = device.read_sector(2, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = device.read_sector(0, &mut small).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = device.write_sector(5, &buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,15 +1,26 @@
+mod cache;
+pub mod mem;
 pub mod sd;
 
 use std::io;
 use std::path::Path;
 
 pub use fat32::traits::FileSystem as FileSystemTrait;
+use fat32::traits::BlockDevice;
 use fat32::vfat::{self, Dir, Entry, File, Shared, VFat};
 
+pub use self::cache::{CacheStats, CachedDevice};
+
 use self::sd::Sd;
 use mutex::Mutex;
 
-pub struct FileSystem(Mutex<Option<Shared<VFat>>>);
+/// The number of sectors kept in the block cache below the file system.
+const CACHE_SECTORS: usize = 256;
+
+pub struct FileSystem {
+    vfat: Mutex<Option<Shared<VFat>>>,
+    cache: Mutex<Option<CachedDevice>>,
+}
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
@@ -17,7 +28,10 @@ impl FileSystem {
     /// The file system must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        FileSystem(Mutex::new(None))
+        FileSystem {
+            vfat: Mutex::new(None),
+            cache: Mutex::new(None),
+        }
     }
 
     /// Initializes the file system.
@@ -27,9 +41,59 @@ impl FileSystem {
     /// Panics if the underlying disk or file sytem failed to initialize.
     pub fn initialize(&self) {
         let sd = Sd::new().expect("sd init");
-        let vfat = VFat::from(sd).expect("vfat init");
-        let mut opt = self.0.lock();
-        opt.get_or_insert(vfat);
+        self.initialize_with(sd);
+    }
+
+    /// Initializes the file system on `device` instead of the SD card. Sector
+    /// accesses go through a block cache in front of `device`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file system on `device` failed to initialize.
+    pub fn initialize_with<T: BlockDevice + Send + 'static>(&self, device: T) {
+        let cache = CachedDevice::new(device, CACHE_SECTORS);
+        let vfat = VFat::from(cache.clone()).expect("vfat init");
+        self.vfat.lock().get_or_insert(vfat);
+        self.cache.lock().get_or_insert(cache);
+    }
+
+    /// Runs `f` while holding the file system's lock and returns its result.
+    ///
+    /// `File`s and `Dir`s access the file system directly rather than through
+    /// this `FileSystem`. Operations on them that may race with other cores
+    /// must be wrapped in `locked()`. `f` must not call methods of this
+    /// `FileSystem`: the lock is not reentrant.
+    pub fn locked<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        let _guard = self.vfat.lock();
+        f()
+    }
+
+    /// Writes all dirty cached sectors back to the disk.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file system is uninitialized.
+    pub fn sync(&self) -> io::Result<()> {
+        self.cache.lock().as_ref().unwrap().sync()
+    }
+
+    /// Returns the block cache's statistics.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file system is uninitialized.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().as_ref().unwrap().stats()
+    }
+}
+
+/// Fails unless the `fat32` crate implements writes, which the `fat32-write`
+/// feature asserts. Without it, calling the `VFat` write methods panics.
+fn check_writable() -> io::Result<()> {
+    if cfg!(feature = "fat32-write") {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, "read-only file system"))
     }
 }
 
@@ -39,29 +103,137 @@ impl<'a> FileSystemTrait for &'a FileSystem {
     type Entry = Entry;
 
     fn open<P: AsRef<Path>>(self, path: P) -> io::Result<Self::Entry> {
-        self.0.lock().as_mut().unwrap().open(path)
+        self.vfat.lock().as_mut().unwrap().open(path)
     }
 
-    fn create_file<P: AsRef<Path>>(self, _path: P) -> io::Result<Self::File> {
-        unimplemented!("read only file system")
+    fn create_file<P: AsRef<Path>>(self, path: P) -> io::Result<Self::File> {
+        check_writable()?;
+        self.vfat.lock().as_mut().unwrap().create_file(path)
     }
 
-    fn create_dir<P>(self, _path: P, _parents: bool) -> io::Result<Self::Dir>
+    fn create_dir<P>(self, path: P, parents: bool) -> io::Result<Self::Dir>
     where
         P: AsRef<Path>,
     {
-        unimplemented!("read only file system")
+        check_writable()?;
+        self.vfat.lock().as_mut().unwrap().create_dir(path, parents)
     }
 
-    fn rename<P, Q>(self, _from: P, _to: Q) -> io::Result<()>
+    fn rename<P, Q>(self, from: P, to: Q) -> io::Result<()>
     where
         P: AsRef<Path>,
         Q: AsRef<Path>,
     {
-        unimplemented!("read only file system")
+        check_writable()?;
+        self.vfat.lock().as_mut().unwrap().rename(from, to)
+    }
+
+    fn remove<P: AsRef<Path>>(self, path: P, children: bool) -> io::Result<()> {
+        check_writable()?;
+        self.vfat.lock().as_mut().unwrap().remove(path, children)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fat32::traits::Entry as EntryTrait;
+    use fs::mem::MemDevice;
+
+    const SECTOR_SIZE: usize = 512;
+    /// The first sector of the FAT32 partition.
+    const PARTITION_START: usize = 1;
+    const RESERVED_SECTORS: usize = 32;
+    const FAT_SECTORS: usize = 8;
+    /// The number of one-sector clusters in the data region.
+    const CLUSTERS: usize = 1000;
+
+    fn put_u16(image: &mut [u8], offset: usize, value: u16) {
+        image[offset] = value as u8;
+        image[offset + 1] = (value >> 8) as u8;
+    }
+
+    fn put_u32(image: &mut [u8], offset: usize, value: u32) {
+        put_u16(image, offset, value as u16);
+        put_u16(image, offset + 2, (value >> 16) as u16);
     }
 
-    fn remove<P: AsRef<Path>>(self, _path: P, _children: bool) -> io::Result<()> {
-        unimplemented!("read only file system")
+    /// Returns the image of a disk holding a single, empty FAT32 partition.
+    fn fat32_image() -> Vec<u8> {
+        let sectors = PARTITION_START + RESERVED_SECTORS + FAT_SECTORS + CLUSTERS;
+        let mut image = vec![0; sectors * SECTOR_SIZE];
+
+        // The master boot record with its first partition entry.
+        image[446 + 4] = 0x0c;
+        put_u32(&mut image, 446 + 8, PARTITION_START as u32);
+        put_u32(&mut image, 446 + 12, (sectors - PARTITION_START) as u32);
+        image[510..512].copy_from_slice(&[0x55, 0xaa]);
+
+        // The partition's extended BIOS parameter block.
+        let bpb = PARTITION_START * SECTOR_SIZE;
+        image[bpb..bpb + 3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        image[bpb + 3..bpb + 11].copy_from_slice(b"MSWIN4.1");
+        put_u16(&mut image, bpb + 11, SECTOR_SIZE as u16);
+        image[bpb + 13] = 1;
+        put_u16(&mut image, bpb + 14, RESERVED_SECTORS as u16);
+        image[bpb + 16] = 1;
+        image[bpb + 21] = 0xf8;
+        put_u32(&mut image, bpb + 32, (sectors - PARTITION_START) as u32);
+        put_u32(&mut image, bpb + 36, FAT_SECTORS as u32);
+        put_u32(&mut image, bpb + 44, 2);
+        image[bpb + 66] = 0x29;
+        image[bpb + 82..bpb + 90].copy_from_slice(b"FAT32   ");
+        image[bpb + 510..bpb + 512].copy_from_slice(&[0x55, 0xaa]);
+
+        // Clusters 0 and 1 are reserved. Cluster 2 holds the root directory.
+        let fat = (PARTITION_START + RESERVED_SECTORS) * SECTOR_SIZE;
+        put_u32(&mut image, fat, 0x0fff_fff8);
+        put_u32(&mut image, fat + 4, 0x0fff_ffff);
+        put_u32(&mut image, fat + 8, 0x0fff_ffff);
+        image
+    }
+
+    #[cfg(feature = "fat32-write")]
+    fn not_found(fs: &FileSystem, path: &str) -> bool {
+        fs.open(path).err().map(|error| error.kind()) == Some(io::ErrorKind::NotFound)
+    }
+
+    fn empty_fs() -> FileSystem {
+        let fs = FileSystem::uninitialized();
+        fs.initialize_with(MemDevice::from_image(fat32_image(), SECTOR_SIZE as u64));
+        fs
+    }
+
+    #[test]
+    #[cfg(not(feature = "fat32-write"))]
+    fn writes_fail_on_read_only_fat32() {
+        let fs = empty_fs();
+        assert!((&fs).create_dir("/logs", false).is_err());
+        assert!((&fs).create_file("/boot.txt").is_err());
+        assert!((&fs).rename("/a", "/b").is_err());
+        assert!((&fs).remove("/a", false).is_err());
+        assert!((&fs).open("/").expect("opened /").is_dir());
+    }
+
+    #[test]
+    #[cfg(feature = "fat32-write")]
+    fn create_rename_remove() {
+        let fs = empty_fs();
+        (&fs).create_dir("/logs", false).expect("created /logs");
+        (&fs).create_dir("/var/log", true).expect("created /var/log");
+        (&fs).create_file("/logs/boot.txt").expect("created /logs/boot.txt");
+        assert!((&fs).open("/logs").expect("opened /logs").is_dir());
+        assert!((&fs).open("/var/log").expect("opened /var/log").is_dir());
+        assert!((&fs).open("/logs/boot.txt").expect("opened /logs/boot.txt").is_file());
+
+        (&fs).rename("/logs/boot.txt", "/var/log/boot.txt").expect("renamed");
+        assert!(not_found(&fs, "/logs/boot.txt"));
+        assert!((&fs).open("/var/log/boot.txt").expect("opened renamed").is_file());
+
+        (&fs).remove("/logs", false).expect("removed /logs");
+        (&fs).remove("/var", true).expect("removed /var");
+        assert!(not_found(&fs, "/logs"));
+        assert!(not_found(&fs, "/var/log/boot.txt"));
+        fs.sync().expect("synced");
     }
 }
@@ -1,68 +1,75 @@
-use console::_print;
 use fat32::traits::BlockDevice;
-use pi::timer;
-use std::i32;
+use pi::emmc::{self, Emmc};
+use std::fmt;
 use std::io;
+use std::u32;
 
-extern "C" {
-    /// A global representing the last SD controller error that occured.
-    static sd_err: i64;
+pub use pi::emmc::Error;
 
-    /// Initializes the SD card controller.
+/// A handle to an SD card controller.
+pub struct Sd {
+    emmc: Emmc,
+}
+
+impl Sd {
+    /// Initializes the SD card controller and returns a handle to it.
+    pub fn new() -> Result<Sd, Error> {
+        Ok(Sd { emmc: Emmc::new()? })
+    }
+
+    /// Reads `buf.len() / 512` consecutive sectors starting at `sector` into
+    /// `buf` with a single multi-block transfer. On success, the number of
+    /// bytes read is returned.
+    ///
+    /// # Errors
     ///
-    /// Returns 0 if initialization is successful. If initialization fails,
-    /// returns -1 if a timeout occured, or -2 if an error sending commands to
-    /// the SD controller occured.
-    fn sd_init() -> i32;
+    /// See `read_sector()`. Additionally, an error of kind `InvalidInput` is
+    /// returned if `buf.len()` is not a multiple of 512.
+    pub fn read_sectors(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let sector = Sd::check(sector, buf.len())?;
+        self.emmc.read_blocks(sector, buf).map_err(Sd::io_error)?;
+        Ok(buf.len())
+    }
 
-    /// Reads sector `n` (512 bytes) from the SD card and writes it to `buffer`.
-    /// It is undefined behavior if `buffer` does not point to at least 512
-    /// bytes of memory.
+    /// Writes `buf` to `buf.len() / 512` consecutive sectors starting at
+    /// `sector` with a single multi-block transfer. On success, the number of
+    /// bytes written is returned.
     ///
-    /// On success, returns the number of bytes read: a positive number.
+    /// # Errors
     ///
-    /// On error, returns 0. The true error code is stored in the `sd_err`
-    /// global. `sd_err` will be set to -1 if a timeout occured or -2 if an
-    /// error sending commands to the SD controller occured. Other error codes
-    /// are also possible but defined only as being less than zero.
-    fn sd_readsector(n: i32, buffer: *mut u8) -> i32;
-}
+    /// See `write_sector()`. Additionally, an error of kind `InvalidInput` is
+    /// returned if `buf.len()` is not a multiple of 512.
+    pub fn write_sectors(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
+        let sector = Sd::check(sector, buf.len())?;
+        self.emmc.write_blocks(sector, buf).map_err(Sd::io_error)?;
+        Ok(buf.len())
+    }
 
-#[no_mangle]
-pub fn wait_micros(us: u32) {
-    timer::spin_sleep_us((100 * us).into())
-}
+    /// Checks that `sector` is addressable and that `len` is a non-zero
+    /// multiple of the sector size.
+    fn check(sector: u64, len: usize) -> io::Result<u32> {
+        if sector > u32::MAX as u64 || len == 0 || len % emmc::BLOCK_SIZE != 0 {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
 
-#[derive(Debug)]
-pub enum Error {
-    Timeout,
-    Control,
-    Unknown(i32),
-}
+        Ok(sector as u32)
+    }
 
-impl Error {
-    fn from(n: i32) -> Option<Error> {
-        match n {
-            n if n >= 0 => None,
-            -1 => Some(Error::Timeout),
-            -2 => Some(Error::Control),
-            n => Some(Error::Unknown(n)),
+    /// Converts a controller error into an I/O error.
+    fn io_error(error: Error) -> io::Error {
+        if error.is_timeout() {
+            io::Error::new(io::ErrorKind::TimedOut, format!("sd: {:?}", error))
+        } else {
+            io::Error::new(io::ErrorKind::Other, format!("sd: {:?}", error))
         }
     }
 }
 
-/// A handle to an SD card controller.
-#[derive(Debug)]
-pub struct Sd;
-
-impl Sd {
-    /// Initializes the SD card controller and returns a handle to it.
-    pub fn new() -> Result<Sd, Error> {
-        if let Some(err) = unsafe { Error::from(sd_init()) } {
-            Err(err)
-        } else {
-            Ok(Sd)
-        }
+impl fmt::Debug for Sd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Sd")
+            .field("high_capacity", &self.emmc.is_high_capacity())
+            .finish()
     }
 }
 
@@ -73,35 +80,37 @@ impl BlockDevice for Sd {
     /// # Errors
     ///
     /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
-    /// `n > 2^31 - 1` (the maximum value for an `i32`).
+    /// `n > 2^32 - 1` (the maximum block address).
     ///
     /// An error of kind `TimedOut` is returned if a timeout occurs while
     /// reading from the SD card.
     ///
     /// An error of kind `Other` is returned for all other errors.
     fn read_sector(&mut self, sector: u64, buf: &mut [u8]) -> io::Result<usize> {
-        if sector > i32::MAX as u64 || buf.len() < 512 {
+        if buf.len() < emmc::BLOCK_SIZE {
             return Err(io::ErrorKind::InvalidInput.into());
         }
 
-        let n = unsafe { sd_readsector(sector as i32, buf.as_mut_ptr()) };
-        if n == 0 {
-            let code = unsafe { sd_err };
-            match Error::from(code as i32) {
-                None => unreachable!("error code must be less than 0"),
-                Some(Error::Timeout) => Err(io::ErrorKind::TimedOut.into()),
-                Some(Error::Control) => Err(io::Error::new(io::ErrorKind::Other, "control error")),
-                Some(Error::Unknown(n)) => Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("unknown error code: {}", n),
-                )),
-            }
-        } else {
-            Ok(n as usize)
-        }
+        self.read_sectors(sector, &mut buf[..emmc::BLOCK_SIZE])
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    /// Writes `buf` to sector `n` of the SD card. On success, the number of
+    /// bytes written is returned.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
+    /// `n > 2^32 - 1` (the maximum block address).
+    ///
+    /// An error of kind `TimedOut` is returned if a timeout occurs while
+    /// writing to the SD card.
+    ///
+    /// An error of kind `Other` is returned for all other errors.
+    fn write_sector(&mut self, sector: u64, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() < emmc::BLOCK_SIZE {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+
+        self.write_sectors(sector, &buf[..emmc::BLOCK_SIZE])
     }
 }
@@ -0,0 +1,137 @@
+use std::mem::size_of;
+
+use pi::gpio::{clear_events, detected_events, Event, GpioBank, PIN_COUNT};
+use pi::interrupt::Interrupt;
+
+use mutex::IrqSafeMutex;
+use traps::TrapFrame;
+use {IRQ, SCHEDULER};
+
+/// The owner of the GPIO pins. Drivers take the pins they use from it.
+pub static GPIO_BANK: IrqSafeMutex<GpioBank> = IrqSafeMutex::new(GpioBank::new());
+
+/// A handler for the events detected on a GPIO pin. It is called with the
+/// pin's number in interrupt context, after the pin's event status has been
+/// cleared, and must not block. A handler for a level event should disarm it
+/// or it is called again right away.
+pub type Handler = fn(pin: u8);
+
+/// Event handlers, indexed by pin.
+static HANDLERS: IrqSafeMutex<[Option<Handler>; PIN_COUNT]> =
+    IrqSafeMutex::new([None; PIN_COUNT]);
+
+/// The number of events `wake_waiters()` was called for on each pin.
+static EVENTS: IrqSafeMutex<[u64; PIN_COUNT]> = IrqSafeMutex::new([0; PIN_COUNT]);
+
+/// A bit mask of the pins armed by `arm_wait()`; bit `n` is set for pin `n`.
+static WAIT_PINS: IrqSafeMutex<u64> = IrqSafeMutex::new(0);
+
+/// Returns the interrupt raised for events on `pin`.
+fn bank_interrupt(pin: u8) -> Interrupt {
+    match pin {
+        0...27 => Interrupt::Gpio0,
+        28...45 => Interrupt::Gpio1,
+        _ => Interrupt::Gpio2,
+    }
+}
+
+/// Registers `handler` for the events armed on `pin`, replacing any handler
+/// registered before, and registers the interrupt of the pin's bank. The
+/// events themselves are armed with `Gpio::enable_event()`.
+///
+/// # Panics
+///
+/// Panics if `pin` is not a GPIO pin.
+pub fn register(pin: u8, handler: Handler) {
+    assert!((pin as usize) < PIN_COUNT, "gpio: no pin {}", pin);
+    HANDLERS.lock()[pin as usize] = Some(handler);
+    IRQ.register(bank_interrupt(pin), handle_irq);
+}
+
+/// Removes the handler for `pin`. Events detected on the pin are cleared but
+/// otherwise ignored.
+pub fn unregister(pin: u8) {
+    if (pin as usize) < PIN_COUNT {
+        HANDLERS.lock()[pin as usize] = None;
+    }
+}
+
+/// Returns the key that processes waiting for events on `pin` block on.
+pub fn key(pin: u8) -> u64 {
+    &HANDLERS as *const _ as u64 + (pin as usize * size_of::<Option<Handler>>()) as u64
+}
+
+/// A handler that counts the event in `events()` and wakes every process
+/// blocked on the pin's `key()`.
+pub fn wake_waiters(pin: u8) {
+    {
+        let mut events = EVENTS.lock();
+        events[pin as usize] = events[pin as usize].wrapping_add(1);
+    }
+
+    SCHEDULER.wake(key(pin), usize::max_value());
+}
+
+/// Returns the number of events `wake_waiters()` was called for on `pin`.
+/// Processes compare it before and after blocking on `key()` so that an event
+/// in between is not missed.
+pub fn events(pin: u8) -> u64 {
+    EVENTS.lock()[pin as usize]
+}
+
+/// Arms `pin` for processes to wait for its edges and returns `events()` of
+/// the pin. The first time, the pin is taken from `GPIO_BANK`, made an input
+/// with both edges armed, and `wake_waiters()` is registered as its handler.
+/// The pin stays armed and taken from then on.
+///
+/// Returns `None` if `pin` is not a GPIO pin or a driver took it.
+pub fn arm_wait(pin: u8) -> Option<u64> {
+    if pin as usize >= PIN_COUNT {
+        return None;
+    }
+
+    let mut armed = WAIT_PINS.lock();
+    if *armed & (1 << pin) == 0 {
+        let mut gpio = GPIO_BANK.lock().take(pin)?.into_input();
+        register(pin, wake_waiters);
+        gpio.clear_event();
+        gpio.enable_event(Event::RisingEdge);
+        gpio.enable_event(Event::FallingEdge);
+        *armed |= 1 << pin;
+    }
+
+    Some(events(pin))
+}
+
+/// Services a GPIO interrupt: clears the detected events and calls the
+/// handlers of the pins they were detected on, in pin order.
+fn handle_irq(_: &mut TrapFrame) {
+    let pending = detected_events();
+    clear_events(pending);
+
+    for pin in 0..PIN_COUNT as u8 {
+        if pending & (1 << pin) == 0 {
+            continue;
+        }
+
+        // Handlers are called without the lock so that they may register
+        // handlers themselves.
+        let handler = HANDLERS.lock()[pin as usize];
+        if let Some(handler) = handler {
+            handler(pin);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_distinct_per_pin() {
+        let keys: Vec<u64> = (0..PIN_COUNT as u8).map(key).collect();
+        for (pin, key) in keys.iter().enumerate() {
+            assert!(!keys[pin + 1..].contains(key), "pin {} shares its key", pin);
+        }
+    }
+}
@@ -26,12 +26,16 @@ extern crate stack_vec;
 pub mod console;
 
 pub mod aarch64;
+pub mod alarm;
 pub mod allocator;
 pub mod fs;
+pub mod gpio;
 pub mod lang_items;
 pub mod mutex;
 pub mod process;
 pub mod shell;
+pub mod smp;
+pub mod sync;
 pub mod syscall;
 pub mod traps;
 pub mod vm;
@@ -47,27 +51,46 @@ use fs::sd::Sd;
 use fs::FileSystem;
 use pi::timer;
 use process::GlobalScheduler;
+use traps::Irq;
+use vm::VMManager;
 
 #[cfg(not(test))]
 #[global_allocator]
 pub static ALLOCATOR: Allocator = Allocator::uninitialized();
 
+/// Host tests allocate stacks and pages from the host's heap.
+#[cfg(test)]
+pub static ALLOCATOR: allocator::HostAllocator = allocator::HostAllocator;
+
+pub static VMM: VMManager = VMManager::uninitialized();
+
 pub static FILE_SYSTEM: FileSystem = FileSystem::uninitialized();
 
 pub static SCHEDULER: GlobalScheduler = GlobalScheduler::uninitialized();
 
-// TODO: enable data cache with sctlr
-//
-//       must invalidate cache before enabling
-//
-//       Before this is done, it looks like the atomic accesses in this mutex
-//       implementation need to be changed to use Aquire Release semantics.
-//
+pub static IRQ: Irq = Irq::new();
+
 #[no_mangle]
 #[cfg(not(test))]
 pub extern "C" fn kmain() {
     timer::spin_sleep_ms(1000);
     ALLOCATOR.initialize();
+    VMM.initialize();
     FILE_SYSTEM.initialize();
+    SCHEDULER.initialize();
+    CONSOLE.lock().enable_interrupts();
+    smp::start_secondary_cores();
+    SCHEDULER.start();
+}
+
+/// The entry point of cores 1 through 3 once `smp::start_secondary_cores()`
+/// releases them. Each core runs on its own EL1 stack with the exception
+/// vectors already installed by `init.S`, and takes no lock before its MMU
+/// and caches are on.
+#[no_mangle]
+#[cfg(not(test))]
+pub extern "C" fn kmain_secondary() {
+    VMM.setup();
+    smp::set_ready();
     SCHEDULER.start();
 }
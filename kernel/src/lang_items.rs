@@ -1,4 +1,6 @@
-use console::_print;
+use std::io::Write;
+
+use console::{_print, CONSOLE};
 
 #[no_mangle]
 #[cfg(not(test))]
@@ -19,6 +21,10 @@ pub extern "C" fn panic_fmt(
         col
     );
 
+    // Output is buffered once the console is interrupt driven, and nothing
+    // drains the buffer after a panic.
+    let _ = CONSOLE.lock().flush();
+
     loop {
         unsafe { asm!("wfe") }
     }
@@ -1,9 +1,59 @@
 use std::sync::atomic::{AtomicBool, AtomicUsize};
-use std::sync::atomic::Ordering::Relaxed;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 use std::cell::UnsafeCell;
 use std::ops::{DerefMut, Deref, Drop};
 use std::fmt;
 
+/// The `owner` of an unlocked `Mutex`.
+const NO_OWNER: usize = usize::max_value();
+
+/// The processor state locks depend on.
+#[cfg(not(test))]
+mod hw {
+    use aarch64;
+
+    pub use aarch64::{mask_interrupts, nop, restore_interrupts};
+
+    /// Returns `true` if the lock word can be taken with exclusive accesses.
+    /// They only work on cacheable memory: the BCM2837 has no global
+    /// exclusive monitor.
+    pub fn exclusives_work() -> bool {
+        aarch64::sctlr() & aarch64::SCTLR_C != 0
+    }
+
+    /// Returns the calling core.
+    pub fn core() -> usize {
+        unsafe { aarch64::affinity() }
+    }
+}
+
+/// Host tests run on "core 0", with the data cache on and no interrupts to
+/// mask.
+#[cfg(test)]
+mod hw {
+    pub fn exclusives_work() -> bool {
+        true
+    }
+
+    pub fn core() -> usize {
+        0
+    }
+
+    pub fn mask_interrupts() -> u64 {
+        0
+    }
+
+    pub fn restore_interrupts(_daif: u64) {}
+
+    pub fn nop() {}
+}
+
+/// A spinning mutual exclusion lock.
+///
+/// A `Mutex` is not reentrant: a core locking a `Mutex` it already holds
+/// spins forever. Interrupts are not masked while it is held, so a `Mutex`
+/// must never be locked from an exception handler; use an `IrqSafeMutex` for
+/// data shared with exception handlers.
 #[repr(align(32))]
 pub struct Mutex<T> {
     data: UnsafeCell<T>,
@@ -25,41 +75,79 @@ impl<T> Mutex<T> {
     pub const fn new(val: T) -> Mutex<T> {
         Mutex {
             lock: AtomicBool::new(false),
-            owner: AtomicUsize::new(usize::max_value()),
+            owner: AtomicUsize::new(NO_OWNER),
             data: UnsafeCell::new(val)
         }
     }
 }
 
 impl<T> Mutex<T> {
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Attempts to acquire the lock without spinning. Returns `None` if the
+    /// lock is held.
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        let this = 0;
-        if !self.lock.load(Relaxed) || self.owner.load(Relaxed) == this {
-            self.lock.store(true, Relaxed);
-            self.owner.store(this, Relaxed);
+        if self.acquire() {
             Some(MutexGuard { lock: &self })
         } else {
             None
         }
     }
 
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Acquires the lock, spinning until it is available.
     #[inline(never)]
     pub fn lock(&self) -> MutexGuard<T> {
-        // Wait until we can "aquire" the lock, then "acquire" it.
         loop {
             match self.try_lock() {
                 Some(guard) => return guard,
-                None => continue
+                None => self.wait()
             }
         }
     }
 
+    /// Returns the core holding the lock, if any.
+    pub fn owner(&self) -> Option<usize> {
+        match self.owner.load(Relaxed) {
+            NO_OWNER => None,
+            owner => Some(owner)
+        }
+    }
+
+    /// Attempts to take the lock for the calling core. Returns `true` on
+    /// success.
+    fn acquire(&self) -> bool {
+        let acquired = if hw::exclusives_work() {
+            // Not `_weak`: `try_lock()` must not fail on a free lock.
+            self.lock.compare_exchange(false, true, Acquire, Relaxed).is_ok()
+        } else {
+            // Only core 0 runs with its data cache off while locking: the
+            // other cores are released after core 0 turns its cache on and
+            // take no lock before turning on their own (see
+            // `smp::start_secondary_cores()`). With a single core running, a
+            // plain check-then-set excludes everyone else.
+            if self.lock.load(Acquire) {
+                false
+            } else {
+                self.lock.store(true, Relaxed);
+                true
+            }
+        };
+
+        if acquired {
+            self.owner.store(hw::core(), Relaxed);
+        }
+
+        acquired
+    }
+
+    /// Spins until the lock looks free, without writing to it.
+    fn wait(&self) {
+        while self.lock.load(Relaxed) {
+            hw::nop();
+        }
+    }
+
     fn unlock(&self) {
-        self.lock.store(false, Relaxed);
+        self.owner.store(NO_OWNER, Relaxed);
+        self.lock.store(false, Release);
     }
 }
 
@@ -91,3 +179,85 @@ impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
         }
     }
 }
+
+/// A spinning mutual exclusion lock that masks IRQs and FIQs on the holding
+/// core while it is held.
+///
+/// Data that exception handlers lock, such as the scheduler, the console and
+/// the allocator, must be behind an `IrqSafeMutex`: with a plain `Mutex`, an
+/// interrupt arriving while the interrupted code holds the lock makes the
+/// handler spin forever. Interrupts stay unmasked while waiting for the lock.
+pub struct IrqSafeMutex<T>(Mutex<T>);
+
+pub struct IrqSafeMutexGuard<'a, T: 'a> {
+    lock: &'a Mutex<T>,
+    daif: u64
+}
+
+impl<'a, T> !Send for IrqSafeMutexGuard<'a, T> { }
+unsafe impl<'a, T: Sync> Sync for IrqSafeMutexGuard<'a, T> { }
+
+impl<T> IrqSafeMutex<T> {
+    pub const fn new(val: T) -> IrqSafeMutex<T> {
+        IrqSafeMutex(Mutex::new(val))
+    }
+
+    /// Attempts to acquire the lock without spinning, masking interrupts on
+    /// success. Returns `None` if the lock is held.
+    pub fn try_lock(&self) -> Option<IrqSafeMutexGuard<T>> {
+        let daif = hw::mask_interrupts();
+        if self.0.acquire() {
+            Some(IrqSafeMutexGuard { lock: &self.0, daif })
+        } else {
+            hw::restore_interrupts(daif);
+            None
+        }
+    }
+
+    /// Masks interrupts and acquires the lock, spinning until it is available.
+    #[inline(never)]
+    pub fn lock(&self) -> IrqSafeMutexGuard<T> {
+        loop {
+            match self.try_lock() {
+                Some(guard) => return guard,
+                None => self.0.wait()
+            }
+        }
+    }
+
+    /// Returns the core holding the lock, if any.
+    pub fn owner(&self) -> Option<usize> {
+        self.0.owner()
+    }
+}
+
+impl<'a, T: 'a> Deref for IrqSafeMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { & *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for IrqSafeMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for IrqSafeMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        // Release the lock before an interrupt can arrive on this core.
+        self.lock.unlock();
+        hw::restore_interrupts(self.daif);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for IrqSafeMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("IrqSafeMutex").field("data", &&*guard).finish(),
+            None => f.debug_struct("IrqSafeMutex").field("data", &"<locked>").finish()
+        }
+    }
+}
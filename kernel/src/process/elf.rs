@@ -0,0 +1,382 @@
+use std::io;
+use std::ops::Range;
+use std::ptr;
+
+use aarch64::cache;
+use process::Stack;
+use vm::{PagePerm, UserPageTable, VirtualAddr, PAGE_SIZE, USER_IMG_BASE, USER_STACK_TOP};
+
+/// The ELF magic number: `\x7fELF`.
+const MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+
+const CLASS_64: u8 = 2;
+const DATA_LSB: u8 = 1;
+const VERSION_CURRENT: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_AARCH64: u16 = 183;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Program header type of a loadable segment.
+const PT_LOAD: u32 = 1;
+
+/// Segment permission flags.
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+/// Error type for ELF loading failures.
+#[derive(Debug)]
+pub enum Error {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file does not start with the ELF magic number.
+    BadMagic,
+    /// The file is a valid ELF file that cannot be run on this machine.
+    Unsupported(&'static str),
+    /// The file's headers are inconsistent.
+    Malformed(&'static str),
+    /// The arguments do not fit on the process's stack.
+    ArgumentsTooLarge,
+    /// Memory for the process could not be allocated.
+    OutOfMemory,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    (buf[offset] as u16) | ((buf[offset + 1] as u16) << 8)
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    (read_u16(buf, offset) as u32) | ((read_u16(buf, offset + 2) as u32) << 16)
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    (read_u32(buf, offset) as u64) | ((read_u32(buf, offset + 4) as u64) << 32)
+}
+
+/// The fields of an ELF64 file header needed to load an executable.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Header {
+    pub entry: u64,
+    pub phoff: u64,
+    pub phentsize: u16,
+    pub phnum: u16,
+}
+
+impl Header {
+    /// Parses and validates the ELF64 header at the start of `image`.
+    pub fn parse(image: &[u8]) -> Result<Header, Error> {
+        if image.len() < HEADER_SIZE || image[..4] != MAGIC {
+            return Err(Error::BadMagic);
+        } else if image[4] != CLASS_64 {
+            return Err(Error::Unsupported("not a 64-bit ELF file"));
+        } else if image[5] != DATA_LSB {
+            return Err(Error::Unsupported("not a little-endian ELF file"));
+        } else if image[6] != VERSION_CURRENT {
+            return Err(Error::Unsupported("unknown ELF version"));
+        } else if read_u16(image, 16) != TYPE_EXEC {
+            return Err(Error::Unsupported("not an executable"));
+        } else if read_u16(image, 18) != MACHINE_AARCH64 {
+            return Err(Error::Unsupported("not an aarch64 executable"));
+        }
+
+        let header = Header {
+            entry: read_u64(image, 24),
+            phoff: read_u64(image, 32),
+            phentsize: read_u16(image, 54),
+            phnum: read_u16(image, 56),
+        };
+
+        if (header.phentsize as usize) < PROGRAM_HEADER_SIZE {
+            return Err(Error::Malformed("program header entry too small"));
+        }
+
+        let table_size = header.phentsize as u64 * header.phnum as u64;
+        match header.phoff.checked_add(table_size) {
+            Some(end) if end <= image.len() as u64 => Ok(header),
+            _ => Err(Error::Malformed("program headers out of bounds")),
+        }
+    }
+
+    /// Returns the program headers in `image`.
+    pub fn program_headers(&self, image: &[u8]) -> Vec<ProgramHeader> {
+        let (phoff, phentsize) = (self.phoff as usize, self.phentsize as usize);
+        (0..self.phnum as usize)
+            .map(|i| ProgramHeader::parse(&image[phoff + i * phentsize..]))
+            .collect()
+    }
+}
+
+/// The fields of an ELF64 program header needed to load a segment.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ProgramHeader {
+    pub kind: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+}
+
+impl ProgramHeader {
+    fn parse(buf: &[u8]) -> ProgramHeader {
+        ProgramHeader {
+            kind: read_u32(buf, 0),
+            flags: read_u32(buf, 4),
+            offset: read_u64(buf, 8),
+            vaddr: read_u64(buf, 16),
+            filesz: read_u64(buf, 32),
+            memsz: read_u64(buf, 40),
+        }
+    }
+
+    /// Returns the page permissions requested by this segment's flags.
+    pub fn perm(&self) -> PagePerm {
+        PagePerm::new(self.flags & PF_W != 0, self.flags & PF_X != 0)
+    }
+
+    /// Returns the numbers of the pages this segment occupies in memory.
+    fn pages(&self) -> Range<usize> {
+        let start = self.vaddr as usize / PAGE_SIZE;
+        let end = (self.vaddr + self.memsz) as usize;
+        start..(end + PAGE_SIZE - 1) / PAGE_SIZE
+    }
+
+    /// Checks that this segment lies within `image` and within the part of
+    /// the user region below the stack.
+    fn validate(&self, image: &[u8]) -> Result<(), Error> {
+        let file_end = self.offset.checked_add(self.filesz);
+        if file_end.map_or(true, |end| end > image.len() as u64) {
+            return Err(Error::Malformed("segment data out of bounds"));
+        } else if self.filesz > self.memsz {
+            return Err(Error::Malformed("segment file size exceeds memory size"));
+        }
+
+        let mem_end = self.vaddr.checked_add(self.memsz);
+        let stack_base = (USER_STACK_TOP - Stack::SIZE) as u64;
+        if self.vaddr < USER_IMG_BASE as u64 || mem_end.map_or(true, |end| end > stack_base) {
+            return Err(Error::Malformed("segment outside of the user region"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps every `PT_LOAD` segment of the executable `image` into `vmap` and
+/// returns the entry point.
+///
+/// Each segment's pages are allocated with the permissions of its flags, its
+/// file data is copied in, and the remainder up to its memory size is zeroed.
+/// A page shared by two segments, such as the last page of the text and the
+/// first of the data, gets the permissions of both.
+pub fn load(vmap: &mut UserPageTable, image: &[u8]) -> Result<u64, Error> {
+    let header = Header::parse(image)?;
+    let segments: Vec<ProgramHeader> = header
+        .program_headers(image)
+        .into_iter()
+        .filter(|ph| ph.kind == PT_LOAD)
+        .collect();
+
+    // The pages to map and their permissions, sorted by page number.
+    let mut pages: Vec<(usize, PagePerm)> = Vec::new();
+    for ph in segments.iter() {
+        ph.validate(image)?;
+        for page in ph.pages() {
+            match pages.binary_search_by_key(&page, |&(page, _)| page) {
+                Ok(index) => pages[index].1 = pages[index].1.union(ph.perm()),
+                Err(index) => pages.insert(index, (page, ph.perm())),
+            }
+        }
+    }
+
+    for &(page, perm) in pages.iter() {
+        let va = VirtualAddr::from(page * PAGE_SIZE);
+        vmap.alloc(va, perm).ok_or(Error::OutOfMemory)?;
+    }
+
+    let mut executable = false;
+    for ph in segments.iter() {
+        let data = &image[ph.offset as usize..(ph.offset + ph.filesz) as usize];
+        copy_to_user(vmap, ph.vaddr as usize, data);
+
+        let entry = header.entry;
+        if ph.flags & PF_X != 0 && entry >= ph.vaddr && entry < ph.vaddr + ph.memsz {
+            executable = true;
+        }
+    }
+
+    if !executable {
+        return Err(Error::Malformed("entry point is not in an executable segment"));
+    }
+
+    Ok(header.entry)
+}
+
+/// Copies `data` into the mapped user memory of `vmap` starting at `va` and
+/// makes it visible to instruction fetches.
+///
+/// # Panics
+///
+/// Panics if any page in the destination range is unmapped.
+fn copy_to_user(vmap: &UserPageTable, va: usize, data: &[u8]) {
+    let mut copied = 0;
+    while copied < data.len() {
+        let addr = va + copied;
+        let len = ::std::cmp::min(PAGE_SIZE - addr % PAGE_SIZE, data.len() - copied);
+        let pa = vmap.translate(addr.into()).expect("mapped user page");
+        unsafe {
+            let dst = pa.as_usize() as *mut u8;
+            ptr::copy_nonoverlapping(data[copied..].as_ptr(), dst, len);
+            cache::sync_icache(dst as usize, len);
+        }
+        copied += len;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::KernelPageTable;
+
+    fn header(phnum: u16) -> Vec<u8> {
+        let mut image = vec![0u8; HEADER_SIZE + PROGRAM_HEADER_SIZE * phnum as usize];
+        image[..4].copy_from_slice(&MAGIC);
+        image[4] = CLASS_64;
+        image[5] = DATA_LSB;
+        image[6] = VERSION_CURRENT;
+        image[16] = TYPE_EXEC as u8;
+        image[18] = MACHINE_AARCH64 as u8;
+        image[24..32].copy_from_slice(&[0x00, 0x10, 0x00, 0x80, 0, 0, 0, 0]);
+        image[32] = HEADER_SIZE as u8;
+        image[54] = PROGRAM_HEADER_SIZE as u8;
+        image[56] = phnum as u8;
+        image
+    }
+
+    fn write_u64(buf: &mut [u8], offset: usize, value: u64) {
+        for i in 0..8 {
+            buf[offset + i] = (value >> (8 * i)) as u8;
+        }
+    }
+
+    /// Fills in the `index`th program header of `image` as a `PT_LOAD`
+    /// segment.
+    fn segment(
+        image: &mut [u8],
+        index: usize,
+        flags: u32,
+        offset: u64,
+        vaddr: u64,
+        filesz: u64,
+        memsz: u64,
+    ) {
+        let ph = HEADER_SIZE + index * PROGRAM_HEADER_SIZE;
+        image[ph] = PT_LOAD as u8;
+        image[ph + 4] = flags as u8;
+        write_u64(image, ph + 8, offset);
+        write_u64(image, ph + 16, vaddr);
+        write_u64(image, ph + 32, filesz);
+        write_u64(image, ph + 40, memsz);
+    }
+
+    /// Returns the byte mapped at `va` in `vmap`.
+    fn user_byte(vmap: &UserPageTable, va: usize) -> u8 {
+        let pa = vmap.translate(va.into()).expect("mapped");
+        unsafe { *(pa.as_usize() as *const u8) }
+    }
+
+    #[test]
+    fn parse_header() {
+        let image = header(1);
+        let header = Header::parse(&image).expect("valid header");
+        assert_eq!(header.entry, 0x8000_1000);
+        assert_eq!(header.phoff, HEADER_SIZE as u64);
+        assert_eq!(header.phnum, 1);
+
+        let ph = header.program_headers(&image)[0];
+        assert_eq!(ph.kind, 0);
+        assert_eq!(ph.perm(), PagePerm::RO);
+    }
+
+    #[test]
+    fn reject_bad_headers() {
+        let mut image = header(1);
+        image[0] = 0;
+        match Header::parse(&image) {
+            Err(Error::BadMagic) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut image = header(1);
+        image[18] = 62;
+        match Header::parse(&image) {
+            Err(Error::Unsupported(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut image = header(2);
+        image.truncate(HEADER_SIZE + PROGRAM_HEADER_SIZE);
+        match Header::parse(&image) {
+            Err(Error::Malformed(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_merges_shared_page() {
+        // A text segment ending in the page where a data segment, followed
+        // by its bss, begins.
+        let mut image = header(2);
+        image.resize(0x1310, 0);
+        for i in 0x200..0x1300 {
+            image[i] = 0xc0;
+        }
+        for i in 0x1300..0x1310 {
+            image[i] = 0xda;
+        }
+        segment(&mut image, 0, PF_X, 0x200, 0x8000_0000, 0x1100, 0x1100);
+        segment(&mut image, 1, PF_W, 0x1300, 0x8000_1100, 0x10, 0x1000);
+
+        let mut vmap = UserPageTable::new(&KernelPageTable::new());
+        assert_eq!(load(&mut vmap, &image).expect("loaded"), 0x8000_1000);
+
+        assert!(!vmap.is_writable(0x8000_0000.into()));
+        assert!(vmap.is_writable(0x8000_1000.into()));
+        assert!(vmap.is_writable(0x8000_2000.into()));
+        assert!(vmap.translate(0x8000_3000.into()).is_none());
+
+        assert_eq!(user_byte(&vmap, 0x8000_10ff), 0xc0);
+        assert_eq!(user_byte(&vmap, 0x8000_1100), 0xda);
+        assert_eq!(user_byte(&vmap, 0x8000_110f), 0xda);
+        assert_eq!(user_byte(&vmap, 0x8000_1110), 0);
+        assert_eq!(user_byte(&vmap, 0x8000_20ff), 0);
+    }
+
+    #[test]
+    fn load_rejects_bad_segments() {
+        let mut vmap = UserPageTable::new(&KernelPageTable::new());
+
+        // The entry point is in a segment that is not executable.
+        let mut image = header(1);
+        image.resize(0x300, 0);
+        segment(&mut image, 0, PF_W, 0x200, 0x8000_1000, 0x100, 0x100);
+        match load(&mut vmap, &image) {
+            Err(Error::Malformed(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // The segment's data is past the end of the file.
+        let mut image = header(1);
+        segment(&mut image, 0, PF_X, 0x200, 0x8000_1000, 0x100, 0x100);
+        match load(&mut vmap, &image) {
+            Err(Error::Malformed(_)) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::sync::Arc;
+
+use fat32::vfat::{Entry, File};
+use mutex::Mutex;
+use process::{PipeReader, PipeWriter};
+use syscall::OsError;
+
+/// The maximum number of files a process can have open at once.
+pub const MAX_FILES: usize = 64;
+
+/// An open file.
+pub enum Descriptor {
+    /// The console.
+    Console,
+    /// A regular file.
+    File(File),
+    /// A directory: its entries, read when it was opened, and the index of
+    /// the next entry to return.
+    Dir { entries: Vec<Entry>, next: usize },
+    /// The read end of a pipe.
+    PipeRead(PipeReader),
+    /// The write end of a pipe.
+    PipeWrite(PipeWriter),
+}
+
+impl fmt::Debug for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Descriptor::Console => write!(f, "Descriptor::Console"),
+            Descriptor::File(_) => write!(f, "Descriptor::File"),
+            Descriptor::Dir { ref entries, next } => {
+                write!(f, "Descriptor::Dir({}/{})", next, entries.len())
+            }
+            Descriptor::PipeRead(ref reader) => write!(f, "Descriptor::PipeRead({:?})", reader),
+            Descriptor::PipeWrite(ref writer) => write!(f, "Descriptor::PipeWrite({:?})", writer),
+        }
+    }
+}
+
+/// A shared handle to an open file. Descriptors are locked individually so
+/// that I/O on one does not hold up the scheduler or other descriptors.
+pub type SharedDescriptor = Arc<Mutex<Descriptor>>;
+
+/// A process's table of open files, indexed by file descriptor.
+#[derive(Debug)]
+pub struct FdTable {
+    files: Vec<Option<SharedDescriptor>>,
+}
+
+impl FdTable {
+    /// Returns a table with the console open as descriptors 0, 1 and 2.
+    pub fn new() -> FdTable {
+        let console = Arc::new(Mutex::new(Descriptor::Console));
+        FdTable {
+            files: vec![Some(console.clone()), Some(console.clone()), Some(console)],
+        }
+    }
+
+    /// Adds `descriptor` at the lowest free file descriptor and returns that
+    /// file descriptor.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::TooManyFiles` if `MAX_FILES` files are open.
+    pub fn insert(&mut self, descriptor: Descriptor) -> Result<u64, OsError> {
+        let descriptor = Some(Arc::new(Mutex::new(descriptor)));
+        match self.files.iter().position(|file| file.is_none()) {
+            Some(fd) => {
+                self.files[fd] = descriptor;
+                Ok(fd as u64)
+            }
+            None if self.files.len() < MAX_FILES => {
+                self.files.push(descriptor);
+                Ok(self.files.len() as u64 - 1)
+            }
+            None => Err(OsError::TooManyFiles),
+        }
+    }
+
+    /// Returns the number of descriptors that can still be opened.
+    pub fn available(&self) -> usize {
+        let closed = self.files.iter().filter(|file| file.is_none()).count();
+        closed + MAX_FILES - self.files.len()
+    }
+
+    /// Opens `descriptor` as `fd`, closing whatever `fd` was open as.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadFileDescriptor` if `fd` is not below `MAX_FILES`.
+    pub fn set(&mut self, fd: u64, descriptor: Descriptor) -> Result<(), OsError> {
+        if fd >= MAX_FILES as u64 {
+            return Err(OsError::BadFileDescriptor);
+        }
+
+        while self.files.len() <= fd as usize {
+            self.files.push(None);
+        }
+
+        self.files[fd as usize] = Some(Arc::new(Mutex::new(descriptor)));
+        Ok(())
+    }
+
+    /// Returns the descriptor open as `fd`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadFileDescriptor` if `fd` is not open.
+    pub fn get(&self, fd: u64) -> Result<SharedDescriptor, OsError> {
+        match self.files.get(fd as usize) {
+            Some(&Some(ref descriptor)) => Ok(descriptor.clone()),
+            _ => Err(OsError::BadFileDescriptor),
+        }
+    }
+
+    /// Closes every open descriptor and returns them.
+    pub fn clear(&mut self) -> Vec<SharedDescriptor> {
+        self.files.drain(..).filter_map(|file| file).collect()
+    }
+
+    /// Closes `fd` and returns its descriptor.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BadFileDescriptor` if `fd` is not open.
+    pub fn remove(&mut self, fd: u64) -> Result<SharedDescriptor, OsError> {
+        match self.files.get_mut(fd as usize) {
+            Some(file) => file.take().ok_or(OsError::BadFileDescriptor),
+            None => Err(OsError::BadFileDescriptor),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn console_preopened() {
+        let table = FdTable::new();
+        for fd in 0..3 {
+            match *table.get(fd).unwrap().lock() {
+                Descriptor::Console => {}
+                ref other => panic!("fd {}: {:?}", fd, other),
+            }
+        }
+
+        assert_eq!(table.get(3).unwrap_err(), OsError::BadFileDescriptor);
+    }
+
+    #[test]
+    fn lowest_free_descriptor() {
+        let mut table = FdTable::new();
+        assert_eq!(table.insert(Descriptor::Console), Ok(3));
+        assert_eq!(table.insert(Descriptor::Console), Ok(4));
+        assert!(table.remove(1).is_ok());
+        assert_eq!(table.remove(1).unwrap_err(), OsError::BadFileDescriptor);
+        assert_eq!(table.insert(Descriptor::Console), Ok(1));
+        assert_eq!(table.insert(Descriptor::Console), Ok(5));
+
+        for _ in 6..MAX_FILES {
+            table.insert(Descriptor::Console).unwrap();
+        }
+        assert_eq!(table.insert(Descriptor::Console), Err(OsError::TooManyFiles));
+    }
+
+    #[test]
+    fn set_descriptor() {
+        let mut table = FdTable::new();
+        table.remove(1).unwrap();
+        assert_eq!(table.set(1, Descriptor::Console), Ok(()));
+        assert_eq!(table.set(5, Descriptor::Console), Ok(()));
+        assert!(table.get(4).is_err());
+        assert_eq!(table.insert(Descriptor::Console), Ok(3));
+        assert_eq!(table.insert(Descriptor::Console), Ok(4));
+        assert_eq!(table.insert(Descriptor::Console), Ok(6));
+        assert_eq!(table.set(MAX_FILES as u64, Descriptor::Console), Err(OsError::BadFileDescriptor));
+        assert_eq!(table.available(), MAX_FILES - 7);
+    }
+}
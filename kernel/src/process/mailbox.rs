@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+
+use process::Id;
+use syscall::OsError;
+
+/// The number of messages a mailbox holds.
+pub const MAILBOX_CAPACITY: usize = 16;
+
+/// The largest message in bytes.
+pub const MESSAGE_MAX: usize = 256;
+
+/// A message sent to a process.
+#[derive(Debug)]
+pub struct Message {
+    /// The ID of the sending process.
+    pub from: Id,
+    pub data: Vec<u8>,
+}
+
+/// A process's queue of received messages.
+///
+/// A process that receives from an empty mailbox is blocked on the mailbox's
+/// `key()` until a message is sent to it. Processes keep their mailbox boxed
+/// so that the key stays the same while the process moves between queues.
+#[derive(Debug, Default)]
+pub struct Mailbox {
+    messages: VecDeque<Message>,
+}
+
+impl Mailbox {
+    /// Returns an empty mailbox.
+    pub fn new() -> Mailbox {
+        Mailbox::default()
+    }
+
+    /// Returns the key processes blocked on this mailbox wait on.
+    pub fn key(&self) -> u64 {
+        self as *const Mailbox as u64
+    }
+
+    /// Adds `message` to the back of the mailbox.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::InvalidArgument` if the message is longer than
+    /// `MESSAGE_MAX` and `OsError::WouldBlock` if the mailbox is full.
+    pub fn push(&mut self, message: Message) -> Result<(), OsError> {
+        if message.data.len() > MESSAGE_MAX {
+            return Err(OsError::InvalidArgument);
+        }
+
+        if self.messages.len() == MAILBOX_CAPACITY {
+            return Err(OsError::WouldBlock);
+        }
+
+        self.messages.push_back(message);
+        Ok(())
+    }
+
+    /// Removes and returns the oldest message.
+    pub fn pop(&mut self) -> Option<Message> {
+        self.messages.pop_front()
+    }
+
+    /// Returns `true` if the mailbox holds no messages.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(from: Id, len: usize) -> Message {
+        Message { from, data: vec![0; len] }
+    }
+
+    #[test]
+    fn bounded_fifo() {
+        let mut mailbox = Mailbox::new();
+        assert!(mailbox.is_empty());
+        assert_eq!(mailbox.push(message(1, MESSAGE_MAX + 1)), Err(OsError::InvalidArgument));
+
+        for from in 0..MAILBOX_CAPACITY as Id {
+            mailbox.push(message(from, MESSAGE_MAX)).unwrap();
+        }
+        assert_eq!(mailbox.push(message(99, 1)), Err(OsError::WouldBlock));
+
+        assert_eq!(mailbox.pop().map(|message| message.from), Some(0));
+        assert_eq!(mailbox.pop().map(|message| message.from), Some(1));
+    }
+}
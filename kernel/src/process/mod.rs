@@ -1,9 +1,23 @@
+mod elf;
+mod fd;
+mod mailbox;
+mod pid;
+mod pipe;
+mod policy;
 mod process;
 mod state;
 mod scheduler;
+mod sleep;
 mod stack;
 
+pub use self::elf::Error as LoadError;
+pub use self::fd::{Descriptor, FdTable, SharedDescriptor, MAX_FILES};
+pub use self::mailbox::{Mailbox, Message, MAILBOX_CAPACITY, MESSAGE_MAX};
+pub use self::pid::PID_MAX;
+pub use self::pipe::{pipe, Pipe, PipeReader, PipeWriter, PIPE_SIZE};
+pub use self::policy::{named_policy, Mlfq, RoundRobin, SchedulingPolicy, StaticPriority};
+pub use self::policy::{MLFQ_BOOST_PERIOD, MLFQ_LEVELS, NICE_MAX, NICE_MIN};
 pub use self::process::{Process, Id};
-pub use self::state::State;
-pub use self::scheduler::{GlobalScheduler, TICK};
+pub use self::state::{EventPollFn, State};
+pub use self::scheduler::{GlobalScheduler, DEFAULT_QUANTUM, MIN_QUANTUM};
 pub use self::stack::Stack;
@@ -0,0 +1,84 @@
+use process::Id;
+
+/// The largest process ID that is handed out.
+pub const PID_MAX: Id = 32767;
+
+/// Allocates process IDs. IDs start at `1`; an ID that is released is handed
+/// out again, lowest first, before any new ID.
+#[derive(Debug)]
+pub struct PidAllocator {
+    /// The lowest ID that has never been handed out.
+    next: Id,
+    /// Released IDs, sorted from highest to lowest.
+    free: Vec<Id>,
+}
+
+impl PidAllocator {
+    /// Returns an allocator that has not handed out any IDs.
+    pub fn new() -> PidAllocator {
+        PidAllocator { next: 1, free: Vec::new() }
+    }
+
+    /// Returns an unused ID, or `None` if all IDs up to `PID_MAX` are in use.
+    pub fn alloc(&mut self) -> Option<Id> {
+        if let Some(id) = self.free.pop() {
+            return Some(id);
+        }
+
+        if self.next > PID_MAX {
+            return None;
+        }
+
+        self.next += 1;
+        Some(self.next - 1)
+    }
+
+    /// Marks `id`, which must have been returned by `alloc()`, as unused.
+    pub fn release(&mut self, id: Id) {
+        debug_assert!(id > 0 && id < self.next, "releasing unallocated pid {}", id);
+        match self.free.binary_search_by(|free| id.cmp(free)) {
+            Ok(_) => panic!("pid {} released twice", id),
+            Err(index) => self.free.insert(index, id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequential() {
+        let mut pids = PidAllocator::new();
+        assert_eq!(pids.alloc(), Some(1));
+        assert_eq!(pids.alloc(), Some(2));
+        assert_eq!(pids.alloc(), Some(3));
+    }
+
+    #[test]
+    fn reuse_lowest_first() {
+        let mut pids = PidAllocator::new();
+        for _ in 0..5 {
+            pids.alloc().unwrap();
+        }
+
+        pids.release(4);
+        pids.release(2);
+        assert_eq!(pids.alloc(), Some(2));
+        assert_eq!(pids.alloc(), Some(4));
+        assert_eq!(pids.alloc(), Some(6));
+    }
+
+    #[test]
+    fn exhaustion() {
+        let mut pids = PidAllocator::new();
+        for id in 1..PID_MAX + 1 {
+            assert_eq!(pids.alloc(), Some(id));
+        }
+        assert_eq!(pids.alloc(), None);
+
+        pids.release(7);
+        assert_eq!(pids.alloc(), Some(7));
+        assert_eq!(pids.alloc(), None);
+    }
+}
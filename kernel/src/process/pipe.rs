@@ -0,0 +1,234 @@
+use std::cmp::min;
+use std::fmt;
+use std::sync::Arc;
+
+use mutex::Mutex;
+use syscall::OsError;
+use SCHEDULER;
+
+/// The number of bytes a pipe buffers.
+pub const PIPE_SIZE: usize = 4096;
+
+/// The state of a pipe, protected by its lock.
+struct Inner {
+    /// A ring buffer of `len` bytes starting at `head`.
+    buf: [u8; PIPE_SIZE],
+    head: usize,
+    len: usize,
+    /// The number of open read and write ends.
+    readers: usize,
+    writers: usize,
+}
+
+/// A bounded byte channel between processes.
+///
+/// A process that reads from an empty pipe or writes to a full one is blocked
+/// on the pipe's `key()` until the other end makes progress or is closed.
+pub struct Pipe {
+    inner: Mutex<Inner>,
+}
+
+impl Pipe {
+    /// Returns an empty pipe with `readers` read ends and `writers` write
+    /// ends open.
+    fn new(readers: usize, writers: usize) -> Pipe {
+        Pipe {
+            inner: Mutex::new(Inner {
+                buf: [0; PIPE_SIZE],
+                head: 0,
+                len: 0,
+                readers,
+                writers,
+            }),
+        }
+    }
+
+    /// Returns the key processes blocked on this pipe wait on.
+    pub fn key(&self) -> u64 {
+        self as *const Pipe as u64
+    }
+
+    /// Reads buffered bytes into `buf`. Returns `Some(0)` at end of file, when
+    /// the pipe is empty and every write end is closed, and `None` if the
+    /// read would block.
+    fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        if inner.len == 0 {
+            return if inner.writers == 0 || buf.len() == 0 { Some(0) } else { None };
+        }
+
+        let n = min(buf.len(), inner.len);
+        for (i, byte) in buf[..n].iter_mut().enumerate() {
+            *byte = inner.buf[(inner.head + i) % PIPE_SIZE];
+        }
+
+        inner.head = (inner.head + n) % PIPE_SIZE;
+        inner.len -= n;
+        Some(n)
+    }
+
+    /// Buffers as much of `buf` as fits. Returns `Ok(None)` if the write would
+    /// block.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::BrokenPipe` if every read end is closed.
+    fn write(&self, buf: &[u8]) -> Result<Option<usize>, OsError> {
+        let mut inner = self.inner.lock();
+        if inner.readers == 0 {
+            return Err(OsError::BrokenPipe);
+        }
+
+        let n = min(buf.len(), PIPE_SIZE - inner.len);
+        if n == 0 && buf.len() > 0 {
+            return Ok(None);
+        }
+
+        for (i, &byte) in buf[..n].iter().enumerate() {
+            let index = (inner.head + inner.len + i) % PIPE_SIZE;
+            inner.buf[index] = byte;
+        }
+
+        inner.len += n;
+        Ok(Some(n))
+    }
+
+    /// Returns `true` if a read would not block.
+    pub fn readable(&self) -> bool {
+        let inner = self.inner.lock();
+        inner.len > 0 || inner.writers == 0
+    }
+
+    /// Returns `true` if a write would not block.
+    pub fn writable(&self) -> bool {
+        let inner = self.inner.lock();
+        inner.len < PIPE_SIZE || inner.readers == 0
+    }
+
+    fn close_reader(&self) {
+        self.inner.lock().readers -= 1;
+    }
+
+    fn close_writer(&self) {
+        self.inner.lock().writers -= 1;
+    }
+}
+
+impl fmt::Debug for Pipe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner.lock();
+        f.debug_struct("Pipe")
+            .field("len", &inner.len)
+            .field("readers", &inner.readers)
+            .field("writers", &inner.writers)
+            .finish()
+    }
+}
+
+/// The read end of a pipe. Dropping it closes it.
+#[derive(Debug)]
+pub struct PipeReader(Arc<Pipe>);
+
+/// The write end of a pipe. Dropping it closes it.
+#[derive(Debug)]
+pub struct PipeWriter(Arc<Pipe>);
+
+/// Returns the two ends of a new pipe.
+pub fn pipe() -> (PipeReader, PipeWriter) {
+    let pipe = Arc::new(Pipe::new(1, 1));
+    (PipeReader(pipe.clone()), PipeWriter(pipe))
+}
+
+impl PipeReader {
+    /// Returns the pipe this end reads from.
+    pub fn pipe(&self) -> &Arc<Pipe> {
+        &self.0
+    }
+
+    /// Reads from the pipe and wakes processes blocked on it. Returns `None`
+    /// if the read would block; see `Pipe::read()`.
+    pub fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let n = self.0.read(buf);
+        if n.map_or(false, |n| n > 0) {
+            SCHEDULER.wake(self.0.key(), usize::max_value());
+        }
+        n
+    }
+}
+
+impl PipeWriter {
+    /// Returns the pipe this end writes to.
+    pub fn pipe(&self) -> &Arc<Pipe> {
+        &self.0
+    }
+
+    /// Writes to the pipe and wakes processes blocked on it. Returns `Ok(None)`
+    /// if the write would block; see `Pipe::write()`.
+    pub fn write(&self, buf: &[u8]) -> Result<Option<usize>, OsError> {
+        let n = self.0.write(buf)?;
+        if n.map_or(false, |n| n > 0) {
+            SCHEDULER.wake(self.0.key(), usize::max_value());
+        }
+        Ok(n)
+    }
+}
+
+// Closing an end wakes the processes blocked on the other one. Ends are
+// closed when processes close them or exit, never with the scheduler locked.
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.close_reader();
+        SCHEDULER.wake(self.0.key(), usize::max_value());
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.close_writer();
+        SCHEDULER.wake(self.0.key(), usize::max_value());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_write_wraps() {
+        let pipe = Pipe::new(1, 1);
+        let mut buf = [0u8; PIPE_SIZE];
+        assert_eq!(pipe.read(&mut buf), None);
+        assert!(!pipe.readable());
+
+        assert_eq!(pipe.write(&[1; PIPE_SIZE - 2]), Ok(Some(PIPE_SIZE - 2)));
+        assert_eq!(pipe.read(&mut buf[..PIPE_SIZE - 3]), Some(PIPE_SIZE - 3));
+        assert_eq!(pipe.write(&[2, 3, 4, 5]), Ok(Some(4)));
+        assert_eq!(pipe.read(&mut buf), Some(5));
+        assert_eq!(&buf[..5], &[1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn full_pipe_blocks_writer() {
+        let pipe = Pipe::new(1, 1);
+        assert_eq!(pipe.write(&[0; PIPE_SIZE + 10]), Ok(Some(PIPE_SIZE)));
+        assert!(!pipe.writable());
+        assert_eq!(pipe.write(&[0]), Ok(None));
+        assert_eq!(pipe.write(&[]), Ok(Some(0)));
+    }
+
+    #[test]
+    fn closed_ends() {
+        let pipe = Pipe::new(1, 1);
+        assert_eq!(pipe.write(b"hi"), Ok(Some(2)));
+        pipe.close_writer();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(pipe.read(&mut buf), Some(2));
+        assert_eq!(pipe.read(&mut buf), Some(0));
+        assert!(pipe.readable());
+
+        pipe.close_reader();
+        assert_eq!(pipe.write(b"hi"), Err(OsError::BrokenPipe));
+        assert!(pipe.writable());
+    }
+}
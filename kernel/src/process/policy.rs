@@ -0,0 +1,194 @@
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fmt;
+
+use process::{Process, State};
+
+/// The highest static priority a process can have.
+pub const NICE_MIN: i64 = -20;
+/// The lowest static priority a process can have.
+pub const NICE_MAX: i64 = 19;
+
+/// The number of queues in the multilevel feedback policy.
+pub const MLFQ_LEVELS: usize = 4;
+
+/// The number of scheduling decisions after which the multilevel feedback
+/// policy moves every process back to the highest queue.
+pub const MLFQ_BOOST_PERIOD: usize = 64;
+
+/// Decides which process runs next.
+///
+/// Processes that are switched out go to the back of the scheduler's queue, so
+/// picking the first of several equally good processes rotates among them.
+pub trait SchedulingPolicy: fmt::Debug + Send {
+    /// Returns the index in `queue` of the process to run next, or `None` if
+    /// no process is ready. Only a process whose `is_ready()` returned `true`
+    /// may be picked.
+    fn pick(&mut self, queue: &mut VecDeque<Process>) -> Option<usize>;
+
+    /// Called when `process` stops running, after its state is set to the
+    /// state it is switched out in: `Ready` if it was preempted, or a waiting
+    /// state if it blocked.
+    fn switched_out(&mut self, _process: &mut Process) {}
+}
+
+/// Returns a new instance of the policy named `name`: `rr` for `RoundRobin`,
+/// `prio` for `StaticPriority` or `mlfq` for `Mlfq`.
+pub fn named_policy(name: &str) -> Option<Box<SchedulingPolicy>> {
+    match name {
+        "rr" => Some(Box::new(RoundRobin)),
+        "prio" => Some(Box::new(StaticPriority)),
+        "mlfq" => Some(Box::new(Mlfq::default())),
+        _ => None,
+    }
+}
+
+/// Returns the index of the ready process in `queue` with the smallest
+/// `key`, the first one if several tie.
+fn pick_min<K, F>(queue: &mut VecDeque<Process>, key: F) -> Option<usize>
+where
+    K: Ord,
+    F: Fn(&Process) -> K,
+{
+    let mut best: Option<(usize, K)> = None;
+    for (index, process) in queue.iter_mut().enumerate() {
+        if !process.is_ready() {
+            continue;
+        }
+
+        let key = key(process);
+        let better = match best {
+            Some((_, ref best)) => key < *best,
+            None => true,
+        };
+
+        if better {
+            best = Some((index, key));
+        }
+    }
+
+    best.map(|(index, _)| index)
+}
+
+/// Runs ready processes in turn, ignoring their priorities.
+#[derive(Debug, Default)]
+pub struct RoundRobin;
+
+impl SchedulingPolicy for RoundRobin {
+    fn pick(&mut self, queue: &mut VecDeque<Process>) -> Option<usize> {
+        queue.iter_mut().position(|process| process.is_ready())
+    }
+}
+
+/// Runs the ready process with the highest static priority, the lowest
+/// `nice` value. Processes are starved for as long as a process with a higher
+/// priority is ready.
+#[derive(Debug, Default)]
+pub struct StaticPriority;
+
+impl SchedulingPolicy for StaticPriority {
+    fn pick(&mut self, queue: &mut VecDeque<Process>) -> Option<usize> {
+        pick_min(queue, |process| process.nice)
+    }
+}
+
+/// A multilevel feedback queue. Processes start in the highest of
+/// `MLFQ_LEVELS` queues. A process that is preempted moves down a queue and
+/// one that blocks, such as a shell waiting for input, moves up. The ready
+/// process in the highest queue runs, with `nice` breaking ties. Every
+/// `MLFQ_BOOST_PERIOD` decisions all processes move back to the highest queue
+/// so that CPU-bound processes are not starved.
+#[derive(Debug, Default)]
+pub struct Mlfq {
+    /// The number of decisions since the last boost.
+    picks: usize,
+}
+
+impl SchedulingPolicy for Mlfq {
+    fn pick(&mut self, queue: &mut VecDeque<Process>) -> Option<usize> {
+        self.picks += 1;
+        if self.picks >= MLFQ_BOOST_PERIOD {
+            self.picks = 0;
+            for process in queue.iter_mut() {
+                process.level = 0;
+            }
+        }
+
+        pick_min(queue, |process| (process.level, process.nice))
+    }
+
+    fn switched_out(&mut self, process: &mut Process) {
+        match process.state {
+            State::Ready => process.level = min(process.level + 1, MLFQ_LEVELS - 1),
+            State::Waiting(_) | State::WaitingForChild(_) => {
+                process.level = process.level.saturating_sub(1)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(nice: &[i64]) -> VecDeque<Process> {
+        nice.iter()
+            .map(|&nice| {
+                let mut process = Process::new().expect("process");
+                process.nice = nice;
+                process
+            })
+            .collect()
+    }
+
+    #[test]
+    fn policies_by_name() {
+        assert_eq!(format!("{:?}", named_policy("rr").expect("rr")), "RoundRobin");
+        assert_eq!(format!("{:?}", named_policy("prio").expect("prio")), "StaticPriority");
+        assert_eq!(format!("{:?}", named_policy("mlfq").expect("mlfq")), "Mlfq { picks: 0 }");
+        assert!(named_policy("fifo").is_none());
+    }
+
+    #[test]
+    fn round_robin_skips_waiting() {
+        let mut queue = queue(&[0, 0]);
+        queue[0].state = State::WaitingForChild(0);
+        assert_eq!(RoundRobin.pick(&mut queue), Some(1));
+        queue[1].state = State::Running;
+        assert_eq!(RoundRobin.pick(&mut queue), None);
+    }
+
+    #[test]
+    fn static_priority_prefers_low_nice() {
+        let mut queue = queue(&[5, -3, 0, -3]);
+        assert_eq!(StaticPriority.pick(&mut queue), Some(1));
+        queue[1].state = State::Running;
+        assert_eq!(StaticPriority.pick(&mut queue), Some(3));
+    }
+
+    #[test]
+    fn mlfq_demotes_hogs_and_boosts() {
+        let mut mlfq = Mlfq::default();
+        let mut queue = queue(&[0, 0]);
+
+        // The first process is preempted until it reaches the lowest queue.
+        for _ in 0..MLFQ_LEVELS + 1 {
+            queue[0].state = State::Ready;
+            mlfq.switched_out(&mut queue[0]);
+        }
+        assert_eq!(queue[0].level, MLFQ_LEVELS - 1);
+        assert_eq!(mlfq.pick(&mut queue), Some(1));
+
+        // Blocking moves a process back up.
+        queue[0].state = State::WaitingForChild(0);
+        mlfq.switched_out(&mut queue[0]);
+        assert_eq!(queue[0].level, MLFQ_LEVELS - 2);
+
+        queue[0].state = State::Ready;
+        for _ in 0..MLFQ_BOOST_PERIOD {
+            mlfq.pick(&mut queue);
+        }
+        assert_eq!(queue[0].level, 0);
+    }
+}
@@ -1,6 +1,14 @@
-use process::{Stack, State};
+use aarch64::{self, cache};
+use fat32::traits::FileSystem as FileSystemTrait;
+use process::elf;
+use process::{FdTable, LoadError, Mailbox, Stack, State};
+use std::io::Read;
 use std::mem;
+use std::path::Path;
 use traps::TrapFrame;
+use vm::{PagePerm, UserPageTable, VirtualAddr, PAGE_SIZE, USER_IMG_BASE, USER_STACK_TOP};
+use FILE_SYSTEM;
+use VMM;
 
 /// Type alias for the type of a process ID.
 pub type Id = u64;
@@ -8,12 +16,30 @@ pub type Id = u64;
 /// A structure that represents the complete state of a process.
 #[derive(Debug)]
 pub struct Process {
+    /// The process's ID, assigned by the scheduler when the process is added.
+    /// `0` until then.
+    pub(super) pid: Id,
     /// The saved trap frame of a process.
     pub trap_frame: Box<TrapFrame>,
     /// The memory allocation used for the process's stack.
     pub stack: Stack,
     /// The scheduling state of the process.
     pub state: State,
+    /// The user address space of the process. Kernel processes have none and
+    /// run on the kernel page table.
+    pub vmap: Option<Box<UserPageTable>>,
+    /// The process's open files.
+    pub files: FdTable,
+    /// The ID of the process that spawned this one, if it is still alive.
+    /// Processes without a parent are reaped as soon as they exit.
+    pub parent: Option<Id>,
+    /// The process's static priority, from `NICE_MIN` (highest) to `NICE_MAX`
+    /// (lowest).
+    pub nice: i64,
+    /// The process's queue in the `Mlfq` policy. `0` is the highest.
+    pub level: usize,
+    /// The messages sent to the process.
+    pub mailbox: Box<Mailbox>,
 }
 
 impl Process {
@@ -26,12 +52,218 @@ impl Process {
         let stack = Stack::new()?;
         let trap_frame: Box<TrapFrame> = Box::new(Default::default());
         Some(Process {
+            pid: 0,
             trap_frame,
             stack,
             state: State::Ready,
+            vmap: None,
+            files: FdTable::new(),
+            parent: None,
+            nice: 0,
+            level: 0,
+            mailbox: Box::new(Mailbox::new()),
         })
     }
 
+    /// Creates a new kernel process that starts executing at `entry` in EL1,
+    /// on its own stack, with the kernel page table.
+    ///
+    /// If enough memory could not be allocated to start the process, returns
+    /// `None`.
+    pub fn kernel(entry: extern "C" fn()) -> Option<Process> {
+        let mut process = Process::new()?;
+        process.trap_frame.sp = process.stack.top().as_u64();
+        process.trap_frame.elr = entry as usize as u64;
+        process.trap_frame.spsr = aarch64::SPSR_EL1T;
+        Some(process)
+    }
+
+    /// Creates a new user process from the flat binary `image`.
+    ///
+    /// The process gets its own address space: `image` is copied into
+    /// executable pages at `USER_IMG_BASE` and the process's stack is mapped
+    /// so that it ends at `USER_STACK_TOP`. The process starts executing at
+    /// `USER_IMG_BASE` in EL0.
+    ///
+    /// If enough memory could not be allocated to start the process, returns
+    /// `None`.
+    pub fn user(image: &[u8]) -> Option<Process> {
+        let mut process = Process::new()?;
+        let mut vmap = Box::new(VMM.user_page_table());
+
+        for (i, chunk) in image.chunks(PAGE_SIZE).enumerate() {
+            let va = VirtualAddr::from(USER_IMG_BASE + i * PAGE_SIZE);
+            let page = vmap.alloc(va, PagePerm::RWX)?;
+            page[..chunk.len()].copy_from_slice(chunk);
+            unsafe { cache::sync_icache(page.as_ptr() as usize, chunk.len()) };
+        }
+
+        process.map_stack(&mut vmap);
+        process.trap_frame.elr = USER_IMG_BASE as u64;
+        process.trap_frame.spsr = aarch64::SPSR_EL0T;
+        process.vmap = Some(vmap);
+        Some(process)
+    }
+
+    /// Loads the aarch64 ELF executable at `path` from `FILE_SYSTEM` into a
+    /// new user process.
+    ///
+    /// Every `PT_LOAD` segment is mapped with the permissions of its flags
+    /// and the process's stack is mapped so that it ends at `USER_STACK_TOP`.
+    /// `args` is copied onto the stack (see `push_args()`). The process starts
+    /// executing at the executable's entry point in EL0.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file could not be read, is not a valid aarch64
+    /// executable, or if memory for the process could not be allocated.
+    pub fn load<P: AsRef<Path>>(path: P, args: &[&str]) -> Result<Process, LoadError> {
+        let mut image = Vec::new();
+        (&FILE_SYSTEM).open_file(path)?.read_to_end(&mut image)?;
+
+        let mut process = Process::new().ok_or(LoadError::OutOfMemory)?;
+        let mut vmap = Box::new(VMM.user_page_table());
+        let entry = elf::load(&mut vmap, &image)?;
+        process.map_stack(&mut vmap);
+
+        process.trap_frame.elr = entry;
+        process.trap_frame.spsr = aarch64::SPSR_EL0T;
+        process.vmap = Some(vmap);
+        process.push_args(args, &[])?;
+        Ok(process)
+    }
+
+    /// Maps this process's stack into `vmap` so that it ends at
+    /// `USER_STACK_TOP` and points the saved stack pointer at its top.
+    fn map_stack(&mut self, vmap: &mut UserPageTable) {
+        let stack_base = USER_STACK_TOP - Stack::SIZE;
+        for i in 0..(Stack::SIZE / PAGE_SIZE) {
+            let offset = i * PAGE_SIZE;
+            let va = VirtualAddr::from(stack_base + offset);
+            let pa = (self.stack.bottom().as_usize() + offset).into();
+            vmap.map(va, pa, PagePerm::RW);
+        }
+
+        self.trap_frame.sp = USER_STACK_TOP as u64;
+    }
+
+    /// Copies `args` and `env` onto the top of this user process's stack in
+    /// the System V layout and sets up the registers for the entry point:
+    ///
+    /// ```text
+    ///   USER_STACK_TOP -> | argument and environment strings |
+    ///                     | NULL, envp[n - 1], ..., envp[0]  |
+    ///                     | NULL, argv[n - 1], ..., argv[0]  |
+    ///               sp -> | argc                             |
+    /// ```
+    ///
+    /// `x0` is set to `argc`, `x1` to `argv` and `x2` to `envp`.
+    fn push_args(&mut self, args: &[&str], env: &[&str]) -> Result<(), LoadError> {
+        let strings: usize = args.iter().chain(env.iter()).map(|s| s.len() + 1).sum();
+        let words = 1 + args.len() + 1 + env.len() + 1;
+        if strings + words * 8 + 16 > Stack::SIZE / 2 {
+            return Err(LoadError::ArgumentsTooLarge);
+        }
+
+        let stack_base = USER_STACK_TOP - Stack::SIZE;
+        let stack_bottom = self.stack.bottom().as_usize();
+        let kernel_ptr = |va: usize| (stack_bottom + (va - stack_base)) as *mut u8;
+
+        let mut sp = USER_STACK_TOP;
+        let mut pointers: Vec<u64> = Vec::with_capacity(words);
+        pointers.push(args.len() as u64);
+        for strs in [args, env].iter() {
+            for s in strs.iter() {
+                sp -= s.len() + 1;
+                unsafe {
+                    let dst = kernel_ptr(sp);
+                    dst.copy_from_nonoverlapping(s.as_ptr(), s.len());
+                    *dst.add(s.len()) = 0;
+                }
+                pointers.push(sp as u64);
+            }
+
+            pointers.push(0);
+        }
+
+        sp = (sp - words * 8) & !0xf;
+        for (i, pointer) in pointers.iter().enumerate() {
+            unsafe { *(kernel_ptr(sp + i * 8) as *mut u64) = *pointer };
+        }
+
+        self.trap_frame.sp = sp as u64;
+        self.trap_frame.x0 = args.len() as u64;
+        self.trap_frame.x1 = (sp + 8) as u64;
+        self.trap_frame.x2 = (sp + 8 * (args.len() + 2)) as u64;
+        Ok(())
+    }
+
+    /// Returns this process's ID. It is not kept in the trap frame, which
+    /// the process can change.
+    pub fn pid(&self) -> Id {
+        self.pid
+    }
+
+    /// Returns `true` if this process has exited.
+    pub fn is_zombie(&self) -> bool {
+        match self.state {
+            State::Zombie(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the base address of the page table this process executes
+    /// with, suitable for `TTBR0`.
+    pub fn ttbr0(&self) -> u64 {
+        match self.vmap {
+            Some(ref vmap) => vmap.baddr().as_u64(),
+            None => VMM.baddr().as_u64(),
+        }
+    }
+
+    /// Returns the physical address that `va` maps to in this process's
+    /// address space, or `None` if it is not mapped. Kernel processes use the
+    /// kernel's identity mapping.
+    pub fn physical_addr(&self, va: u64) -> Option<u64> {
+        match self.vmap {
+            Some(ref vmap) => vmap.translate(VirtualAddr::from(va as usize)).map(|pa| pa.as_u64()),
+            None => Some(va),
+        }
+    }
+
+    /// Returns `true` if the `len` bytes at `va` are mapped in this process's
+    /// address space, and writable if `write` is `true`. Kernel processes
+    /// share the kernel's address space and may pass any non-null pointer.
+    pub fn can_access(&self, va: u64, len: u64, write: bool) -> bool {
+        let vmap = match self.vmap {
+            Some(ref vmap) => vmap,
+            None => return va != 0,
+        };
+
+        let end = match va.checked_add(len) {
+            Some(end) => end as usize,
+            None => return false,
+        };
+
+        let mut page = va as usize & !(PAGE_SIZE - 1);
+        while page < end {
+            let page_va = VirtualAddr::from(page);
+            let ok = if write {
+                vmap.is_writable(page_va)
+            } else {
+                vmap.translate(page_va).is_some()
+            };
+
+            if !ok {
+                return false;
+            }
+
+            page += PAGE_SIZE;
+        }
+
+        true
+    }
+
     /// Returns `true` if this process is ready to be scheduled.
     ///
     /// This functions returns `true` only if one of the following holds:
@@ -49,15 +281,72 @@ impl Process {
     pub fn is_ready(&mut self) -> bool {
         let replace = match mem::replace(&mut self.state, State::Ready) {
             State::Ready => return true,
-            State::Running => State::Running,
             State::Waiting(mut f) => if f(self) {
                 return true;
             } else {
                 State::Waiting(f)
             },
+            state => state,
         };
 
         mem::replace(&mut self.state, replace);
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the kernel address of the user stack address `va` of
+    /// `process`.
+    fn stack_ptr(process: &Process, va: u64) -> *const u8 {
+        let offset = va as usize - (USER_STACK_TOP - Stack::SIZE);
+        (process.stack.bottom().as_usize() + offset) as *const u8
+    }
+
+    fn read_word(process: &Process, va: u64) -> u64 {
+        unsafe { *(stack_ptr(process, va) as *const u64) }
+    }
+
+    fn read_str(process: &Process, va: u64) -> String {
+        let mut bytes = Vec::new();
+        let mut ptr = stack_ptr(process, va);
+        unsafe {
+            while *ptr != 0 {
+                bytes.push(*ptr);
+                ptr = ptr.add(1);
+            }
+        }
+        String::from_utf8(bytes).expect("utf-8")
+    }
+
+    #[test]
+    fn push_args_layout() {
+        let mut process = Process::new().expect("process");
+        process.push_args(&["/bin/echo", "hi"], &["HOME=/"]).expect("pushed");
+
+        let tf = &process.trap_frame;
+        let (sp, argv, envp) = (tf.sp, tf.x1, tf.x2);
+        assert_eq!(sp % 16, 0);
+        assert!(sp < USER_STACK_TOP as u64 && sp >= (USER_STACK_TOP - Stack::SIZE) as u64);
+        assert_eq!((tf.x0, argv, envp), (2, sp + 8, sp + 32));
+
+        assert_eq!(read_word(&process, sp), 2);
+        assert_eq!(read_str(&process, read_word(&process, argv)), "/bin/echo");
+        assert_eq!(read_str(&process, read_word(&process, argv + 8)), "hi");
+        assert_eq!(read_word(&process, argv + 16), 0);
+        assert_eq!(read_str(&process, read_word(&process, envp)), "HOME=/");
+        assert_eq!(read_word(&process, envp + 8), 0);
+    }
+
+    #[test]
+    fn push_args_too_large() {
+        let mut process = Process::new().expect("process");
+        let huge = ::std::iter::repeat('a').take(Stack::SIZE / 2).collect::<String>();
+        match process.push_args(&[&huge], &[]) {
+            Err(LoadError::ArgumentsTooLarge) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,22 +1,112 @@
+use std::cmp::{max, min};
 use std::collections::VecDeque;
-use std::mem;
+use std::u64;
 
-use aarch64::wfi;
-use mutex::Mutex;
-use pi::interrupt::{Controller, Interrupt};
-use process::{Id, Process, State};
+use aarch64::{self, sev, wfe, wfi};
+use alarm;
+use mutex::IrqSafeMutex;
+use pi::atags::Atags;
+use pi::generic_timer::Timer;
+use process::pid::PidAllocator;
+use process::sleep::SleepQueue;
+use process::{named_policy, Id, Mlfq, Process, SchedulingPolicy, State};
 use shell;
+use smp::{self, NCORES};
+use syscall::{OsError, WAIT_ANY};
 use timer;
-use traps::TrapFrame;
-use FILE_SYSTEM;
+use traps::{set_result, TrapFrame};
+use {FILE_SYSTEM, IRQ, SCHEDULER, VMM};
 
-/// The `tick` time.
-// FIXME: When you're ready, change this to something more reasonable.
-pub const TICK: u32 = 2 * 1000 * 1000;
+/// The default length of a time slice in microseconds. It can be changed
+/// with `quantum=<microseconds>` on the kernel command line or with
+/// `GlobalScheduler::set_quantum()`.
+pub const DEFAULT_QUANTUM: u64 = 10 * 1000;
+
+/// The shortest time slice in microseconds.
+pub const MIN_QUANTUM: u64 = 100;
+
+/// How often the load average is sampled, in microseconds.
+const LOAD_PERIOD: u32 = 1000 * 1000;
+
+/// The number of samples the load average decays over: a minute's worth.
+const LOAD_SAMPLES: u64 = 60;
+
+/// While processes wait on a poll function, idle cores wake with the event
+/// stream every 2^16 ticks of the 19.2MHz counter (~3.4ms) to poll them.
+const EVENT_STREAM_BIT: u8 = 15;
+
+/// Programs the calling core's generic timer to fire at the core's timer
+/// deadline; see `Scheduler::timer_deadline()`. `now` is the current time in
+/// microseconds.
+fn program_timer(scheduler: &Scheduler, core: usize, now: u64) {
+    let deadline = scheduler.timer_deadline(core);
+    if deadline == u64::MAX {
+        // Disabling the timer also deasserts an interrupt it raised, which
+        // would keep an idle core from sleeping.
+        Timer::Physical.disable();
+        return;
+    }
+
+    Timer::Physical.tick_in(deadline.saturating_sub(now));
+}
+
+/// Waits on an idle core until there may be a process to run. While
+/// processes wait on a poll function (`poll`), the core also wakes with the
+/// event stream.
+///
+/// Otherwise core 0, which takes the device interrupts, uses `wfi`: it wakes
+/// for a pending interrupt even while interrupts are masked, and they are
+/// unmasked afterwards to take it. `wfe` only wakes for an unmasked interrupt
+/// or an event, so cores waiting with it unmask interrupts while they wait.
+fn idle(core: usize, poll: bool) {
+    unsafe {
+        if poll {
+            aarch64::enable_event_stream(EVENT_STREAM_BIT);
+        } else {
+            aarch64::disable_event_stream();
+        }
+    }
+
+    if core == 0 && !poll {
+        wfi();
+        let daif = aarch64::unmask_interrupts();
+        aarch64::isb();
+        aarch64::restore_interrupts(daif);
+    } else {
+        let daif = aarch64::unmask_interrupts();
+        wfe();
+        aarch64::restore_interrupts(daif);
+    }
+}
+
+/// Returns the time slice given as `quantum=<microseconds>` on the kernel
+/// command line, if any.
+fn cmdline_quantum() -> Option<u64> {
+    Atags::cmdline_arg("quantum")?.parse().ok()
+}
+
+/// Returns the scheduling policy given as `sched=<rr|prio|mlfq>` on the kernel
+/// command line, if any.
+fn cmdline_policy() -> Option<Box<SchedulingPolicy>> {
+    named_policy(Atags::cmdline_arg("sched")?)
+}
 
 /// Process scheduler for the entire machine.
 #[derive(Debug)]
-pub struct GlobalScheduler(Mutex<Option<Scheduler>>);
+pub struct GlobalScheduler(IrqSafeMutex<Option<Scheduler>>);
+
+/// Preempts the calling core's current process when its time slice ends and
+/// wakes sleeping processes whose deadline has passed.
+fn handle_timer(tf: &mut TrapFrame) {
+    SCHEDULER.tick(tf);
+}
+
+/// Samples the load average. Called by a periodic system timer alarm.
+fn sample_load() {
+    if let Some(scheduler) = SCHEDULER.0.lock().as_mut() {
+        scheduler.sample_load();
+    }
+}
 
 extern "C" fn start_shell_1() {
     loop {
@@ -33,69 +123,360 @@ extern "C" fn start_shell_2() {
 impl GlobalScheduler {
     /// Returns an uninitialized wrapper around a local scheduler.
     pub const fn uninitialized() -> GlobalScheduler {
-        GlobalScheduler(Mutex::new(None))
+        GlobalScheduler(IrqSafeMutex::new(None))
+    }
+
+    /// Initializes the scheduler and adds the initial shell processes to it.
+    /// The time slice and the scheduling policy are read from the kernel
+    /// command line. The policy defaults to `Mlfq`. The load average is
+    /// sampled by an alarm on a system timer compare channel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the initial processes could not be allocated or no compare
+    /// channel is free.
+    pub fn initialize(&self) {
+        let mut scheduler = match cmdline_policy() {
+            Some(policy) => Scheduler::with_policy(policy),
+            None => Scheduler::new(),
+        };
+
+        if let Some(quantum) = cmdline_quantum() {
+            scheduler.set_quantum(quantum);
+        }
+
+        scheduler.add(Process::kernel(start_shell_1).expect("first process")).expect("first pid");
+        scheduler.add(Process::kernel(start_shell_2).expect("second process")).expect("second pid");
+        *self.0.lock() = Some(scheduler);
+        alarm::set_alarm(LOAD_PERIOD, Some(LOAD_PERIOD), sample_load).expect("load alarm");
     }
 
     /// Adds a process to the scheduler's queue and returns that process's ID.
     /// For more details, see the documentation on `Scheduler::add()`.
+    ///
+    /// A process that cannot be added is dropped after the scheduler is
+    /// unlocked: dropping the pipe ends it holds wakes processes.
     pub fn add(&self, process: Process) -> Option<Id> {
+        let result = self.0
+            .lock()
+            .as_mut()
+            .expect("scheduler uninitialized")
+            .add(process);
+
+        match result {
+            Ok(id) => {
+                sev();
+                Some(id)
+            }
+            Err(_process) => None,
+        }
+    }
+
+    /// Returns the length of a time slice in microseconds.
+    pub fn quantum(&self) -> u64 {
+        self.0.lock().as_ref().expect("scheduler uninitialized").quantum
+    }
+
+    /// Sets the length of time slices that start from now on to `us`
+    /// microseconds, but at least `MIN_QUANTUM`.
+    pub fn set_quantum(&self, us: u64) {
         self.0
             .lock()
             .as_mut()
             .expect("scheduler uninitialized")
-            .add(process)
+            .set_quantum(us)
     }
 
-    /// Performs a context switch using `tf` by setting the state of the current
-    /// process to `new_state`, saving `tf` into the current process, and
-    /// restoring the next process's trap frame into `tf`. For more details, see
-    /// the documentation on `Scheduler::switch()`.
+    /// Replaces the scheduling policy with `policy`. The old policy is dropped
+    /// after the scheduler is unlocked.
+    pub fn set_policy(&self, policy: Box<SchedulingPolicy>) {
+        let _old = self.0
+            .lock()
+            .as_mut()
+            .expect("scheduler uninitialized")
+            .set_policy(policy);
+    }
+
+    /// Returns the one-minute load average in thousandths: the mean number of
+    /// processes that were ready or running.
+    pub fn load(&self) -> u64 {
+        self.0.lock().as_ref().expect("scheduler uninitialized").load
+    }
+
+    /// Returns the number of microseconds core `core` has spent idle.
+    pub fn idle_time(&self, core: usize) -> u64 {
+        self.0
+            .lock()
+            .as_ref()
+            .expect("scheduler uninitialized")
+            .idle_time(core, timer::current_time())
+    }
+
+    /// Calls `f` with the calling core's current process and returns its
+    /// result, or `None` if the core has no current process. The scheduler is
+    /// locked while `f` runs.
+    pub fn with_current<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Process) -> R,
+    {
+        let core = smp::core();
+        let mut guard = self.0.lock();
+        let scheduler = guard.as_mut().expect("scheduler uninitialized");
+        scheduler.running[core].as_mut().map(f)
+    }
+
+    /// Calls `f` with the process whose ID is `pid` and returns its result, or
+    /// `None` if there is no such process. The scheduler is locked while `f`
+    /// runs.
+    pub fn with_process<F, R>(&self, pid: Id, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Process) -> R,
+    {
+        let mut guard = self.0.lock();
+        guard.as_mut().expect("scheduler uninitialized").get(pid).map(f)
+    }
+
+    /// Performs a context switch using `tf` by setting the state of the
+    /// calling core's current process to `new_state`, saving `tf` into it, and
+    /// restoring the next process's trap frame into `tf`. If the calling core
+    /// has no current process, returns `None` and leaves `tf` untouched.
+    /// Otherwise returns `Some` of the process ID that was context switched
+    /// into `tf`.
+    ///
+    /// This method blocks until there is a process to switch to. The scheduler
+    /// is not locked while waiting so that the other cores keep scheduling.
     #[must_use]
     pub fn switch(&self, new_state: State, tf: &mut TrapFrame) -> Option<Id> {
+        let core = smp::core();
         self.0
             .lock()
             .as_mut()
             .expect("scheduler uninitialized")
-            .switch(new_state, tf)
+            .schedule_out(core, new_state, tf)?;
+
+        Some(self.switch_to(core, tf))
     }
 
-    fn new_process(fn_ptr: *const fn()) -> Process {
-        let mut process = Process::new().expect("first process");
+    /// Puts the calling core's current process to sleep for `us` microseconds
+    /// and switches to the next ready process in `tf`. The process is woken
+    /// by the scheduler with the time it actually slept, in milliseconds, as
+    /// the result of its system call. If the calling core has no current
+    /// process, returns `None` and leaves `tf` untouched.
+    #[must_use]
+    pub fn sleep(&self, us: u64, tf: &mut TrapFrame) -> Option<Id> {
+        let core = smp::core();
         {
-            let tf = &mut *(process.trap_frame);
-            tf.sp = process.stack.top().as_u64();
-            tf.elr = fn_ptr as *const fn() as u64;
-            // spsr is already in the proper state when zeroed
+            let mut guard = self.0.lock();
+            let scheduler = guard.as_mut().expect("scheduler uninitialized");
+            let now = timer::current_time();
+            scheduler.sleep(core, now, now.saturating_add(us), tf)?;
+            program_timer(scheduler, core, now);
         }
-        process
+
+        Some(self.switch_to(core, tf))
     }
 
-    /// Initializes the scheduler and starts executing processes in user space
-    /// using timer interrupt based preemptive scheduling. This method should
-    /// not return under normal conditions.
-    pub fn start(&self) {
-        let mut interrupt_controller = Controller::new();
-        interrupt_controller.enable(Interrupt::Timer1);
+    /// Handles the calling core's timer interrupt: wakes the processes whose
+    /// sleep has ended and, if the current process's time slice is over,
+    /// switches to the next ready process in `tf`.
+    pub fn tick(&self, tf: &mut TrapFrame) {
+        let core = smp::core();
+        {
+            let mut guard = self.0.lock();
+            let scheduler = guard.as_mut().expect("scheduler uninitialized");
+            let now = timer::current_time();
+            if scheduler.wake_sleepers(now) {
+                sev();
+            }
+
+            if now < scheduler.quantum_end[core] {
+                program_timer(scheduler, core, now);
+                return;
+            }
+        }
+
+        let _ = self.switch(State::Ready, tf);
+    }
+
+    /// Blocks the calling core's current process on `key` if `condition`,
+    /// called with the process, returns `true`, and switches to the next ready
+    /// process in `tf`. The condition is checked under the scheduler's lock,
+    /// so a `wake()` for `key` that follows a change `condition` depends on
+    /// cannot be missed. Returns `false`, leaving `tf` untouched, if the
+    /// condition is `false` or the calling core has no current process.
+    ///
+    /// Keys are the addresses of what processes wait on: the physical address
+    /// of a futex word, or the kernel address of a pipe, mailbox or the console.
+    pub fn block_if<F>(&self, key: u64, condition: F, tf: &mut TrapFrame) -> bool
+    where
+        F: FnOnce(&mut Process) -> bool,
+    {
+        let core = smp::core();
+        {
+            let mut guard = self.0.lock();
+            let scheduler = guard.as_mut().expect("scheduler uninitialized");
+            let blocks = match scheduler.running[core] {
+                Some(ref mut process) => condition(process),
+                None => false,
+            };
+
+            if !blocks || scheduler.schedule_out(core, State::Blocked(key), tf).is_none() {
+                return false;
+            }
+        }
+
+        self.switch_to(core, tf);
+        true
+    }
+
+    /// Wakes up to `count` processes blocked on `key`, in the order they
+    /// blocked. Returns the number of processes woken.
+    pub fn wake(&self, key: u64, count: usize) -> usize {
+        let woken = self.0
+            .lock()
+            .as_mut()
+            .expect("scheduler uninitialized")
+            .wake(key, count);
+
+        if woken > 0 {
+            sev();
+        }
+        woken
+    }
+
+    /// Terminates the calling core's current process with exit status
+    /// `status` and switches to the next ready process in `tf`. The process
+    /// becomes a zombie until its parent reaps it with `wait()`, or is freed
+    /// right away if it has no parent. If the calling core has no current
+    /// process, returns `None` and leaves `tf` untouched.
+    #[must_use]
+    pub fn exit(&self, status: u64, tf: &mut TrapFrame) -> Option<Id> {
+        let core = smp::core();
+
+        // The process's page table may be freed below, so stop using it first.
+        unsafe {
+            aarch64::set_ttbr0(VMM.baddr().as_u64());
+            aarch64::tlb_invalidate_all();
+        }
 
-        // 2000 us * 1000 ms = 2 s
-        timer::tick_in(TICK);
+        let freed = {
+            let mut guard = self.0.lock();
+            let scheduler = guard.as_mut().expect("scheduler uninitialized");
+            scheduler.exit(core, status)?;
+            scheduler.take_freed()
+        };
+
+        drop(freed);
+        sev();
+        Some(self.switch_to(core, tf))
+    }
 
-        let mut sched_opt = self.0.lock();
-        let scheduler = sched_opt.get_or_insert_with(|| Scheduler::new());
-        let p1 = GlobalScheduler::new_process(start_shell_1 as *const fn());
-        let p2 = GlobalScheduler::new_process(start_shell_2 as *const fn());
-        let tf = &*(p1.trap_frame) as *const TrapFrame as *const u8;
-        scheduler.add(p1);
-        scheduler.add(p2);
+    /// Reaps an exited child of the calling core's current process: the child
+    /// `pid`, or any child if `pid` is `WAIT_ANY`. Returns `Ok(Some((id,
+    /// status)))` with the reaped child's ID and exit status.
+    ///
+    /// If a matching child exists but has not exited, the current process
+    /// waits until one does: it is switched out of `tf` and `Ok(None)` is
+    /// returned. The process is woken with the same `tf`, so the caller should
+    /// arrange for the request to be retried.
+    ///
+    /// # Errors
+    ///
+    /// Returns `OsError::NoChild` if the current process has no matching
+    /// child.
+    pub fn wait(&self, pid: Id, tf: &mut TrapFrame) -> Result<Option<(Id, u64)>, OsError> {
+        let core = smp::core();
+        let (reaped, freed) = {
+            let mut guard = self.0.lock();
+            let scheduler = guard.as_mut().expect("scheduler uninitialized");
+            let reaped = scheduler.reap(core, pid)?;
+            if reaped.is_none() {
+                // Switch out under the same lock so that an exiting child
+                // cannot miss its parent going to sleep.
+                scheduler
+                    .schedule_out(core, State::WaitingForChild(pid), tf)
+                    .ok_or(OsError::NoChild)?;
+            }
 
+            (reaped, scheduler.take_freed())
+        };
+
+        drop(freed);
+        if reaped.is_some() {
+            return Ok(reaped);
+        }
+
+        self.switch_to(core, tf);
+        Ok(None)
+    }
+
+    /// Waits until a process is ready, makes it `core`'s current process, and
+    /// restores its trap frame into `tf`. The process's page table is
+    /// installed in `TTBR0` and its time slice starts before returning.
+    /// Returns the process's ID.
+    ///
+    /// While no process is ready, the core is idle: it has no time slice, so
+    /// its timer is only programmed for wakeups, and the time is accounted to the
+    /// core's idle time.
+    fn switch_to(&self, core: usize, tf: &mut TrapFrame) -> Id {
+        loop {
+            let poll = {
+                let mut guard = self.0.lock();
+                let scheduler = guard.as_mut().expect("scheduler uninitialized");
+                let now = timer::current_time();
+                if scheduler.wake_sleepers(now) {
+                    sev();
+                }
+
+                match scheduler.switch_to(core, tf) {
+                    Some(id) => {
+                        scheduler.leave_idle(core, now);
+                        scheduler.quantum_end[core] = now + scheduler.quantum;
+                        program_timer(scheduler, core, now);
+
+                        let ttbr0 = scheduler.running[core].as_ref().expect("switched").ttbr0();
+                        unsafe {
+                            aarch64::set_ttbr0(ttbr0);
+                            aarch64::tlb_invalidate_all();
+                        }
+                        return id;
+                    }
+                    None => {
+                        scheduler.enter_idle(core, now);
+                        scheduler.quantum_end[core] = u64::MAX;
+                        program_timer(scheduler, core, now);
+                        scheduler.has_pollers()
+                    }
+                }
+            };
+
+            idle(core, poll);
+        }
+    }
+
+    /// Starts executing processes on the calling core, which is preempted by
+    /// its own generic timer. This method should not return under normal
+    /// conditions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the scheduler is uninitialized.
+    pub fn start(&self) {
+        let core = smp::core();
+        IRQ.register_timer(Timer::Physical, handle_timer);
+
+        let mut tf = TrapFrame::default();
+        self.switch_to(core, &mut tf);
+
+        // `context_restore` leaves `x0` and `x30` alone since the exception
+        // vectors restore them; restore them from the trap frame here.
         unsafe {
-            asm!("mov x0, $0
+            asm!("mov sp, $1
+                  mov x0, $0
                   bl  context_restore
-                  ldr x0, =_start
-                  add sp, x0, #0
-                  mov x0, #0
+                  ldp x30, x0, [x0, #784]
                   eret"
-                 :: "r"(tf)
+                 :: "r"(&tf as *const TrapFrame), "r"(smp::stack_top(core))
                  :: "volatile");
         }
     }
@@ -103,79 +484,530 @@ impl GlobalScheduler {
 
 #[derive(Debug)]
 struct Scheduler {
+    /// The processes that are not running on any core.
     processes: VecDeque<Process>,
-    current_id: Option<Id>,
-    last_id: Option<Id>,
+    /// The process running on each core, indexed by core.
+    running: [Option<Process>; NCORES],
+    /// The IDs of all processes, running or not.
+    pids: PidAllocator,
+    /// Decides which process runs next.
+    policy: Box<SchedulingPolicy>,
+    /// The processes in the `Sleeping` state.
+    sleepers: SleepQueue,
+    /// When the time slice of each core's current process ends, in
+    /// microseconds of the system timer. `u64::MAX` if the core is idle.
+    quantum_end: [u64; NCORES],
+    /// The length of a time slice in microseconds.
+    quantum: u64,
+    /// The number of microseconds each core spent idle before it last left
+    /// the idle state.
+    idle_time: [u64; NCORES],
+    /// When each core became idle, or `u64::MAX` if it is not idle.
+    idle_since: [u64; NCORES],
+    /// The load average in thousandths, decayed over `LOAD_SAMPLES`.
+    load: u64,
+    /// Processes freed under the lock, to be dropped once it is released.
+    freed: Vec<Process>,
 }
 
 impl Scheduler {
-    /// Returns a new `Scheduler` with an empty queue.
+    /// Returns a new `Scheduler` with an empty queue and the `Mlfq` policy.
     fn new() -> Scheduler {
+        Scheduler::with_policy(Box::new(Mlfq::default()))
+    }
+
+    /// Returns a new `Scheduler` with an empty queue that picks processes
+    /// with `policy`.
+    fn with_policy(policy: Box<SchedulingPolicy>) -> Scheduler {
         Scheduler {
             processes: VecDeque::new(),
-            current_id: Some(1),
-            last_id: None,
+            running: [None, None, None, None],
+            pids: PidAllocator::new(),
+            policy,
+            sleepers: SleepQueue::new(),
+            quantum_end: [u64::MAX; NCORES],
+            quantum: DEFAULT_QUANTUM,
+            idle_time: [0; NCORES],
+            idle_since: [u64::MAX; NCORES],
+            load: 0,
+            freed: Vec::new(),
         }
     }
 
-    fn current(&mut self) -> Option<&mut Process> {
-        self.processes.get_mut(0)
-    }
-
     /// Adds a process to the scheduler's queue and returns that process's ID if
     /// a new process can be scheduled. The process ID is newly allocated for
-    /// the process and saved in its `trap_frame`. If no further processes can
-    /// be scheduled, returns `None`.
-    ///
-    /// If this is the first process added, it is marked as the current process.
-    /// It is the caller's responsibility to ensure that the first time `switch`
-    /// is called, that process is executing on the CPU.
-    fn add(&mut self, mut process: Process) -> Option<Id> {
-        let pid = if let Some(prev) = self.current_id {
-            let current_id = prev.checked_add(1);
-            self.last_id = Some(prev);
-            Some(prev)
-        } else {
-            None
-        }?;
+    /// the process. If no further processes can be scheduled, returns the
+    /// process back so that the caller decides where it is dropped.
+    fn add(&mut self, mut process: Process) -> Result<Id, Process> {
+        let pid = match self.pids.alloc() {
+            Some(pid) => pid,
+            None => return Err(process),
+        };
 
-        process.trap_frame.tpidr = pid;
+        process.pid = pid;
         self.processes.push_back(process);
-        Some(pid)
+        Ok(pid)
+    }
+
+    /// Returns an iterator over every process, running or not.
+    fn iter_mut<'a>(&'a mut self) -> Box<Iterator<Item = &'a mut Process> + 'a> {
+        let running = self.running.iter_mut().filter_map(|process| process.as_mut());
+        Box::new(self.processes.iter_mut().chain(running))
+    }
+
+    /// Returns the process whose ID is `pid`, running or not.
+    fn get(&mut self, pid: Id) -> Option<&mut Process> {
+        self.iter_mut().find(|process| process.pid() == pid)
+    }
+
+    /// Frees the ID of `process` and sets the process aside to be dropped by
+    /// `take_freed()`'s caller. Dropping a process frees its stack, trap frame
+    /// and address space.
+    fn free(&mut self, process: Process) {
+        self.pids.release(process.pid());
+        self.freed.push(process);
+    }
+
+    /// Returns the processes freed since the last call. The caller drops them
+    /// after unlocking the scheduler: dropping the pipe ends a process holds
+    /// wakes processes, which locks the scheduler.
+    fn take_freed(&mut self) -> Vec<Process> {
+        ::std::mem::replace(&mut self.freed, Vec::new())
+    }
+
+    /// Saves `tf` into `core`'s current process and puts it to sleep from
+    /// `now` until `until`. Returns `None` if `core` has no current process.
+    fn sleep(&mut self, core: usize, now: u64, until: u64, tf: &TrapFrame) -> Option<()> {
+        let pid = self.running[core].as_ref()?.pid();
+        self.schedule_out(core, State::Sleeping { since: now, until }, tf)?;
+        self.sleepers.insert(until, pid);
+        Some(())
+    }
+
+    /// Makes every process whose sleep ends at or before `now` ready, with
+    /// the number of milliseconds it slept as the result of its system call.
+    /// Returns `true` if any process was woken.
+    fn wake_sleepers(&mut self, now: u64) -> bool {
+        let mut woken = false;
+        while let Some(pid) = self.sleepers.pop_expired(now) {
+            let process = match self.get(pid) {
+                Some(process) => process,
+                None => continue,
+            };
+
+            let since = match process.state {
+                State::Sleeping { since, .. } => since,
+                _ => continue,
+            };
+
+            set_result(&mut process.trap_frame, Ok((now - since) / 1000));
+            process.state = State::Ready;
+            woken = true;
+        }
+
+        woken
+    }
+
+    /// Makes up to `count` processes blocked on `key` ready, those that
+    /// blocked first first. Returns the number of processes
+    /// woken.
+    fn wake(&mut self, key: u64, count: usize) -> usize {
+        let mut woken = 0;
+        for process in self.processes.iter_mut() {
+            if woken == count {
+                break;
+            }
+
+            let blocked = match process.state {
+                State::Blocked(k) => k == key,
+                _ => false,
+            };
+
+            if blocked {
+                process.state = State::Ready;
+                woken += 1;
+            }
+        }
+
+        woken
+    }
+
+    /// Returns `true` if a process is waiting on a poll function.
+    fn has_pollers(&self) -> bool {
+        self.processes.iter().any(|process| match process.state {
+            State::Waiting(_) => true,
+            _ => false,
+        })
+    }
+
+    /// Sets the length of a time slice to `us` microseconds, but at least
+    /// `MIN_QUANTUM`.
+    fn set_quantum(&mut self, us: u64) {
+        self.quantum = max(us, MIN_QUANTUM);
+    }
+
+    /// Replaces the scheduling policy with `policy` and returns the old one.
+    fn set_policy(&mut self, policy: Box<SchedulingPolicy>) -> Box<SchedulingPolicy> {
+        ::std::mem::replace(&mut self.policy, policy)
+    }
+
+    /// Folds the number of processes that are ready or running into the load
+    /// average.
+    fn sample_load(&mut self) {
+        let ready = self.processes.iter().filter(|process| match process.state {
+            State::Ready => true,
+            _ => false,
+        });
+        let running = self.running.iter().filter(|process| process.is_some());
+        let runnable = (ready.count() + running.count()) as u64;
+        self.load = (self.load * (LOAD_SAMPLES - 1) + runnable * 1000) / LOAD_SAMPLES;
+    }
+
+    /// Records that `core` is idle from `now` on, unless it already is.
+    fn enter_idle(&mut self, core: usize, now: u64) {
+        if self.idle_since[core] == u64::MAX {
+            self.idle_since[core] = now;
+        }
+    }
+
+    /// Records that `core` stopped being idle at `now`.
+    fn leave_idle(&mut self, core: usize, now: u64) {
+        if self.idle_since[core] != u64::MAX {
+            self.idle_time[core] += now.saturating_sub(self.idle_since[core]);
+            self.idle_since[core] = u64::MAX;
+        }
+    }
+
+    /// Returns the number of microseconds `core` has spent idle up to `now`.
+    fn idle_time(&self, core: usize, now: u64) -> u64 {
+        match self.idle_since[core] {
+            u64::MAX => self.idle_time[core],
+            since => self.idle_time[core] + now.saturating_sub(since),
+        }
+    }
+
+    /// Returns the time `core`'s timer should next fire at: the end of the
+    /// core's time slice or the nearest wakeup, whichever is earlier.
+    /// `u64::MAX` if there is neither. Every core's timer covers the nearest
+    /// wakeup, so a process that goes to sleep on any core is woken on time
+    /// even if the other cores are idle with their timers stopped.
+    fn timer_deadline(&self, core: usize) -> u64 {
+        let wakeup = self.sleepers.next_deadline().unwrap_or(u64::MAX);
+        min(self.quantum_end[core], wakeup)
+    }
+
+    /// Terminates `core`'s current process with exit status `status`. Its
+    /// children are orphaned and any that already exited are freed. If the
+    /// process's parent is alive, the process is kept as a zombie and the
+    /// parent is woken if it is waiting for it; otherwise it is freed. Returns
+    /// `None` if `core` has no current process.
+    fn exit(&mut self, core: usize, status: u64) -> Option<()> {
+        let mut process = self.running[core].take()?;
+        let pid = process.pid();
+
+        for child in self.iter_mut().filter(|child| child.parent == Some(pid)) {
+            child.parent = None;
+        }
+
+        let orphans: Vec<Id> = self
+            .processes
+            .iter()
+            .filter(|child| child.parent.is_none() && child.is_zombie())
+            .map(|child| child.pid())
+            .collect();
+
+        for orphan in orphans {
+            let index = self.processes.iter().position(|child| child.pid() == orphan);
+            if let Some(child) = index.and_then(|index| self.processes.remove(index)) {
+                self.free(child);
+            }
+        }
+
+        let has_parent = match process.parent {
+            Some(ppid) => match self.get(ppid) {
+                Some(parent) => {
+                    let wakes = match parent.state {
+                        State::WaitingForChild(id) => id == WAIT_ANY || id == pid,
+                        _ => false,
+                    };
+
+                    if wakes {
+                        parent.state = State::Ready;
+                    }
+                    true
+                }
+                None => false,
+            },
+            None => false,
+        };
+
+        if has_parent {
+            process.state = State::Zombie(status);
+            self.processes.push_back(process);
+        } else {
+            self.free(process);
+        }
+
+        Some(())
     }
 
-    fn next(&mut self) -> Option<&mut Process> {
+    /// Frees an exited child of `core`'s current process: the child `pid`, or
+    /// any child if `pid` is `WAIT_ANY`. Returns `Ok(Some((id, status)))` with
+    /// the child's ID and exit status, or `Ok(None)` if matching children
+    /// exist but none has exited.
+    fn reap(&mut self, core: usize, pid: Id) -> Result<Option<(Id, u64)>, OsError> {
+        let ppid = self.running[core].as_ref().ok_or(OsError::NoChild)?.pid();
+        let matches = |process: &Process| {
+            process.parent == Some(ppid) && (pid == WAIT_ANY || process.pid() == pid)
+        };
+
+        if !self.iter_mut().any(|process| matches(process)) {
+            return Err(OsError::NoChild);
+        }
+
         let index = self
             .processes
-            .iter_mut()
-            .position(|process| process.is_ready())?;
-        let mut back: VecDeque<Process> = self.processes.drain(..index).collect();
-        self.processes.append(&mut back);
-        self.current()
-    }
-
-    /// Sets the current process's state to `new_state`, finds the next process
-    /// to switch to, and performs the context switch on `tf` by saving `tf`
-    /// into the current process and restoring the next process's trap frame
-    /// into `tf`. If there is no current process, returns `None`. Otherwise,
-    /// returns `Some` of the process ID that was context switched into `tf`.
-    ///
-    /// This method blocks until there is a process to switch to, conserving
-    /// energy as much as possible in the interim.
-    fn switch(&mut self, new_state: State, tf: &mut TrapFrame) -> Option<Id> {
-        let mut cur = self.processes.pop_front()?;
-        let old_tf = tf.clone();
-        mem::replace(&mut *cur.trap_frame, old_tf);
-        mem::replace(&mut cur.state, new_state);
+            .iter()
+            .position(|process| matches(process) && process.is_zombie());
+
+        let child = match index.and_then(|index| self.processes.remove(index)) {
+            Some(child) => child,
+            None => return Ok(None),
+        };
+
+        let reaped = match child.state {
+            State::Zombie(status) => (child.pid(), status),
+            _ => unreachable!("reaped a live process"),
+        };
+
+        self.free(child);
+        Ok(Some(reaped))
+    }
+
+    /// Saves `tf` into `core`'s current process, sets its state to
+    /// `new_state`, and moves it to the back of the queue. Returns `None` if
+    /// `core` has no current process.
+    fn schedule_out(&mut self, core: usize, new_state: State, tf: &TrapFrame) -> Option<()> {
+        let mut cur = self.running[core].take()?;
+        *cur.trap_frame = *tf;
+        cur.state = new_state;
+        self.policy.switched_out(&mut cur);
         self.processes.push_back(cur);
+        Some(())
+    }
 
-        loop {
-            if let Some(next) = self.next() {
-                mem::replace(tf, *next.trap_frame);
-                return Some(tf.tpidr);
-            } else {
-                wfi();
-            }
+    /// Picks a ready process with the scheduling policy, makes it `core`'s
+    /// current process, and restores its trap frame into `tf`. Returns `None`
+    /// if no process is ready. Otherwise returns `Some` of the process's ID.
+    fn switch_to(&mut self, core: usize, tf: &mut TrapFrame) -> Option<Id> {
+        let index = self.policy.pick(&mut self.processes)?;
+
+        let mut next = self.processes.remove(index)?;
+        let pid = next.pid();
+        next.state = State::Running;
+        *tf = *next.trap_frame;
+        self.running[core] = Some(next);
+        Some(pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use process::{RoundRobin, StaticPriority};
+
+    /// Adds a process whose trap frame is marked with `elr` and returns its ID.
+    fn add(scheduler: &mut Scheduler, elr: u64, parent: Option<Id>) -> Id {
+        let mut process = Process::new().expect("process");
+        process.trap_frame.elr = elr;
+        process.parent = parent;
+        scheduler.add(process).expect("pid")
+    }
+
+    #[test]
+    fn add_allocates_distinct_pids() {
+        let mut scheduler = Scheduler::new();
+        assert_eq!(add(&mut scheduler, 0, None), 1);
+        assert_eq!(add(&mut scheduler, 0, None), 2);
+        assert_eq!(add(&mut scheduler, 0, None), 3);
+        assert_eq!(scheduler.get(2).map(|process| process.pid()), Some(2));
+        assert!(scheduler.get(4).is_none());
+    }
+
+    #[test]
+    fn switch_reports_next_pid() {
+        let mut scheduler = Scheduler::new();
+        add(&mut scheduler, 0x100, None);
+        add(&mut scheduler, 0x200, None);
+
+        let mut tf = TrapFrame::default();
+        assert_eq!(scheduler.switch_to(0, &mut tf), Some(1));
+        assert_eq!(tf.elr, 0x100);
+        assert_eq!(scheduler.switch_to(1, &mut tf), Some(2));
+        assert_eq!(tf.elr, 0x200);
+        assert_eq!(scheduler.switch_to(2, &mut tf), None);
+
+        // The trap frame saved on the way out is restored on the way back in.
+        // The process's thread pointer is its own and does not change its ID.
+        tf.elr = 0x204;
+        tf.tpidr = 1;
+        assert_eq!(scheduler.schedule_out(1, State::Ready, &tf), Some(()));
+        assert_eq!(scheduler.get(2).map(|process| process.trap_frame.elr), Some(0x204));
+        let mut tf = TrapFrame::default();
+        assert_eq!(scheduler.switch_to(3, &mut tf), Some(2));
+        assert_eq!((tf.tpidr, tf.elr), (1, 0x204));
+        assert_eq!(scheduler.running[3].as_ref().map(|process| process.pid()), Some(2));
+        assert_eq!(scheduler.running[0].as_ref().map(|process| process.pid()), Some(1));
+        assert_eq!(scheduler.schedule_out(2, State::Ready, &tf), None);
+    }
+
+    #[test]
+    fn reaped_pids_are_reused() {
+        let mut scheduler = Scheduler::new();
+        let parent = add(&mut scheduler, 0, None);
+        let mut tf = TrapFrame::default();
+        assert_eq!(scheduler.switch_to(0, &mut tf), Some(parent));
+
+        let child = add(&mut scheduler, 0, Some(parent));
+        assert_eq!(scheduler.reap(0, child), Ok(None));
+        assert_eq!(scheduler.switch_to(1, &mut tf), Some(child));
+        assert_eq!(scheduler.exit(1, 7), Some(()));
+        assert!(scheduler.get(child).map_or(false, |process| process.is_zombie()));
+
+        // The PID stays taken until the zombie is reaped.
+        assert_eq!(add(&mut scheduler, 0, None), 3);
+        assert_eq!(scheduler.reap(0, WAIT_ANY), Ok(Some((child, 7))));
+        assert!(scheduler.get(child).is_none());
+        assert_eq!(scheduler.reap(0, child), Err(OsError::NoChild));
+        assert_eq!(add(&mut scheduler, 0, None), child);
+    }
+
+    #[test]
+    fn orphans_are_freed_on_exit() {
+        let mut scheduler = Scheduler::new();
+        let parent = add(&mut scheduler, 0, None);
+        let mut tf = TrapFrame::default();
+        assert_eq!(scheduler.switch_to(0, &mut tf), Some(parent));
+        let child = add(&mut scheduler, 0, Some(parent));
+
+        // A parent waiting for its child is woken when the child exits.
+        assert_eq!(scheduler.schedule_out(0, State::WaitingForChild(child), &tf), Some(()));
+        assert_eq!(scheduler.switch_to(1, &mut tf), Some(child));
+        assert_eq!(scheduler.exit(1, 0), Some(()));
+        assert!(scheduler.get(parent).map_or(false, |process| process.is_ready()));
+
+        // The parent exits without reaping: its zombie child goes with it.
+        assert_eq!(scheduler.switch_to(0, &mut tf), Some(parent));
+        assert_eq!(scheduler.exit(0, 0), Some(()));
+        assert!(scheduler.get(parent).is_none());
+        assert!(scheduler.get(child).is_none());
+        assert_eq!(scheduler.take_freed().len(), 2);
+        assert!(scheduler.take_freed().is_empty());
+        assert_eq!(add(&mut scheduler, 0, None), 1);
+        assert_eq!(add(&mut scheduler, 0, None), 2);
+    }
+
+    #[test]
+    fn sleepers_are_woken_at_their_deadline() {
+        let mut scheduler = Scheduler::new();
+        let first = add(&mut scheduler, 0, None);
+        let second = add(&mut scheduler, 0, None);
+        let (mut tf0, mut tf1) = (TrapFrame::default(), TrapFrame::default());
+        assert_eq!(scheduler.switch_to(0, &mut tf0), Some(first));
+        assert_eq!(scheduler.switch_to(1, &mut tf1), Some(second));
+        scheduler.quantum_end[0] = 50_000;
+
+        assert_eq!(scheduler.sleep(0, 1000, 31_000, &tf0), Some(()));
+        assert_eq!(scheduler.sleep(1, 2000, 12_000, &tf1), Some(()));
+
+        let mut tf = TrapFrame::default();
+        assert_eq!(scheduler.timer_deadline(0), 12_000);
+        assert_eq!(scheduler.timer_deadline(1), 12_000);
+        assert_eq!(scheduler.switch_to(0, &mut tf), None);
+
+        scheduler.wake_sleepers(12_500);
+        assert_eq!(scheduler.timer_deadline(0), 31_000);
+        assert_eq!(scheduler.timer_deadline(1), 31_000);
+        assert_eq!(scheduler.switch_to(0, &mut tf), Some(second));
+        assert_eq!((tf.x0, tf.x7), (10, 0));
+
+        scheduler.wake_sleepers(40_000);
+        assert_eq!(scheduler.timer_deadline(0), 50_000);
+        assert_eq!(scheduler.timer_deadline(1), u64::MAX);
+        assert_eq!(scheduler.switch_to(1, &mut tf), Some(first));
+        assert_eq!((tf.x0, tf.x7), (39, 0));
+    }
+
+    #[test]
+    fn idle_time_is_accounted_per_core() {
+        let mut scheduler = Scheduler::new();
+        scheduler.enter_idle(1, 1000);
+        scheduler.enter_idle(1, 1500);
+        assert_eq!(scheduler.idle_time(1, 1800), 800);
+        scheduler.leave_idle(1, 2000);
+        assert_eq!(scheduler.idle_time(1, 9000), 1000);
+
+        scheduler.enter_idle(1, 5000);
+        scheduler.leave_idle(1, 5500);
+        scheduler.leave_idle(1, 7000);
+        assert_eq!(scheduler.idle_time(1, 9000), 1500);
+        assert_eq!(scheduler.idle_time(0, 9000), 0);
+    }
+
+    #[test]
+    fn wake_blocked_in_order() {
+        let mut scheduler = Scheduler::new();
+        let mut tfs = [TrapFrame::default(); 3];
+        for core in 0..3 {
+            add(&mut scheduler, 0, None);
+            assert!(scheduler.switch_to(core, &mut tfs[core]).is_some());
+        }
+
+        for core in 0..3 {
+            let key = if core == 1 { 0x2000 } else { 0x1000 };
+            assert_eq!(scheduler.schedule_out(core, State::Blocked(key), &tfs[core]), Some(()));
+        }
+
+        assert_eq!(scheduler.wake(0x3000, 1), 0);
+        assert_eq!(scheduler.wake(0x1000, 1), 1);
+        assert_eq!(scheduler.get(1).map(|process| process.is_ready()), Some(true));
+        assert_eq!(scheduler.get(3).map(|process| process.is_ready()), Some(false));
+        assert_eq!(scheduler.wake(0x1000, usize::max_value()), 1);
+        assert_eq!(scheduler.wake(0x2000, 2), 1);
+        assert_eq!(scheduler.wake(0x2000, 2), 0);
+    }
+
+    #[test]
+    fn load_average_decays_toward_runnable_count() {
+        let mut scheduler = Scheduler::new();
+        add(&mut scheduler, 0, None);
+        add(&mut scheduler, 0, None);
+        let mut tf = TrapFrame::default();
+        assert_eq!(scheduler.switch_to(0, &mut tf), Some(1));
+
+        scheduler.sample_load();
+        assert_eq!(scheduler.load, 2000 / LOAD_SAMPLES);
+        for _ in 0..10 * LOAD_SAMPLES {
+            scheduler.sample_load();
         }
+        let load = scheduler.load;
+        assert!(load > 1900 && load <= 2000);
+
+        assert_eq!(scheduler.schedule_out(0, State::Blocked(0x1000), &tf), Some(()));
+        scheduler.sample_load();
+        assert!(scheduler.load < load);
+    }
+
+    #[test]
+    fn policy_can_be_replaced() {
+        let mut scheduler = Scheduler::with_policy(Box::new(RoundRobin));
+        add(&mut scheduler, 0, None);
+        add(&mut scheduler, 0, None);
+        scheduler.processes[1].nice = -5;
+
+        let old = scheduler.set_policy(Box::new(StaticPriority));
+        assert_eq!(format!("{:?}", old), "RoundRobin");
+        let mut tf = TrapFrame::default();
+        assert_eq!(scheduler.switch_to(0, &mut tf), Some(2));
     }
 }
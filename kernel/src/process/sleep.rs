@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+
+use process::Id;
+
+/// A process waiting for a deadline.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Sleeper {
+    /// The time, in microseconds of the system timer, to wake the process at.
+    deadline: u64,
+    pid: Id,
+}
+
+/// Sleeping processes, ordered by the time they are to be woken at.
+#[derive(Debug, Default)]
+pub struct SleepQueue {
+    /// Sorted by deadline. Sleepers with the same deadline are in the order
+    /// they were added in.
+    sleepers: VecDeque<Sleeper>,
+}
+
+impl SleepQueue {
+    /// Returns an empty queue.
+    pub fn new() -> SleepQueue {
+        SleepQueue::default()
+    }
+
+    /// Adds the process `pid`, to be woken at `deadline`.
+    pub fn insert(&mut self, deadline: u64, pid: Id) {
+        let index = self
+            .sleepers
+            .iter()
+            .position(|sleeper| sleeper.deadline > deadline)
+            .unwrap_or(self.sleepers.len());
+
+        self.sleepers.insert(index, Sleeper { deadline, pid });
+    }
+
+    /// Returns the earliest deadline in the queue.
+    pub fn next_deadline(&self) -> Option<u64> {
+        self.sleepers.front().map(|sleeper| sleeper.deadline)
+    }
+
+    /// Removes and returns the ID of a process whose deadline is at or before
+    /// `now`, earliest first.
+    pub fn pop_expired(&mut self, now: u64) -> Option<Id> {
+        if self.next_deadline()? > now {
+            return None;
+        }
+
+        self.sleepers.pop_front().map(|sleeper| sleeper.pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline_order() {
+        let mut queue = SleepQueue::new();
+        assert_eq!(queue.next_deadline(), None);
+
+        queue.insert(300, 1);
+        queue.insert(100, 2);
+        queue.insert(200, 3);
+        queue.insert(100, 4);
+        assert_eq!(queue.next_deadline(), Some(100));
+
+        assert_eq!(queue.pop_expired(99), None);
+        assert_eq!(queue.pop_expired(250), Some(2));
+        assert_eq!(queue.pop_expired(250), Some(4));
+        assert_eq!(queue.pop_expired(250), Some(3));
+        assert_eq!(queue.pop_expired(250), None);
+        assert_eq!(queue.next_deadline(), Some(300));
+    }
+}
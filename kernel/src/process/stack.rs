@@ -3,9 +3,9 @@ use std::ptr::Unique;
 
 use ALLOCATOR;
 use alloc::allocator::{Alloc, Layout};
-use vm::PhysicalAddr;
+use vm::{PhysicalAddr, PAGE_SIZE};
 
-/// A process stack. The default size is 1M1B with an alignment of 16 bytes.
+/// A process stack. The default size is 1MiB with an alignment of one page.
 pub struct Stack {
     ptr: Unique<[u8; Stack::SIZE]>
 }
@@ -14,8 +14,9 @@ impl Stack {
     /// The default stack size is 1MiB.
     pub const SIZE: usize = 1 << 20;
 
-    /// The default stack alignment is 16 bytes.
-    pub const ALIGN: usize = 16;
+    /// The default stack alignment is one page so that stacks can be mapped
+    /// into user address spaces.
+    pub const ALIGN: usize = PAGE_SIZE;
 
     /// The default layout for a stack.
     fn layout() -> Layout {
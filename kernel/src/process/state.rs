@@ -1,6 +1,6 @@
 use std::fmt;
 
-use process::Process;
+use process::{Id, Process};
 
 /// Type of a function used to determine if a process is ready to be scheduled
 /// again. The scheduler calls this function when it is the process's turn to
@@ -17,6 +17,18 @@ pub enum State {
     Waiting(EventPollFn),
     /// The process is currently running.
     Running,
+    /// The process is waiting for its child with the given ID, or for any
+    /// child if the ID is `0`, to exit.
+    WaitingForChild(Id),
+    /// The process is blocked on the given key until it is woken by
+    /// `GlobalScheduler::wake()`.
+    Blocked(u64),
+    /// The process called `sleep` at `since` and is woken by the scheduler at
+    /// `until`, both in microseconds of the system timer.
+    Sleeping { since: u64, until: u64 },
+    /// The process has exited with the given status and is waiting to be
+    /// reaped by its parent.
+    Zombie(u64),
 }
 
 impl fmt::Debug for State {
@@ -25,6 +37,12 @@ impl fmt::Debug for State {
             State::Ready => write!(f, "State::Ready"),
             State::Running => write!(f, "State::Running"),
             State::Waiting(_) => write!(f, "State::Waiting"),
+            State::WaitingForChild(id) => write!(f, "State::WaitingForChild({})", id),
+            State::Blocked(key) => write!(f, "State::Blocked({:#x})", key),
+            State::Sleeping { since, until } => {
+                write!(f, "State::Sleeping({}..{})", since, until)
+            }
+            State::Zombie(status) => write!(f, "State::Zombie({})", status),
         }
     }
 }
@@ -4,13 +4,18 @@ use fat32::traits::{
 };
 use fs::FileSystem;
 use stack_vec::StackVec;
+use std::cmp::max;
 use std::fmt;
 use std::io;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read};
 use std::path::{Component, Path, PathBuf};
 use std::str::from_utf8;
 use std::str::FromStr;
-use syscall;
+use pi::timer;
+use process::{pipe, Descriptor, LoadError, Process};
+use smp::NCORES;
+use syscall::{self, OsError, STDIN, STDOUT};
+use SCHEDULER;
 
 trait CanonicalJoin
 where
@@ -107,6 +112,11 @@ impl<'a> Shell<'a> {
         };
 
         let cmd_str = from_utf8(&mut line[..n]).map_err(|_| Error::InvalidUtf8)?;
+        if cmd_str.contains('|') {
+            self.pipeline(cmd_str)?;
+            return Ok(EvalStatus::Continue);
+        }
+
         let cmd = Command::parse(cmd_str, &mut args)?;
 
         if cmd.path() == "exit" {
@@ -126,6 +136,14 @@ impl<'a> Shell<'a> {
             "ls" => self.ls(args),
             "cat" => self.cat(args),
             "sleep" => self.sleep(args),
+            "touch" => self.touch(args),
+            "mkdir" => self.mkdir(args),
+            "rm" => self.rm(args),
+            "mv" => self.mv(args),
+            "run" => self.run(args),
+            "exec" => self.exec(args),
+            "sync" => self.sync(args),
+            "sched" => self.sched(args),
             path => Err(Error::UnknownCommand {
                 command: path.to_string(),
             }),
@@ -237,6 +255,188 @@ impl<'a> Shell<'a> {
         syscall::sleep(ms)?;
         Ok(())
     }
+
+    fn touch(&self, args: &[&str]) -> Result<(), Error> {
+        if args.len() == 0 {
+            return Err(Error::InvalidArgs {
+                message: "usage: touch <file> [file]..".into(),
+            });
+        }
+
+        for arg in args {
+            let path = self.cwd.canonical_join(&PathBuf::from(arg))?;
+            if self.fs.open(&path).is_err() {
+                self.fs.create_file(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn mkdir(&self, args: &[&str]) -> Result<(), Error> {
+        let usage_err = || {
+            Err(Error::InvalidArgs {
+                message: "usage: mkdir [-p] <directory>".into(),
+            })
+        };
+
+        let (path, parents) = match args.len() {
+            1 => (args[0], false),
+            2 => {
+                if args[0] != "-p" {
+                    return usage_err();
+                }
+                (args[1], true)
+            }
+            _ => return usage_err(),
+        };
+
+        let path = self.cwd.canonical_join(&PathBuf::from(path))?;
+        self.fs.create_dir(&path, parents)?;
+        Ok(())
+    }
+
+    fn rm(&self, args: &[&str]) -> Result<(), Error> {
+        let usage_err = || {
+            Err(Error::InvalidArgs {
+                message: "usage: rm [-r] <path>".into(),
+            })
+        };
+
+        let (path, children) = match args.len() {
+            1 => (args[0], false),
+            2 => {
+                if args[0] != "-r" {
+                    return usage_err();
+                }
+                (args[1], true)
+            }
+            _ => return usage_err(),
+        };
+
+        let path = self.cwd.canonical_join(&PathBuf::from(path))?;
+        self.fs.remove(&path, children)?;
+        Ok(())
+    }
+
+    fn mv(&self, args: &[&str]) -> Result<(), Error> {
+        if args.len() != 2 {
+            return Err(Error::InvalidArgs {
+                message: "usage: mv <from> <to>".into(),
+            });
+        }
+
+        let from = self.cwd.canonical_join(&PathBuf::from(args[0]))?;
+        let to = self.cwd.canonical_join(&PathBuf::from(args[1]))?;
+        self.fs.rename(&from, &to)?;
+        Ok(())
+    }
+
+    fn run(&self, args: &[&str]) -> Result<(), Error> {
+        if args.len() != 1 {
+            return Err(Error::InvalidArgs {
+                message: "usage: run <binary>".into(),
+            });
+        }
+
+        let path = self.cwd.canonical_join(&PathBuf::from(args[0]))?;
+        let mut image = Vec::new();
+        self.fs.open_file(&path)?.read_to_end(&mut image)?;
+
+        let process = Process::user(&image).ok_or(Error::OutOfMemory)?;
+        let pid = SCHEDULER.add(process).ok_or(Error::OutOfMemory)?;
+        kprintln!("[{}] {}", pid, path.display());
+        Ok(())
+    }
+
+    fn exec(&self, args: &[&str]) -> Result<(), Error> {
+        if args.len() == 0 {
+            return Err(Error::InvalidArgs {
+                message: "usage: exec <executable> [args]..".into(),
+            });
+        }
+
+        let path = self.cwd.canonical_join(&PathBuf::from(args[0]))?;
+        let process = Process::load(&path, args)?;
+        let pid = SCHEDULER.add(process).ok_or(Error::OutOfMemory)?;
+        kprintln!("[{}] {}", pid, path.display());
+        Ok(())
+    }
+
+    /// Starts each `|`-separated stage of `line` as a process whose standard
+    /// output is piped to the standard input of the next.
+    fn pipeline(&self, line: &str) -> Result<(), Error> {
+        let mut processes = Vec::new();
+        for stage in line.split('|') {
+            let args: Vec<&str> = stage.split_whitespace().collect();
+            if args.len() == 0 {
+                return Err(Error::InvalidArgs {
+                    message: "usage: <executable> [args].. | <executable> [args]..".into(),
+                });
+            }
+
+            let path = self.cwd.canonical_join(&PathBuf::from(args[0]))?;
+            processes.push((Process::load(&path, &args)?, path));
+        }
+
+        for i in 1..processes.len() {
+            let (reader, writer) = pipe();
+            processes[i - 1].0.files.set(STDOUT, Descriptor::PipeWrite(writer))?;
+            processes[i].0.files.set(STDIN, Descriptor::PipeRead(reader))?;
+        }
+
+        for (process, path) in processes {
+            let pid = SCHEDULER.add(process).ok_or(Error::OutOfMemory)?;
+            kprintln!("[{}] {}", pid, path.display());
+        }
+        Ok(())
+    }
+
+    fn sched(&self, args: &[&str]) -> Result<(), Error> {
+        let usage_err = || Error::InvalidArgs {
+            message: "usage: sched [quantum <microseconds>]".into(),
+        };
+
+        match args.len() {
+            0 => {}
+            2 if args[0] == "quantum" => {
+                let us = u64::from_str(args[1]).map_err(|_| usage_err())?;
+                SCHEDULER.set_quantum(us);
+            }
+            _ => return Err(usage_err()),
+        }
+
+        kprintln!("quantum: {}us", SCHEDULER.quantum());
+        let load = SCHEDULER.load();
+        kprintln!("load: {}.{:03}", load / 1000, load % 1000);
+        let uptime = timer::current_time();
+        for core in 0..NCORES {
+            let idle = SCHEDULER.idle_time(core);
+            kprintln!(
+                "core {}: idle {}ms ({}%)",
+                core,
+                idle / 1000,
+                idle * 100 / max(uptime, 1)
+            );
+        }
+        Ok(())
+    }
+
+    fn sync(&self, args: &[&str]) -> Result<(), Error> {
+        if args.len() != 0 {
+            return Err(Error::InvalidArgs {
+                message: "usage: sync".into(),
+            });
+        }
+
+        self.fs.sync()?;
+        let stats = self.fs.cache_stats();
+        kprintln!(
+            "cache: {} hits, {} misses, {} writebacks",
+            stats.hits, stats.misses, stats.writebacks
+        );
+        Ok(())
+    }
 }
 
 /// Error type for `Command` parse failures.
@@ -250,7 +450,9 @@ enum Error {
     InvalidUtf8,
     Io { error: io::Error },
     Path { path: PathBuf, message: String },
-    Syscall { error: syscall::Error },
+    Syscall { error: OsError },
+    OutOfMemory,
+    Load { error: LoadError },
 }
 
 impl From<io::Error> for Error {
@@ -259,8 +461,14 @@ impl From<io::Error> for Error {
     }
 }
 
-impl From<syscall::Error> for Error {
-    fn from(error: syscall::Error) -> Self {
+impl From<LoadError> for Error {
+    fn from(error: LoadError) -> Self {
+        Error::Load { error }
+    }
+}
+
+impl From<OsError> for Error {
+    fn from(error: OsError) -> Self {
         Error::Syscall { error }
     }
 }
@@ -281,6 +489,8 @@ impl fmt::Display for Error {
                 ref message,
             } => write!(f, "{}: {}", path.display(), message),
             &Syscall { ref error } => write!(f, "syscall: {:?}", error),
+            &OutOfMemory => write!(f, "out of memory"),
+            &Load { ref error } => write!(f, "exec: {:?}", error),
         }
     }
 }
@@ -302,8 +512,13 @@ impl BufferedIo {
 
         loop {
             {
+                // Block in the kernel until input arrives instead of
+                // spinning, so that the scheduler sees the shell as
+                // interactive and runs it ahead of CPU-bound processes.
+                let mut byte = [0u8];
+                syscall::read(syscall::STDIN, &mut byte)?;
+                let b = byte[0];
                 let mut console = CONSOLE.lock();
-                let b = console.read_byte();
                 match b {
                     b'\r' | b'\n' => {
                         console.write_byte(b'\r');
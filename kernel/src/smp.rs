@@ -0,0 +1,69 @@
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering, ATOMIC_BOOL_INIT};
+
+use aarch64::{self, cache};
+
+/// The number of cores on the Raspberry Pi 3.
+pub const NCORES: usize = 4;
+
+/// The size of each core's EL1 stack. Core `n`'s stack ends at
+/// `_start - n * KERN_STACK_SIZE`. This must match `KERN_STACK_SIZE` in
+/// `init.S`.
+pub const KERN_STACK_SIZE: usize = 0x10000;
+
+/// The address of the firmware's spin-table mailboxes. Until released, core
+/// `n` polls the 64-bit word at `SPIN_TABLE_BASE + 8 * n` and jumps to it once
+/// it is nonzero.
+const SPIN_TABLE_BASE: usize = 0xd8;
+
+/// Whether each core has finished setting itself up.
+static READY: [AtomicBool; NCORES] = [ATOMIC_BOOL_INIT; NCORES];
+
+extern "C" {
+    static _start: u8;
+    static _start_secondary: u8;
+}
+
+/// Returns the index of the core currently executing.
+#[inline(always)]
+pub fn core() -> usize {
+    unsafe { aarch64::affinity() }
+}
+
+/// Returns the address of the top of core `core`'s EL1 stack.
+pub fn stack_top(core: usize) -> usize {
+    unsafe { &_start as *const u8 as usize - core * KERN_STACK_SIZE }
+}
+
+/// Releases cores 1 through `NCORES - 1` by writing the address of
+/// `_start_secondary` to their spin-table mailboxes, then waits until each of
+/// them has called `set_ready()`.
+///
+/// The released cores run with their MMU and caches off until they call
+/// `VMM.setup()`, so the calling core's data cache is cleaned to main memory
+/// before they are woken. Until then they must not take any lock: exclusive
+/// accesses do not work on uncached memory, and the lock word would be
+/// accessed with attributes that differ from the other cores'.
+///
+/// The kernel page table and the scheduler must be initialized before this is
+/// called: the released cores use both right away.
+pub fn start_secondary_cores() {
+    let entry = unsafe { &_start_secondary as *const u8 as u64 };
+    for core in 1..NCORES {
+        let mailbox = (SPIN_TABLE_BASE + 8 * core) as *mut u64;
+        unsafe { ptr::write_volatile(mailbox, entry) };
+    }
+
+    cache::clean_invalidate_all();
+    aarch64::sev();
+    for core in 1..NCORES {
+        while !READY[core].load(Ordering::Acquire) {
+            aarch64::nop();
+        }
+    }
+}
+
+/// Marks the calling core as set up.
+pub fn set_ready() {
+    READY[core()].store(true, Ordering::Release);
+}
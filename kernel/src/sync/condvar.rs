@@ -0,0 +1,68 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Release};
+
+use sync::MutexGuard;
+use sync::futex;
+
+/// A condition variable for use with `sync::Mutex`.
+#[derive(Debug)]
+pub struct Condvar {
+    /// Incremented on every notification so that a notification between
+    /// unlocking the mutex and blocking is not lost.
+    seq: AtomicUsize,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar { seq: AtomicUsize::new(0) }
+    }
+
+    /// Unlocks `guard`'s mutex, blocks until the condition variable is
+    /// notified, and locks the mutex again. Like any condition variable it
+    /// may return spuriously, so callers should check their condition in a
+    /// loop.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Acquire);
+        drop(guard);
+
+        let _ = futex::wait(&self.seq, seq);
+        mutex.lock()
+    }
+
+    /// Wakes one process blocked in `wait()`.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Release);
+        let _ = futex::wake(&self.seq, 1);
+    }
+
+    /// Wakes every process blocked in `wait()`.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Release);
+        let _ = futex::wake(&self.seq, usize::max_value());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sync::Mutex;
+
+    #[test]
+    fn wait_unlocks_until_notified() {
+        static MUTEX: Mutex<bool> = Mutex::new(false);
+        static CONDVAR: Condvar = Condvar::new();
+        futex::while_blocked(|| {
+            *MUTEX.try_lock().expect("unlocked while waiting") = true;
+            CONDVAR.notify_one();
+        });
+
+        let mut guard = MUTEX.lock();
+        while !*guard {
+            guard = CONDVAR.wait(guard);
+        }
+
+        assert!(MUTEX.try_lock().is_none());
+        assert_eq!(futex::pending(), 0);
+    }
+}
@@ -0,0 +1,79 @@
+// Blocking synchronization for processes.
+//
+// Waiters block through the futex system calls, so these types can only be
+// used in process context: by user programs and by kernel processes such as
+// the shell. Exception handlers have no process of their own to block and
+// must wait with `GlobalScheduler::block_if()` and `wake()` instead.
+
+mod condvar;
+mod mutex;
+mod semaphore;
+mod wait_queue;
+
+pub use self::condvar::Condvar;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::semaphore::Semaphore;
+pub use self::wait_queue::WaitQueue;
+
+/// How waiters block and are woken.
+#[cfg(not(test))]
+mod futex {
+    pub use syscall::{futex_wait as wait, futex_wake as wake};
+}
+
+/// Host tests have no scheduler to block in. Instead, a test queues what the
+/// other processes do while the caller is blocked with `while_blocked()`, and
+/// `wait()` runs it. The caller must be woken by then.
+#[cfg(test)]
+mod futex {
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::SeqCst;
+
+    use syscall::OsError;
+
+    thread_local! {
+        static OTHERS: RefCell<VecDeque<Box<FnMut()>>> = RefCell::new(VecDeque::new());
+        static BLOCKED_ON: Cell<Option<usize>> = Cell::new(None);
+        static WOKEN: Cell<bool> = Cell::new(false);
+    }
+
+    /// Queues `others` to run the next time the caller blocks.
+    pub fn while_blocked<F: FnMut() + 'static>(others: F) {
+        OTHERS.with(|queue| queue.borrow_mut().push_back(Box::new(others)));
+    }
+
+    /// Returns the number of queued `while_blocked()` closures that have not
+    /// run.
+    pub fn pending() -> usize {
+        OTHERS.with(|queue| queue.borrow().len())
+    }
+
+    pub fn wait(word: &AtomicUsize, expected: usize) -> Result<(), OsError> {
+        if word.load(SeqCst) != expected {
+            return Err(OsError::WouldBlock);
+        }
+
+        let mut others = OTHERS
+            .with(|queue| queue.borrow_mut().pop_front())
+            .expect("blocked with no other process to wake it");
+
+        BLOCKED_ON.with(|blocked| blocked.set(Some(word as *const _ as usize)));
+        WOKEN.with(|woken| woken.set(false));
+        others();
+        BLOCKED_ON.with(|blocked| blocked.set(None));
+        assert!(WOKEN.with(|woken| woken.get()), "blocked caller was never woken");
+        Ok(())
+    }
+
+    pub fn wake(word: &AtomicUsize, count: usize) -> Result<usize, OsError> {
+        let blocked = BLOCKED_ON.with(|blocked| blocked.get());
+        if count == 0 || blocked != Some(word as *const _ as usize) || WOKEN.with(|w| w.get()) {
+            return Ok(0);
+        }
+
+        WOKEN.with(|woken| woken.set(true));
+        Ok(1)
+    }
+}
@@ -0,0 +1,133 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use sync::futex;
+
+const UNLOCKED: usize = 0;
+/// Locked, and no process is blocked on the lock.
+const LOCKED: usize = 1;
+/// Locked, and processes may be blocked on the lock.
+const CONTENDED: usize = 2;
+
+/// A mutual exclusion lock that blocks the calling process, rather than
+/// spinning, while the lock is held. Unlike `mutex::Mutex`, it can be held for
+/// long periods, such as across I/O, without wasting other processes' time.
+pub struct Mutex<T> {
+    state: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T: 'a> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> !Send for MutexGuard<'a, T> {}
+unsafe impl<'a, T: Sync> Sync for MutexGuard<'a, T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(val: T) -> Mutex<T> {
+        Mutex {
+            state: AtomicUsize::new(UNLOCKED),
+            data: UnsafeCell::new(val),
+        }
+    }
+
+    /// Acquires the lock if it is free. Returns `None` if it is held.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        match self.state.compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed) {
+            Ok(_) => Some(MutexGuard { mutex: self }),
+            Err(_) => None,
+        }
+    }
+
+    /// Acquires the lock, blocking until it is free.
+    pub fn lock(&self) -> MutexGuard<T> {
+        if let Some(guard) = self.try_lock() {
+            return guard;
+        }
+
+        // Mark the lock contended so that its holder wakes a waiter when it
+        // unlocks. A process that takes the lock this way cannot know whether
+        // others are still blocked, so it leaves the lock contended.
+        while self.state.swap(CONTENDED, Acquire) != UNLOCKED {
+            let _ = futex::wait(&self.state, CONTENDED);
+        }
+
+        MutexGuard { mutex: self }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Release) == CONTENDED {
+            let _ = futex::wake(&self.state, 1);
+        }
+    }
+}
+
+impl<'a, T: 'a> MutexGuard<'a, T> {
+    /// Returns the mutex this guard locks.
+    pub fn mutex(&self) -> &'a Mutex<T> {
+        self.mutex
+    }
+}
+
+impl<'a, T: 'a> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.debug_struct("Mutex").field("data", &"<locked>").finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_lock() {
+        let mutex = Mutex::new(1);
+        {
+            let mut guard = mutex.try_lock().expect("unlocked");
+            *guard += 1;
+            assert!(mutex.try_lock().is_none());
+        }
+
+        assert_eq!(*mutex.try_lock().expect("unlocked"), 2);
+    }
+    #[test]
+    fn lock_blocks_until_unlocked() {
+        static MUTEX: Mutex<usize> = Mutex::new(0);
+        let mut held = MUTEX.try_lock();
+        futex::while_blocked(move || drop(held.take()));
+
+        *MUTEX.lock() += 1;
+        assert_eq!(*MUTEX.try_lock().expect("unlocked"), 1);
+        assert_eq!(futex::pending(), 0);
+    }
+}
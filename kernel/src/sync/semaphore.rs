@@ -0,0 +1,97 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use sync::WaitQueue;
+
+/// A counting semaphore. `down()` blocks the calling process while the count
+/// is zero.
+#[derive(Debug)]
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+impl Semaphore {
+    /// Returns a semaphore with an initial count of `count`.
+    pub const fn new(count: usize) -> Semaphore {
+        Semaphore {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Decrements the count if it is not zero. Returns `true` if it was
+    /// decremented.
+    pub fn try_down(&self) -> bool {
+        let mut count = self.count.load(Relaxed);
+        while count > 0 {
+            match self.count.compare_exchange_weak(count, count - 1, Acquire, Relaxed) {
+                Ok(_) => return true,
+                Err(current) => count = current,
+            }
+        }
+
+        false
+    }
+
+    /// Decrements the count, blocking until it is not zero.
+    pub fn down(&self) {
+        self.waiters.wait_until(|| self.try_down());
+    }
+
+    /// Increments the count and wakes a blocked `down()`.
+    pub fn up(&self) {
+        self.count.fetch_add(1, Release);
+        self.waiters.notify_one();
+    }
+
+    /// Returns the current count.
+    pub fn count(&self) -> usize {
+        self.count.load(Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sync::futex;
+
+    #[test]
+    fn try_down() {
+        let semaphore = Semaphore::new(2);
+        assert!(semaphore.try_down());
+        assert!(semaphore.try_down());
+        assert!(!semaphore.try_down());
+        assert_eq!(semaphore.count(), 0);
+    }
+    #[test]
+    fn up_before_down_is_not_lost() {
+        let semaphore = Semaphore::new(0);
+        semaphore.up();
+        semaphore.down();
+        assert_eq!(semaphore.count(), 0);
+    }
+
+    #[test]
+    fn down_blocks_until_up() {
+        static SEMAPHORE: Semaphore = Semaphore::new(0);
+        futex::while_blocked(|| SEMAPHORE.up());
+        SEMAPHORE.down();
+        assert_eq!(SEMAPHORE.count(), 0);
+        assert_eq!(futex::pending(), 0);
+    }
+
+    #[test]
+    fn woken_down_blocks_again_if_count_was_taken() {
+        static SEMAPHORE: Semaphore = Semaphore::new(0);
+        futex::while_blocked(|| {
+            SEMAPHORE.up();
+            assert!(SEMAPHORE.try_down());
+        });
+        futex::while_blocked(|| SEMAPHORE.up());
+
+        SEMAPHORE.down();
+        assert_eq!(SEMAPHORE.count(), 0);
+        assert_eq!(futex::pending(), 0);
+    }
+}
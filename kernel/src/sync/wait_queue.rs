@@ -0,0 +1,96 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Release};
+
+use sync::futex;
+
+/// A queue of processes waiting for a condition to become true.
+///
+/// Waiting processes are blocked by the scheduler, through the futex system
+/// calls, until the queue is notified. Like everything in `sync`, a
+/// `WaitQueue` can only be used by processes, not by exception handlers or
+/// before the scheduler has started.
+#[derive(Debug)]
+pub struct WaitQueue {
+    /// Incremented on every notification. Waiters block on it so that a
+    /// notification between checking the condition and blocking is not lost.
+    seq: AtomicUsize,
+}
+
+impl WaitQueue {
+    /// Returns an empty `WaitQueue`.
+    pub const fn new() -> WaitQueue {
+        WaitQueue { seq: AtomicUsize::new(0) }
+    }
+
+    /// Blocks until `condition` returns `true`. The condition is checked
+    /// before blocking and again after every notification.
+    pub fn wait_until<F: FnMut() -> bool>(&self, mut condition: F) {
+        loop {
+            let seq = self.seq.load(Acquire);
+            if condition() {
+                return;
+            }
+
+            // Fails right away if the queue was notified since `seq` was read.
+            let _ = futex::wait(&self.seq, seq);
+        }
+    }
+
+    /// Wakes the process that has waited the longest. Returns the number of
+    /// processes woken.
+    pub fn notify_one(&self) -> usize {
+        self.notify(1)
+    }
+
+    /// Wakes every waiting process. Returns the number of processes woken.
+    pub fn notify_all(&self) -> usize {
+        self.notify(usize::max_value())
+    }
+
+    fn notify(&self, count: usize) -> usize {
+        self.seq.fetch_add(1, Release);
+        futex::wake(&self.seq, count).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, ATOMIC_BOOL_INIT};
+    use std::sync::atomic::Ordering::SeqCst;
+
+    #[test]
+    fn wait_until_does_not_block_if_condition_holds() {
+        let queue = WaitQueue::new();
+        queue.wait_until(|| true);
+        assert_eq!(queue.notify_one(), 0);
+    }
+
+    #[test]
+    fn notify_wakes_blocked_waiter() {
+        static QUEUE: WaitQueue = WaitQueue::new();
+        static READY: AtomicBool = ATOMIC_BOOL_INIT;
+        futex::while_blocked(|| {
+            READY.store(true, SeqCst);
+            assert_eq!(QUEUE.notify_all(), 1);
+        });
+
+        QUEUE.wait_until(|| READY.load(SeqCst));
+        assert_eq!(futex::pending(), 0);
+    }
+
+    #[test]
+    fn notify_between_check_and_block_is_not_lost() {
+        let queue = WaitQueue::new();
+        let mut checks = 0;
+        queue.wait_until(|| {
+            checks += 1;
+            if checks == 1 {
+                queue.notify_one();
+            }
+            checks > 1
+        });
+
+        assert_eq!(checks, 2);
+    }
+}
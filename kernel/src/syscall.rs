@@ -1,27 +1,398 @@
-#[derive(Debug)]
-pub struct Error {}
+// System call ABI.
+//
+// The system call number is the immediate of the `svc` instruction. Up to six
+// arguments are passed in `x0`-`x5`. On return, `x7` holds `0` on success or
+// an `OsError` code on failure, and on success `x0` holds the result. All
+// other registers are preserved.
 
-impl From<u64> for Error {
+use std::io;
+use std::sync::atomic::AtomicUsize;
+
+/// `sleep(ms) -> elapsed_ms`
+pub const SYS_SLEEP: u16 = 1;
+/// `open(path, path_len, flags) -> fd`
+pub const SYS_OPEN: u16 = 2;
+/// `read(fd, buf, len) -> bytes_read`
+pub const SYS_READ: u16 = 3;
+/// `write(fd, buf, len) -> bytes_written`
+pub const SYS_WRITE: u16 = 4;
+/// `close(fd) -> 0`
+pub const SYS_CLOSE: u16 = 5;
+/// `lseek(fd, offset, whence) -> new_offset`
+pub const SYS_LSEEK: u16 = 6;
+/// `fstat(fd, stat: *mut Stat) -> 0`
+pub const SYS_FSTAT: u16 = 7;
+/// `getdents(fd, buf: *mut Dirent, count) -> entries_read`
+pub const SYS_GETDENTS: u16 = 8;
+/// `exit(status) -> !`
+pub const SYS_EXIT: u16 = 9;
+/// `getpid() -> pid`
+pub const SYS_GETPID: u16 = 10;
+/// `wait(pid, status: *mut u64) -> pid`
+pub const SYS_WAIT: u16 = 11;
+/// `spawn(path, path_len, args, args_len) -> pid`
+pub const SYS_SPAWN: u16 = 12;
+/// `setpriority(pid, nice) -> 0`
+pub const SYS_SETPRIORITY: u16 = 13;
+/// `futex_wait(word: *const u64, expected) -> 0`
+pub const SYS_FUTEX_WAIT: u16 = 14;
+/// `futex_wake(word: *const u64, count) -> processes_woken`
+pub const SYS_FUTEX_WAKE: u16 = 15;
+/// `pipe(fds: *mut [u64; 2]) -> 0`
+pub const SYS_PIPE: u16 = 16;
+/// `send(pid, buf, len) -> 0`
+pub const SYS_SEND: u16 = 17;
+/// `recv(buf, len, from: *mut u64) -> message_len`
+pub const SYS_RECV: u16 = 18;
+/// `gpio_wait(pin) -> 0`
+pub const SYS_GPIO_WAIT: u16 = 19;
+
+/// The file descriptor of the console that processes start with as their
+/// standard input.
+pub const STDIN: u64 = 0;
+/// The file descriptor of the console that processes start with as their
+/// standard output.
+pub const STDOUT: u64 = 1;
+/// The file descriptor of the console that processes start with as their
+/// standard error.
+pub const STDERR: u64 = 2;
+
+/// `open` flag: create the file if it does not exist.
+pub const O_CREATE: u64 = 1 << 0;
+
+/// `lseek` whence: the offset is relative to the start of the file.
+pub const SEEK_SET: u64 = 0;
+/// `lseek` whence: the offset is relative to the current position.
+pub const SEEK_CUR: u64 = 1;
+/// `lseek` whence: the offset is relative to the end of the file.
+pub const SEEK_END: u64 = 2;
+
+/// `wait` pid: wait for any child.
+pub const WAIT_ANY: u64 = 0;
+
+/// `setpriority` pid: the calling process.
+pub const PID_SELF: u64 = 0;
+
+/// The exit status of a process the kernel terminated for a data or
+/// instruction abort.
+pub const EXIT_FAULT: u64 = 139;
+
+/// `Stat::kind` of a regular file.
+pub const KIND_FILE: u64 = 1;
+/// `Stat::kind` of a directory.
+pub const KIND_DIR: u64 = 2;
+/// `Stat::kind` of a character device such as the console.
+pub const KIND_CHAR: u64 = 3;
+/// `Stat::kind` of a pipe.
+pub const KIND_FIFO: u64 = 4;
+
+/// Status of an open file, as returned by `fstat`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Stat {
+    /// One of the `KIND_*` constants.
+    pub kind: u64,
+    /// The size of the file in bytes. Zero for anything but regular files.
+    pub size: u64,
+}
+
+/// The maximum length of a name in a `Dirent`.
+pub const NAME_MAX: usize = 255;
+
+/// A directory entry, as returned by `getdents`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Dirent {
+    /// One of the `KIND_*` constants.
+    pub kind: u64,
+    /// The size of the entry in bytes.
+    pub size: u64,
+    /// The length of `name`.
+    pub name_len: u64,
+    /// The UTF-8 name of the entry. Only the first `name_len` bytes are valid.
+    pub name: [u8; NAME_MAX],
+}
+
+impl Dirent {
+    /// Returns an empty `Dirent`.
+    pub fn empty() -> Dirent {
+        Dirent {
+            kind: 0,
+            size: 0,
+            name_len: 0,
+            name: [0; NAME_MAX],
+        }
+    }
+
+    /// Returns the name of the entry.
+    pub fn name(&self) -> &str {
+        let len = ::std::cmp::min(self.name_len as usize, NAME_MAX);
+        ::std::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+/// Error codes returned by system calls in `x7`.
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OsError {
+    /// An error without a more specific code.
+    Unknown = 1,
+    /// There is no system call with the requested number (`ENOSYS`).
+    NoSuchSyscall = 2,
+    /// An argument is out of range for the system call (`EINVAL`).
+    InvalidArgument = 3,
+    /// A pointer argument does not point to accessible memory (`EFAULT`).
+    BadAddress = 4,
+    /// The file descriptor is not open (`EBADF`).
+    BadFileDescriptor = 5,
+    /// The process has too many open files (`EMFILE`).
+    TooManyFiles = 6,
+    /// No file or directory exists at the path (`ENOENT`).
+    NoEntry = 7,
+    /// A file or directory already exists at the path (`EEXIST`).
+    AlreadyExists = 8,
+    /// The operation requires a file but got a directory (`EISDIR`).
+    IsDirectory = 9,
+    /// The operation requires a directory but got a file (`ENOTDIR`).
+    NotDirectory = 10,
+    /// The file cannot be seeked (`ESPIPE`).
+    IllegalSeek = 11,
+    /// The file system or device failed (`EIO`).
+    Io = 12,
+    /// The process has no child matching the request (`ECHILD`).
+    NoChild = 13,
+    /// The file is not an executable this machine can run (`ENOEXEC`).
+    ExecFormat = 14,
+    /// The arguments are too large (`E2BIG`).
+    ArgumentsTooLong = 15,
+    /// Memory could not be allocated (`ENOMEM`).
+    OutOfMemory = 16,
+    /// No process has the requested ID (`ESRCH`).
+    NoSuchProcess = 17,
+    /// The process may not perform the operation (`EPERM`).
+    PermissionDenied = 18,
+    /// The operation would block but the condition it waits for already
+    /// changed (`EAGAIN`).
+    WouldBlock = 19,
+    /// The read end of the pipe is closed (`EPIPE`).
+    BrokenPipe = 20,
+    /// The device is in use by a driver (`EBUSY`).
+    Busy = 21,
+}
+
+impl OsError {
+    /// Returns `Ok(value)` if `error` is `0` and the corresponding error
+    /// otherwise.
+    pub fn into_result(value: u64, error: u64) -> Result<u64, OsError> {
+        match error {
+            0 => Ok(value),
+            code => Err(OsError::from(code)),
+        }
+    }
+}
+
+impl From<u64> for OsError {
     fn from(code: u64) -> Self {
-        Error {}
+        use self::OsError::*;
+        match code {
+            2 => NoSuchSyscall,
+            3 => InvalidArgument,
+            4 => BadAddress,
+            5 => BadFileDescriptor,
+            6 => TooManyFiles,
+            7 => NoEntry,
+            8 => AlreadyExists,
+            9 => IsDirectory,
+            10 => NotDirectory,
+            11 => IllegalSeek,
+            12 => Io,
+            13 => NoChild,
+            14 => ExecFormat,
+            15 => ArgumentsTooLong,
+            16 => OutOfMemory,
+            17 => NoSuchProcess,
+            18 => PermissionDenied,
+            19 => WouldBlock,
+            20 => BrokenPipe,
+            21 => Busy,
+            _ => Unknown,
+        }
     }
 }
 
-pub fn sleep(ms: u32) -> Result<u32, Error> {
-    let error: u64;
-    let elapsed: u64;
-    unsafe {
-        asm!("mov x0, $2
-              svc 1
-              mov $0, x0
-              mov $1, x7"
-             : "=r"(elapsed), "=r"(error)
-             : "r"(ms)
-             : "x0", "x7")
+impl From<io::Error> for OsError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => OsError::NoEntry,
+            io::ErrorKind::AlreadyExists => OsError::AlreadyExists,
+            io::ErrorKind::InvalidInput => OsError::InvalidArgument,
+            _ => OsError::Io,
+        }
     }
-    if error == 0 {
-        Ok(elapsed as u32)
-    } else {
-        Err(Error::from(error))
+}
+
+/// Performs system call `$nr` with up to six arguments and returns a
+/// `Result<u64, OsError>`.
+macro_rules! syscall {
+    ($nr:expr) => (syscall!($nr, 0, 0, 0, 0, 0, 0));
+    ($nr:expr, $a0:expr) => (syscall!($nr, $a0, 0, 0, 0, 0, 0));
+    ($nr:expr, $a0:expr, $a1:expr) => (syscall!($nr, $a0, $a1, 0, 0, 0, 0));
+    ($nr:expr, $a0:expr, $a1:expr, $a2:expr) => (syscall!($nr, $a0, $a1, $a2, 0, 0, 0));
+    ($nr:expr, $a0:expr, $a1:expr, $a2:expr, $a3:expr) => {
+        syscall!($nr, $a0, $a1, $a2, $a3, 0, 0)
+    };
+    ($nr:expr, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr) => {
+        syscall!($nr, $a0, $a1, $a2, $a3, $a4, 0)
+    };
+    ($nr:expr, $a0:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr, $a5:expr) => {{
+        let value: u64;
+        let error: u64;
+        unsafe {
+            asm!("svc $2"
+                 : "={x0}"(value), "={x7}"(error)
+                 : "i"($nr),
+                   "{x0}"($a0 as u64), "{x1}"($a1 as u64), "{x2}"($a2 as u64),
+                   "{x3}"($a3 as u64), "{x4}"($a4 as u64), "{x5}"($a5 as u64)
+                 : "memory"
+                 : "volatile");
+        }
+
+        OsError::into_result(value, error)
+    }};
+}
+
+/// Sleeps for `ms` milliseconds and returns the approximate number of
+/// milliseconds that actually elapsed.
+pub fn sleep(ms: u32) -> Result<u32, OsError> {
+    syscall!(SYS_SLEEP, ms).map(|elapsed| elapsed as u32)
+}
+
+/// Opens the file or directory at the absolute path `path` and returns a new
+/// file descriptor for it. With `O_CREATE` in `flags`, a missing file is
+/// created.
+pub fn open(path: &str, flags: u64) -> Result<u64, OsError> {
+    syscall!(SYS_OPEN, path.as_ptr(), path.len(), flags)
+}
+
+/// Reads up to `buf.len()` bytes from `fd` into `buf`, blocking until at least
+/// one byte is available on the console. Returns the number of bytes read.
+pub fn read(fd: u64, buf: &mut [u8]) -> Result<usize, OsError> {
+    syscall!(SYS_READ, fd, buf.as_mut_ptr(), buf.len()).map(|n| n as usize)
+}
+
+/// Writes up to `buf.len()` bytes from `buf` to `fd`. Returns the number of
+/// bytes written.
+pub fn write(fd: u64, buf: &[u8]) -> Result<usize, OsError> {
+    syscall!(SYS_WRITE, fd, buf.as_ptr(), buf.len()).map(|n| n as usize)
+}
+
+/// Closes `fd`.
+pub fn close(fd: u64) -> Result<(), OsError> {
+    syscall!(SYS_CLOSE, fd).map(|_| ())
+}
+
+/// Moves the position of `fd` to `offset` relative to `whence`, one of the
+/// `SEEK_*` constants. Returns the new position.
+pub fn lseek(fd: u64, offset: i64, whence: u64) -> Result<u64, OsError> {
+    syscall!(SYS_LSEEK, fd, offset, whence)
+}
+
+/// Returns the status of `fd`.
+pub fn fstat(fd: u64) -> Result<Stat, OsError> {
+    let mut stat = Stat::default();
+    syscall!(SYS_FSTAT, fd, &mut stat as *mut Stat).map(|_| stat)
+}
+
+/// Reads the next entries of the directory `fd` into `entries`. Returns the
+/// number of entries read, which is `0` at the end of the directory.
+pub fn getdents(fd: u64, entries: &mut [Dirent]) -> Result<usize, OsError> {
+    syscall!(SYS_GETDENTS, fd, entries.as_mut_ptr(), entries.len()).map(|n| n as usize)
+}
+
+/// Terminates the calling process with exit status `status`. Open files are
+/// closed, and the status is kept for the parent to collect with `wait`.
+pub fn exit(status: u64) -> ! {
+    let _ = syscall!(SYS_EXIT, status);
+    unreachable!("exit returned");
+}
+
+/// Returns the ID of the calling process.
+pub fn getpid() -> Result<u64, OsError> {
+    syscall!(SYS_GETPID)
+}
+
+/// Waits for the child `pid`, or for any child if `pid` is `WAIT_ANY`, to
+/// exit and reaps it. Returns the child's ID and exit status.
+pub fn wait(pid: u64) -> Result<(u64, u64), OsError> {
+    let mut status = 0u64;
+    syscall!(SYS_WAIT, pid, &mut status as *mut u64).map(|pid| (pid, status))
+}
+
+/// Starts the program at the absolute path `path` as a child of the calling
+/// process, with arguments `args`. Returns the child's ID.
+///
+/// The arguments are passed to the kernel as one buffer with each argument
+/// followed by a NUL byte, so they cannot contain NUL bytes themselves.
+pub fn spawn(path: &str, args: &[&str]) -> Result<u64, OsError> {
+    let mut buf = Vec::new();
+    for arg in args {
+        if arg.as_bytes().contains(&0) {
+            return Err(OsError::InvalidArgument);
+        }
+
+        buf.extend_from_slice(arg.as_bytes());
+        buf.push(0);
     }
+
+    syscall!(SYS_SPAWN, path.as_ptr(), path.len(), buf.as_ptr(), buf.len())
+}
+
+/// Sets the static priority of the calling process if `pid` is `PID_SELF`,
+/// or of its child `pid` otherwise, to `nice`. Lower values run first; the
+/// valid range is `-20` to `19`.
+pub fn setpriority(pid: u64, nice: i64) -> Result<(), OsError> {
+    syscall!(SYS_SETPRIORITY, pid, nice).map(|_| ())
+}
+
+/// Blocks the calling process on `word` if it holds `expected`, until another
+/// process calls `futex_wake` on it. Fails with `OsError::WouldBlock` if
+/// `word` does not hold `expected`. The check and the blocking are atomic with
+/// respect to `futex_wake`, so a wakeup that follows a change to `word` is
+/// never missed. Returns spuriously if the process is woken for another reason.
+pub fn futex_wait(word: &AtomicUsize, expected: usize) -> Result<(), OsError> {
+    syscall!(SYS_FUTEX_WAIT, word as *const AtomicUsize, expected).map(|_| ())
+}
+
+/// Wakes up to `count` processes blocked on `word` by `futex_wait`. Returns
+/// the number of processes woken.
+pub fn futex_wake(word: &AtomicUsize, count: usize) -> Result<usize, OsError> {
+    syscall!(SYS_FUTEX_WAKE, word as *const AtomicUsize, count).map(|n| n as usize)
+}
+
+/// Creates a pipe. Returns the file descriptors of its read and write ends.
+pub fn pipe() -> Result<(u64, u64), OsError> {
+    let mut fds = [0u64; 2];
+    syscall!(SYS_PIPE, fds.as_mut_ptr()).map(|_| (fds[0], fds[1]))
+}
+
+/// Sends `data`, at most 256 bytes, to the mailbox of process `pid` without
+/// blocking. Fails with `OsError::WouldBlock` if the mailbox is full.
+pub fn send(pid: u64, data: &[u8]) -> Result<(), OsError> {
+    syscall!(SYS_SEND, pid, data.as_ptr(), data.len()).map(|_| ())
+}
+
+/// Receives the oldest message in the calling process's mailbox into `buf`,
+/// blocking until there is one. Returns the sender's ID and the length of the
+/// message, which is truncated if it is longer than `buf`.
+pub fn recv(buf: &mut [u8]) -> Result<(u64, usize), OsError> {
+    let mut from = 0u64;
+    syscall!(SYS_RECV, buf.as_mut_ptr(), buf.len(), &mut from as *mut u64)
+        .map(|len| (from, len as usize))
+}
+
+/// Blocks the calling process until the next rising or falling edge on GPIO
+/// pin `pin`. The first wait on a pin makes it an input and reserves it for
+/// waiting from then on. Fails with `OsError::Busy` if a driver uses the pin.
+pub fn gpio_wait(pin: u8) -> Result<(), OsError> {
+    syscall!(SYS_GPIO_WAIT, pin).map(|_| ())
 }
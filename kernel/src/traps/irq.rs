@@ -1,16 +1,127 @@
-use pi::interrupt::Interrupt;
+use pi::generic_timer::Timer;
+use pi::interrupt::{Controller, Interrupt, MAX_INTERRUPTS};
+use pi::local_interrupt::{LocalController, LocalInterrupt, MAX_LOCAL_INTERRUPTS};
 
-use process::{State, TICK};
-use timer::tick_in;
+use mutex::IrqSafeMutex;
+use smp;
 use traps::TrapFrame;
-use SCHEDULER;
+use IRQ;
 
-pub fn handle_irq(interrupt: Interrupt, tf: &mut TrapFrame) {
-    match interrupt {
-        Interrupt::Timer1 => {
-            SCHEDULER.switch(State::Ready, tf);
-            tick_in(TICK)
+/// An interrupt handler. It is called with the trap frame of the interrupted
+/// context, which it may switch to another process, and must not block.
+pub type IrqHandler = fn(tf: &mut TrapFrame);
+
+/// The kernel's interrupt handlers, indexed by interrupt source. Drivers
+/// register a handler for their device's interrupt instead of being wired
+/// into the exception handler.
+pub struct Irq(IrqSafeMutex<Handlers>);
+
+struct Handlers {
+    shared: [Option<IrqHandler>; MAX_INTERRUPTS],
+    local: [Option<IrqHandler>; MAX_LOCAL_INTERRUPTS],
+}
+
+impl Irq {
+    /// Returns a table with no handlers registered.
+    pub const fn new() -> Irq {
+        Irq(IrqSafeMutex::new(Handlers {
+            shared: [None; MAX_INTERRUPTS],
+            local: [None; MAX_LOCAL_INTERRUPTS],
+        }))
+    }
+
+    /// Registers `handler` for `int`, replacing any handler registered
+    /// before, and enables `int`.
+    pub fn register(&self, int: Interrupt, handler: IrqHandler) {
+        self.0.lock().shared[int as usize] = Some(handler);
+        Controller::new().enable(int);
+    }
+
+    /// Registers `handler` for `timer`'s interrupt, replacing any handler
+    /// registered before, and routes the interrupt to the calling core. Each
+    /// core has its own timer, so each core that uses it registers it; the
+    /// handler is shared and runs on the core whose timer fired.
+    pub fn register_timer(&self, timer: Timer, handler: IrqHandler) {
+        self.0.lock().local[LocalInterrupt::for_timer(timer) as usize] = Some(handler);
+        LocalController::new(smp::core()).enable_timer(timer);
+    }
+
+    /// Registers `handler` for `int` and delivers `int` as an FIQ. Only one
+    /// interrupt can be delivered as an FIQ; registering another replaces it.
+    pub fn register_fiq(&self, int: Interrupt, handler: IrqHandler) {
+        self.0.lock().shared[int as usize] = Some(handler);
+        let mut controller = Controller::new();
+        controller.disable(int);
+        controller.enable_fiq(int);
+    }
+
+    /// Disables `int`, as an IRQ or an FIQ, and removes its handler.
+    pub fn unregister(&self, int: Interrupt) {
+        let mut controller = Controller::new();
+        if controller.fiq() == Some(int) {
+            controller.disable_fiq();
         }
-        _ => panic!("unexpected interrupt: {:?}", interrupt),
+        controller.disable(int);
+        self.0.lock().shared[int as usize] = None;
+    }
+
+    /// Calls the handler registered for `int` with `tf`. The handler is
+    /// called without the table locked so that it may register handlers
+    /// itself. Returns `false` if no handler is registered.
+    pub fn invoke(&self, int: Interrupt, tf: &mut TrapFrame) -> bool {
+        let handler = self.0.lock().shared[int as usize];
+        call(handler, tf)
+    }
+
+    /// Like `invoke()`, but for the calling core's local interrupt `int`.
+    pub fn invoke_local(&self, int: LocalInterrupt, tf: &mut TrapFrame) -> bool {
+        let handler = self.0.lock().local[int as usize];
+        call(handler, tf)
+    }
+}
+
+fn call(handler: Option<IrqHandler>, tf: &mut TrapFrame) -> bool {
+    match handler {
+        Some(handler) => {
+            handler(tf);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Handles an IRQ by calling the handler of every interrupt pending on the
+/// calling core: its local interrupts, and, on the core the GPU interrupt
+/// controller is routed to, the pending shared interrupts.
+///
+/// # Panics
+///
+/// Panics if an interrupt without a handler is pending.
+pub fn handle_irq(tf: &mut TrapFrame) {
+    for local in LocalController::new(smp::core()).pending() {
+        if local != LocalInterrupt::Gpu {
+            if !IRQ.invoke_local(local, tf) {
+                panic!("unexpected local interrupt: {:?}", local);
+            }
+            continue;
+        }
+
+        for int in Controller::new().pending() {
+            if !IRQ.invoke(int, tf) {
+                panic!("unexpected interrupt: {:?}", int);
+            }
+        }
+    }
+}
+
+/// Handles an FIQ by calling the handler of the interrupt delivered as one.
+///
+/// # Panics
+///
+/// Panics if no interrupt with a handler is delivered as an FIQ.
+pub fn handle_fiq(tf: &mut TrapFrame) {
+    match Controller::new().fiq() {
+        Some(int) if IRQ.invoke(int, tf) => {}
+        int => panic!("unexpected FIQ: {:?}", int),
     }
 }
@@ -3,16 +3,18 @@ mod syndrome;
 mod syscall;
 mod trap_frame;
 
-use pi::interrupt::{Controller, Interrupt};
 use shell;
 
+pub use self::irq::{Irq, IrqHandler};
+pub use self::syscall::set_result;
 pub use self::trap_frame::TrapFrame;
 
-use self::irq::handle_irq;
+use self::irq::{handle_fiq, handle_irq};
 use self::syndrome::Syndrome;
-use self::syscall::handle_syscall;
+use self::syscall::{handle_syscall, terminate};
 use aarch64;
-use console::_print;
+use syscall::EXIT_FAULT;
+use SCHEDULER;
 
 #[repr(u16)]
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
@@ -45,26 +47,37 @@ pub struct Info {
 /// the trap frame for the exception.
 #[no_mangle]
 pub extern "C" fn handle_exception(info: Info, esr: u32, tf: &mut TrapFrame) {
-    kprintln!("info: {:?}", info);
-    kprintln!("esr: {:x}", esr);
-    kprintln!("tf: {:#?}", tf);
-
     match (info.kind, Syndrome::from(esr)) {
         (Kind::Synchronous, Syndrome::Brk(x)) => {
             shell::shell(&::FILE_SYSTEM, "?");
             tf.elr += 4;
         }
         (Kind::Synchronous, Syndrome::Svc(x)) => {
-            let elapsed = handle_syscall(x, tf);
+            handle_syscall(x, tf);
         }
-        (Kind::Irq, _) => {
-            let int = if Controller::new().is_pending(Interrupt::Timer1) {
-                Interrupt::Timer1
-            } else {
-                panic!("unexpected interrupt");
-            };
-            handle_irq(int, tf);
+        (Kind::Synchronous, syndrome @ Syndrome::DataAbort { .. })
+        | (Kind::Synchronous, syndrome @ Syndrome::InstructionAbort { .. })
+            if info.source == Source::LowerAArch64 =>
+        {
+            kill_faulting(syndrome, tf);
         }
+        (Kind::Irq, _) => handle_irq(tf),
+        (Kind::Fiq, _) => handle_fiq(tf),
         (_, syndrome) => panic!("unexpected syndrome: {:?}", syndrome),
     }
 }
+
+/// Terminates the calling core's current process, which caused the abort
+/// described by `syndrome`, with exit status `EXIT_FAULT`. Its open files are
+/// closed as if it had exited itself.
+fn kill_faulting(syndrome: Syndrome, tf: &mut TrapFrame) {
+    let pid = SCHEDULER.with_current(|process| process.pid()).unwrap_or_default();
+    kprintln!(
+        "process {} killed: {:?} at {:#x}, address {:#x}",
+        pid,
+        syndrome,
+        tf.elr,
+        aarch64::far()
+    );
+    terminate(EXIT_FAULT, tf);
+}
@@ -0,0 +1,328 @@
+use std::cmp::min;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::path::Path;
+use std::sync::Arc;
+use std::{ptr, str};
+
+use console::{self, CONSOLE};
+use fat32::traits::{Dir as DirTrait, Entry as EntryTrait, File as FileTrait};
+use fat32::traits::FileSystem as FileSystemTrait;
+use process::{self, Descriptor, Pipe, Process, SharedDescriptor};
+use syscall::{Dirent, OsError, Stat, NAME_MAX, O_CREATE, SEEK_CUR, SEEK_END, SEEK_SET};
+use syscall::{KIND_CHAR, KIND_DIR, KIND_FIFO, KIND_FILE};
+use traps::TrapFrame;
+use super::{check_access, set_result, user_slice, user_slice_mut};
+use {FILE_SYSTEM, SCHEDULER};
+
+/// Returns the calling process's descriptor open as `fd`.
+fn descriptor(fd: u64) -> Result<SharedDescriptor, OsError> {
+    SCHEDULER
+        .with_current(|process| process.files.get(fd))
+        .unwrap_or(Err(OsError::BadFileDescriptor))
+}
+
+fn open(path: u64, path_len: u64, flags: u64) -> Result<u64, OsError> {
+    let path = str::from_utf8(user_slice(path, path_len)?).map_err(|_| OsError::InvalidArgument)?;
+    let path = Path::new(path);
+    if !path.is_absolute() {
+        return Err(OsError::InvalidArgument);
+    }
+
+    // Entries share the file system through reference counts that are not
+    // atomic, so they are only dropped with the file system locked.
+    let descriptor = match (&FILE_SYSTEM).open(path) {
+        Ok(entry) => FILE_SYSTEM.locked(move || -> io::Result<Descriptor> {
+            if entry.is_dir() {
+                let entries = entry.as_dir().unwrap().entries()?.collect();
+                Ok(Descriptor::Dir { entries, next: 0 })
+            } else {
+                Ok(Descriptor::File(entry.into_file().unwrap()))
+            }
+        })?,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound && flags & O_CREATE != 0 => {
+            Descriptor::File((&FILE_SYSTEM).create_file(path)?)
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    // A descriptor that does not fit in the table is dropped below rather
+    // than by the table with the scheduler locked.
+    let mut descriptor = Some(descriptor);
+    let fd = SCHEDULER
+        .with_current(|process| {
+            if process.files.available() == 0 {
+                return Err(OsError::TooManyFiles);
+            }
+
+            process.files.insert(descriptor.take().expect("descriptor"))
+        })
+        .unwrap_or(Err(OsError::Unknown));
+
+    if let Some(descriptor) = descriptor {
+        FILE_SYSTEM.locked(move || drop(descriptor));
+    }
+    fd
+}
+
+/// The outcome of a read or write.
+enum Transfer {
+    /// The given number of bytes were transferred.
+    Done(u64),
+    /// Nothing can be read until the console has input.
+    Console,
+    /// Nothing can be read from the pipe until it has data.
+    PipeRead(Arc<Pipe>),
+    /// Nothing can be written to the pipe until it has room.
+    PipeWrite(Arc<Pipe>),
+}
+
+fn read(fd: u64, buf: u64, len: u64) -> Result<Transfer, OsError> {
+    let buf = user_slice_mut(buf, len)?;
+    let descriptor = descriptor(fd)?;
+    let mut descriptor = descriptor.lock();
+    let n = match *descriptor {
+        Descriptor::Console => {
+            let mut console = CONSOLE.lock();
+            if buf.len() > 0 && !console.has_byte() {
+                return Ok(Transfer::Console);
+            }
+
+            let mut n = 0;
+            while n < buf.len() {
+                match console.read_byte() {
+                    Some(byte) => buf[n] = byte,
+                    None => break,
+                }
+                n += 1;
+            }
+            n
+        }
+        Descriptor::File(ref mut file) => FILE_SYSTEM.locked(|| file.read(buf))?,
+        Descriptor::Dir { .. } => return Err(OsError::IsDirectory),
+        Descriptor::PipeRead(ref reader) => match reader.read(buf) {
+            Some(n) => n,
+            None => return Ok(Transfer::PipeRead(reader.pipe().clone())),
+        },
+        Descriptor::PipeWrite(_) => return Err(OsError::BadFileDescriptor),
+    };
+
+    Ok(Transfer::Done(n as u64))
+}
+
+fn write(fd: u64, buf: u64, len: u64) -> Result<Transfer, OsError> {
+    let buf = user_slice(buf, len)?;
+    let descriptor = descriptor(fd)?;
+    let mut descriptor = descriptor.lock();
+    let n = match *descriptor {
+        Descriptor::Console => CONSOLE.lock().write(buf)?,
+        Descriptor::File(ref mut file) => FILE_SYSTEM.locked(|| file.write(buf))?,
+        Descriptor::Dir { .. } => return Err(OsError::IsDirectory),
+        Descriptor::PipeRead(_) => return Err(OsError::BadFileDescriptor),
+        Descriptor::PipeWrite(ref writer) => match writer.write(buf)? {
+            Some(n) => n,
+            None => return Ok(Transfer::PipeWrite(writer.pipe().clone())),
+        },
+    };
+
+    Ok(Transfer::Done(n as u64))
+}
+
+/// Sets the result of a read or write, or blocks the calling process until
+/// the transfer can make progress and arranges for it to make the system call
+/// again.
+fn finish(transfer: Result<Transfer, OsError>, tf: &mut TrapFrame) {
+    let transfer = match transfer {
+        Ok(Transfer::Done(n)) => return set_result(tf, Ok(n)),
+        Ok(transfer) => transfer,
+        Err(error) => return set_result(tf, Err(error)),
+    };
+
+    tf.elr -= 4;
+    match transfer {
+        // If input arrived or the pipe became ready since it was checked, the
+        // process is not blocked and makes the system call again right away.
+        Transfer::Console => {
+            let key = console::input_key();
+            SCHEDULER.block_if(key, |_: &mut Process| !CONSOLE.lock().has_byte(), tf);
+        }
+        Transfer::PipeRead(pipe) => {
+            SCHEDULER.block_if(pipe.key(), |_: &mut Process| !pipe.readable(), tf);
+        }
+        Transfer::PipeWrite(pipe) => {
+            SCHEDULER.block_if(pipe.key(), |_: &mut Process| !pipe.writable(), tf);
+        }
+        Transfer::Done(_) => unreachable!(),
+    }
+}
+
+fn close(fd: u64) -> Result<u64, OsError> {
+    let descriptor = SCHEDULER
+        .with_current(|process| process.files.remove(fd))
+        .unwrap_or(Err(OsError::BadFileDescriptor))?;
+
+    let result = sync(&descriptor);
+    release(descriptor);
+    result.map(|_| 0)
+}
+
+/// Writes any changes made through `descriptor` to the file system.
+pub fn sync(descriptor: &SharedDescriptor) -> Result<(), OsError> {
+    let mut descriptor = descriptor.lock();
+    if let Descriptor::File(ref mut file) = *descriptor {
+        FILE_SYSTEM.locked(|| file.sync())?;
+    }
+
+    Ok(())
+}
+
+/// Drops `descriptor`. The last handle to a file or directory shares the file
+/// system through reference counts that are not atomic, so it is dropped with
+/// the file system locked. Pipe ends are dropped unlocked.
+pub fn release(descriptor: SharedDescriptor) {
+    let shares_file_system = match *descriptor.lock() {
+        Descriptor::File(_) | Descriptor::Dir { .. } => true,
+        _ => false,
+    };
+
+    if shares_file_system {
+        FILE_SYSTEM.locked(move || drop(descriptor));
+    }
+}
+
+fn pipe(fds: u64) -> Result<u64, OsError> {
+    check_access(fds, 2 * size_of::<u64>() as u64, true)?;
+
+    // Pipe ends wake the scheduler when they are dropped, so they must not be
+    // dropped while it is locked: they are only moved into the table once
+    // both are sure to fit.
+    let mut ends = Some(process::pipe());
+    let (read_fd, write_fd) = SCHEDULER
+        .with_current(|process| {
+            if process.files.available() < 2 {
+                return Err(OsError::TooManyFiles);
+            }
+
+            let (reader, writer) = ends.take().expect("pipe ends");
+            let read_fd = process.files.insert(Descriptor::PipeRead(reader))?;
+            let write_fd = process.files.insert(Descriptor::PipeWrite(writer))?;
+            Ok((read_fd, write_fd))
+        })
+        .unwrap_or(Err(OsError::Unknown))?;
+
+    unsafe { ptr::write_unaligned(fds as *mut [u64; 2], [read_fd, write_fd]) };
+    Ok(0)
+}
+
+fn lseek(fd: u64, offset: u64, whence: u64) -> Result<u64, OsError> {
+    let descriptor = descriptor(fd)?;
+    let mut descriptor = descriptor.lock();
+    match *descriptor {
+        Descriptor::Console | Descriptor::PipeRead(_) | Descriptor::PipeWrite(_) => {
+            Err(OsError::IllegalSeek)
+        }
+        Descriptor::File(ref mut file) => {
+            let pos = match whence {
+                SEEK_SET => SeekFrom::Start(offset),
+                SEEK_CUR => SeekFrom::Current(offset as i64),
+                SEEK_END => SeekFrom::End(offset as i64),
+                _ => return Err(OsError::InvalidArgument),
+            };
+
+            Ok(FILE_SYSTEM.locked(|| file.seek(pos))?)
+        }
+        Descriptor::Dir { ref entries, ref mut next } => {
+            // Directories can only be rewound or moved to an entry index.
+            if whence != SEEK_SET || offset > entries.len() as u64 {
+                return Err(OsError::InvalidArgument);
+            }
+
+            *next = offset as usize;
+            Ok(offset)
+        }
+    }
+}
+
+fn fstat(fd: u64, stat: u64) -> Result<u64, OsError> {
+    check_access(stat, size_of::<Stat>() as u64, true)?;
+    let descriptor = descriptor(fd)?;
+    let descriptor = descriptor.lock();
+    let value = match *descriptor {
+        Descriptor::Console => Stat { kind: KIND_CHAR, size: 0 },
+        Descriptor::File(ref file) => Stat { kind: KIND_FILE, size: file.size() },
+        Descriptor::Dir { .. } => Stat { kind: KIND_DIR, size: 0 },
+        Descriptor::PipeRead(_) | Descriptor::PipeWrite(_) => Stat { kind: KIND_FIFO, size: 0 },
+    };
+
+    unsafe { ptr::write_unaligned(stat as *mut Stat, value) };
+    Ok(0)
+}
+
+fn getdents(fd: u64, buf: u64, count: u64) -> Result<u64, OsError> {
+    let len = count.checked_mul(size_of::<Dirent>() as u64).ok_or(OsError::BadAddress)?;
+    check_access(buf, len, true)?;
+
+    let descriptor = descriptor(fd)?;
+    let mut descriptor = descriptor.lock();
+    let (entries, next) = match *descriptor {
+        Descriptor::Dir { ref entries, ref mut next } => (entries, next),
+        _ => return Err(OsError::NotDirectory),
+    };
+
+    let n = min(count as usize, entries.len() - *next);
+    for (i, entry) in entries[*next..*next + n].iter().enumerate() {
+        let mut dirent = Dirent::empty();
+        let name = entry.name().as_bytes();
+        let name_len = min(name.len(), NAME_MAX);
+        dirent.name[..name_len].copy_from_slice(&name[..name_len]);
+        dirent.name_len = name_len as u64;
+        match entry.as_file() {
+            Some(file) => {
+                dirent.kind = KIND_FILE;
+                dirent.size = file.size();
+            }
+            None => dirent.kind = KIND_DIR,
+        }
+
+        unsafe { ptr::write_unaligned((buf as *mut Dirent).add(i), dirent) };
+    }
+
+    *next += n;
+    Ok(n as u64)
+}
+
+pub fn sys_open(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, open(args[0], args[1], args[2]));
+}
+
+/// Reads from a descriptor. A read from the console with no input available,
+/// or from an empty pipe, blocks: the process waits for input and then makes
+/// the system call again.
+pub fn sys_read(args: [u64; 6], tf: &mut TrapFrame) {
+    finish(read(args[0], args[1], args[2]), tf);
+}
+
+/// Writes to a descriptor. A write to a full pipe blocks like a read.
+pub fn sys_write(args: [u64; 6], tf: &mut TrapFrame) {
+    finish(write(args[0], args[1], args[2]), tf);
+}
+
+pub fn sys_close(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, close(args[0]));
+}
+
+pub fn sys_lseek(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, lseek(args[0], args[1], args[2]));
+}
+
+pub fn sys_fstat(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, fstat(args[0], args[1]));
+}
+
+pub fn sys_getdents(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, getdents(args[0], args[1], args[2]));
+}
+
+pub fn sys_pipe(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, pipe(args[0]));
+}
@@ -0,0 +1,26 @@
+use pi::gpio::PIN_COUNT;
+
+use gpio;
+use process::Process;
+use syscall::OsError;
+use traps::TrapFrame;
+use super::set_result;
+use SCHEDULER;
+
+/// Blocks the process until the next edge on GPIO pin `args[0]`. The result
+/// is set before blocking since the process resumes after the call.
+pub fn sys_gpio_wait(args: [u64; 6], tf: &mut TrapFrame) {
+    if args[0] >= PIN_COUNT as u64 {
+        return set_result(tf, Err(OsError::InvalidArgument));
+    }
+
+    let pin = args[0] as u8;
+    let seen = match gpio::arm_wait(pin) {
+        Some(seen) => seen,
+        None => return set_result(tf, Err(OsError::Busy)),
+    };
+
+    // An edge detected since the pin was armed ends the wait right away.
+    set_result(tf, Ok(0));
+    SCHEDULER.block_if(gpio::key(pin), |_: &mut Process| gpio::events(pin) == seen, tf);
+}
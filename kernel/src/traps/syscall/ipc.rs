@@ -0,0 +1,76 @@
+use std::cmp::min;
+use std::mem::size_of;
+use std::ptr;
+
+use process::{Message, Process, MESSAGE_MAX};
+use syscall::OsError;
+use traps::TrapFrame;
+use super::{check_access, set_result, user_slice, user_slice_mut};
+use SCHEDULER;
+
+fn send(pid: u64, buf: u64, len: u64) -> Result<u64, OsError> {
+    if len > MESSAGE_MAX as u64 {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let data = user_slice(buf, len)?.to_vec();
+    let from = SCHEDULER.with_current(|process| process.pid()).ok_or(OsError::Unknown)?;
+    let key = SCHEDULER
+        .with_process(pid, |process| {
+            if process.is_zombie() {
+                return Err(OsError::NoSuchProcess);
+            }
+
+            process.mailbox.push(Message { from, data })?;
+            Ok(process.mailbox.key())
+        })
+        .unwrap_or(Err(OsError::NoSuchProcess))?;
+
+    // Only the owner of a mailbox blocks on it.
+    SCHEDULER.wake(key, 1);
+    Ok(0)
+}
+
+pub fn sys_send(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, send(args[0], args[1], args[2]));
+}
+
+/// Receives the oldest message in the calling process's mailbox. If the
+/// mailbox is empty, the process blocks until a message is sent to it and
+/// then makes the system call again.
+pub fn sys_recv(args: [u64; 6], tf: &mut TrapFrame) {
+    let (buf, len, from) = (args[0], args[1], args[2]);
+    let buf = match user_slice_mut(buf, len) {
+        Ok(buf) => buf,
+        Err(error) => return set_result(tf, Err(error)),
+    };
+
+    if from != 0 {
+        if let Err(error) = check_access(from, size_of::<u64>() as u64, true) {
+            return set_result(tf, Err(error));
+        }
+    }
+
+    let (message, key) = match SCHEDULER.with_current(|p| (p.mailbox.pop(), p.mailbox.key())) {
+        Some(received) => received,
+        None => return set_result(tf, Err(OsError::Unknown)),
+    };
+
+    match message {
+        Some(message) => {
+            let n = min(buf.len(), message.data.len());
+            buf[..n].copy_from_slice(&message.data[..n]);
+            if from != 0 {
+                unsafe { ptr::write_unaligned(from as *mut u64, message.from) };
+            }
+
+            set_result(tf, Ok(message.data.len() as u64));
+        }
+        None => {
+            // If a message arrived since the mailbox was checked, the process
+            // is not blocked and makes the system call again right away.
+            tf.elr -= 4;
+            SCHEDULER.block_if(key, |process: &mut Process| process.mailbox.is_empty(), tf);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+mod file;
+mod gpio;
+mod ipc;
+mod proc;
+mod sync;
+
+use std::{slice, u32};
+
+use self::file::{sys_close, sys_fstat, sys_getdents, sys_lseek, sys_open, sys_pipe, sys_read};
+use self::file::sys_write;
+use self::gpio::sys_gpio_wait;
+use self::ipc::{sys_recv, sys_send};
+use self::proc::{sys_exit, sys_getpid, sys_setpriority, sys_spawn, sys_wait};
+use self::sync::{sys_futex_wait, sys_futex_wake};
+
+pub use self::proc::terminate;
+use syscall::OsError;
+use traps::TrapFrame;
+use SCHEDULER;
+
+/// A system call handler. `args` holds `x0`-`x5` of the calling process.
+///
+/// A handler must report its result with `set_result()` before switching
+/// away from the calling process; a handler that blocks reports it when the
+/// process is woken instead.
+type Handler = fn(args: [u64; 6], tf: &mut TrapFrame);
+
+/// System call handlers, indexed by system call number.
+static SYSCALLS: [Option<Handler>; 20] = [
+    None,
+    Some(sys_sleep as Handler),       // SYS_SLEEP
+    Some(sys_open as Handler),        // SYS_OPEN
+    Some(sys_read as Handler),        // SYS_READ
+    Some(sys_write as Handler),       // SYS_WRITE
+    Some(sys_close as Handler),       // SYS_CLOSE
+    Some(sys_lseek as Handler),       // SYS_LSEEK
+    Some(sys_fstat as Handler),       // SYS_FSTAT
+    Some(sys_getdents as Handler),    // SYS_GETDENTS
+    Some(sys_exit as Handler),        // SYS_EXIT
+    Some(sys_getpid as Handler),      // SYS_GETPID
+    Some(sys_wait as Handler),        // SYS_WAIT
+    Some(sys_spawn as Handler),       // SYS_SPAWN
+    Some(sys_setpriority as Handler), // SYS_SETPRIORITY
+    Some(sys_futex_wait as Handler),  // SYS_FUTEX_WAIT
+    Some(sys_futex_wake as Handler),  // SYS_FUTEX_WAKE
+    Some(sys_pipe as Handler),        // SYS_PIPE
+    Some(sys_send as Handler),        // SYS_SEND
+    Some(sys_recv as Handler),        // SYS_RECV
+    Some(sys_gpio_wait as Handler),   // SYS_GPIO_WAIT
+];
+
+/// Stores `result` in the result registers of `tf`: on success the value in
+/// `x0` and `0` in `x7`, otherwise the error code in `x7`.
+pub fn set_result(tf: &mut TrapFrame, result: Result<u64, OsError>) {
+    match result {
+        Ok(value) => {
+            tf.x0 = value;
+            tf.x7 = 0;
+        }
+        Err(error) => tf.x7 = error as u64,
+    }
+}
+
+/// Checks that the calling process can access the `len` bytes at `va`, for
+/// writing if `write` is `true`.
+fn check_access(va: u64, len: u64, write: bool) -> Result<(), OsError> {
+    match SCHEDULER.with_current(|process| process.can_access(va, len, write)) {
+        Some(true) => Ok(()),
+        _ => Err(OsError::BadAddress),
+    }
+}
+
+/// Returns the `len` bytes of the calling process's memory at `va`. The
+/// process's page table is installed while it makes a system call, so its
+/// memory is accessed directly.
+fn user_slice<'a>(va: u64, len: u64) -> Result<&'a [u8], OsError> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+
+    check_access(va, len, false)?;
+    Ok(unsafe { slice::from_raw_parts(va as *const u8, len as usize) })
+}
+
+/// Like `user_slice()`, but for memory the process can write to.
+fn user_slice_mut<'a>(va: u64, len: u64) -> Result<&'a mut [u8], OsError> {
+    if len == 0 {
+        return Ok(&mut []);
+    }
+
+    check_access(va, len, true)?;
+    Ok(unsafe { slice::from_raw_parts_mut(va as *mut u8, len as usize) })
+}
+
+/// Sleep for `ms` milliseconds.
+///
+/// This system call takes one parameter: the number of milliseconds to sleep.
+///
+/// In addition to the usual status value, this system call returns one
+/// parameter: the approximate true elapsed time from when `sleep` was called to
+/// when `sleep` returned. The scheduler wakes the process and sets the result.
+pub fn sleep(ms: u32, tf: &mut TrapFrame) {
+    let _ = SCHEDULER.sleep((ms as u64) * 1000, tf);
+}
+
+fn sys_sleep(args: [u64; 6], tf: &mut TrapFrame) {
+    if args[0] > u32::MAX as u64 {
+        return set_result(tf, Err(OsError::InvalidArgument));
+    }
+
+    sleep(args[0] as u32, tf);
+}
+
+/// Dispatches system call `num` made by the process whose trap frame is `tf`.
+/// Unknown system calls fail with `OsError::NoSuchSyscall`.
+pub fn handle_syscall(num: u16, tf: &mut TrapFrame) {
+    let args = [tf.x0, tf.x1, tf.x2, tf.x3, tf.x4, tf.x5];
+    match SYSCALLS.get(num as usize).and_then(|handler| *handler) {
+        Some(handler) => handler(args, tf),
+        None => set_result(tf, Err(OsError::NoSuchSyscall)),
+    }
+}
@@ -0,0 +1,119 @@
+use std::mem::size_of;
+use std::path::Path;
+use std::{ptr, str};
+
+use process::{LoadError, Process, NICE_MAX, NICE_MIN};
+use syscall::{OsError, PID_SELF};
+use traps::TrapFrame;
+use super::file::{release, sync};
+use super::{check_access, set_result, user_slice};
+use SCHEDULER;
+
+/// Returns the `OsError` corresponding to a failure to load a program.
+fn load_error(error: LoadError) -> OsError {
+    match error {
+        LoadError::Io(error) => error.into(),
+        LoadError::BadMagic | LoadError::Unsupported(_) | LoadError::Malformed(_) => {
+            OsError::ExecFormat
+        }
+        LoadError::ArgumentsTooLarge => OsError::ArgumentsTooLong,
+        LoadError::OutOfMemory => OsError::OutOfMemory,
+    }
+}
+
+fn spawn(path: u64, path_len: u64, args: u64, args_len: u64) -> Result<u64, OsError> {
+    let path = str::from_utf8(user_slice(path, path_len)?).map_err(|_| OsError::InvalidArgument)?;
+    let path = Path::new(path);
+    if !path.is_absolute() {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let args = str::from_utf8(user_slice(args, args_len)?).map_err(|_| OsError::InvalidArgument)?;
+    let args: Vec<&str> = args.split_terminator('\0').collect();
+
+    let mut process = Process::load(path, &args).map_err(load_error)?;
+    process.parent = Some(SCHEDULER.with_current(|parent| parent.pid()).ok_or(OsError::Unknown)?);
+    SCHEDULER.add(process).ok_or(OsError::OutOfMemory)
+}
+
+fn setpriority(pid: u64, nice: u64) -> Result<u64, OsError> {
+    let nice = nice as i64;
+    if nice < NICE_MIN || nice > NICE_MAX {
+        return Err(OsError::InvalidArgument);
+    }
+
+    let caller = SCHEDULER.with_current(|process| process.pid()).ok_or(OsError::Unknown)?;
+    let pid = if pid == PID_SELF { caller } else { pid };
+    SCHEDULER
+        .with_process(pid, |process| {
+            if process.pid() != caller && process.parent != Some(caller) {
+                return Err(OsError::PermissionDenied);
+            }
+
+            process.nice = nice;
+            Ok(0)
+        })
+        .unwrap_or(Err(OsError::NoSuchProcess))
+}
+
+/// Terminates the calling process with exit status `status`. Its open files
+/// are synced and closed before it is switched out for good.
+pub fn terminate(status: u64, tf: &mut TrapFrame) {
+    let files = SCHEDULER
+        .with_current(|process| process.files.clear())
+        .unwrap_or_default();
+
+    for descriptor in files {
+        let _ = sync(&descriptor);
+        release(descriptor);
+    }
+
+    let _ = SCHEDULER.exit(status, tf);
+}
+
+pub fn sys_exit(args: [u64; 6], tf: &mut TrapFrame) {
+    terminate(args[0], tf);
+}
+
+pub fn sys_getpid(_args: [u64; 6], tf: &mut TrapFrame) {
+    let pid = SCHEDULER.with_current(|process| process.pid());
+    set_result(tf, pid.ok_or(OsError::Unknown));
+}
+
+/// Reaps an exited child and stores its exit status at `args[1]` unless that
+/// is null. If no matching child has exited yet, the process waits for one
+/// and then makes the system call again.
+pub fn sys_wait(args: [u64; 6], tf: &mut TrapFrame) {
+    let (pid, status) = (args[0], args[1]);
+    if status != 0 {
+        if let Err(error) = check_access(status, size_of::<u64>() as u64, true) {
+            return set_result(tf, Err(error));
+        }
+    }
+
+    // Rewind to the `svc` so that a process that has to wait retries the call
+    // when it is woken.
+    tf.elr -= 4;
+    match SCHEDULER.wait(pid, tf) {
+        Ok(Some((child, code))) => {
+            tf.elr += 4;
+            if status != 0 {
+                unsafe { ptr::write_unaligned(status as *mut u64, code) };
+            }
+            set_result(tf, Ok(child));
+        }
+        Ok(None) => {}
+        Err(error) => {
+            tf.elr += 4;
+            set_result(tf, Err(error));
+        }
+    }
+}
+
+pub fn sys_spawn(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, spawn(args[0], args[1], args[2], args[3]));
+}
+
+pub fn sys_setpriority(args: [u64; 6], tf: &mut TrapFrame) {
+    set_result(tf, setpriority(args[0], args[1]));
+}
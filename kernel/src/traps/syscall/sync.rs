@@ -0,0 +1,52 @@
+use std::mem::size_of;
+use std::ptr;
+
+use process::Process;
+use syscall::OsError;
+use traps::TrapFrame;
+use super::{check_access, set_result};
+use SCHEDULER;
+
+/// Returns the key of the futex word at `addr` in the calling process: its
+/// physical address, so that processes sharing the word share the key.
+fn futex_key(addr: u64) -> Result<u64, OsError> {
+    if addr % size_of::<u64>() as u64 != 0 {
+        return Err(OsError::InvalidArgument);
+    }
+
+    check_access(addr, size_of::<u64>() as u64, false)?;
+    SCHEDULER
+        .with_current(|process| process.physical_addr(addr))
+        .and_then(|pa| pa)
+        .ok_or(OsError::BadAddress)
+}
+
+/// Blocks the process if the futex word at `args[0]` holds `args[1]`. The
+/// result is set before blocking since the process resumes after the call.
+pub fn sys_futex_wait(args: [u64; 6], tf: &mut TrapFrame) {
+    let (addr, expected) = (args[0], args[1]);
+    let key = match futex_key(addr) {
+        Ok(key) => key,
+        Err(error) => return set_result(tf, Err(error)),
+    };
+
+    set_result(tf, Ok(0));
+    let holds = |_: &mut Process| unsafe { ptr::read_volatile(addr as *const u64) == expected };
+    if !SCHEDULER.block_if(key, holds, tf) {
+        set_result(tf, Err(OsError::WouldBlock));
+    }
+}
+
+pub fn sys_futex_wake(args: [u64; 6], tf: &mut TrapFrame) {
+    let result = futex_key(args[0]).map(|key| {
+        let count = if args[1] > usize::max_value() as u64 {
+            usize::max_value()
+        } else {
+            args[1] as usize
+        };
+
+        SCHEDULER.wake(key, count) as u64
+    });
+
+    set_result(tf, result);
+}
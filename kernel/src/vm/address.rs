@@ -1,9 +1,11 @@
 use std::fmt;
 
 /// A virtual address.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct VirtualAddr(usize);
 
 /// A physical address.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PhysicalAddr(usize);
 
 macro_rules! impl_for {
@@ -14,6 +16,12 @@ macro_rules! impl_for {
             }
         }
 
+        impl From<usize> for $T {
+            fn from(raw_addr: usize) -> $T {
+                $T(raw_addr)
+            }
+        }
+
         impl $T {
             /// Returns the inner address of `self`.
             pub fn as_ptr(&self) -> *const u8 {
@@ -1,3 +1,140 @@
 mod address;
+mod pagetable;
 
 pub use self::address::{PhysicalAddr, VirtualAddr};
+pub use self::pagetable::{Entry, KernelPageTable, PagePerm, Table, UserPageTable};
+pub use self::pagetable::{ENTRIES, LOCAL_BASE};
+pub use self::pagetable::{l1_index, l2_index, l3_index};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64;
+use mutex::IrqSafeMutex;
+
+/// The size of a page (and of the translation granule) in bytes.
+pub const PAGE_SIZE: usize = 4096;
+
+/// The size of the memory region mapped by a level 2 block descriptor.
+pub const L2_BLOCK_SIZE: usize = PAGE_SIZE * ENTRIES;
+
+/// The size of the memory region mapped by a level 1 block descriptor.
+pub const L1_BLOCK_SIZE: usize = L2_BLOCK_SIZE * ENTRIES;
+
+/// The virtual address at which user images are loaded: the start of the
+/// user region of every `UserPageTable`.
+pub const USER_IMG_BASE: usize = 0x8000_0000;
+
+/// The size of the user region of a process's address space.
+pub const USER_MAX_VM_SIZE: usize = L1_BLOCK_SIZE;
+
+/// The virtual address of the top of every user process's stack.
+pub const USER_STACK_TOP: usize = USER_IMG_BASE + USER_MAX_VM_SIZE;
+
+/// The number of bits of virtual address translated through `TTBR0_EL1`.
+pub const VA_BITS: u64 = 32;
+
+/// Memory attribute encodings, indexed by the `Entry::ATTR_*` constants:
+///
+///   * 0: normal, inner/outer write-back read/write-allocate
+///   * 1: device-nGnRE
+///   * 2: normal, inner/outer non-cacheable
+const MAIR: u64 = (0xFF << 0) | (0x04 << 8) | (0x44 << 16);
+
+/// Translation control (ref: D7.2.84). `TTBR0_EL1` walks use a 4KiB granule,
+/// a `VA_BITS` input size, inner shareable write-back table walks and a 32-bit
+/// physical address size.
+///
+/// Walks through `TTBR1_EL1` are disabled, so it is never programmed. This is
+/// deliberate: the kernel is linked and identity mapped at its physical
+/// addresses, in the low GiBs of every `TTBR0_EL1` table, and each
+/// `UserPageTable` shares those mappings. A kernel table in `TTBR1_EL1` would
+/// only add an unused high alias, and stray high addresses would translate
+/// instead of faulting.
+const TCR: u64 = (64 - VA_BITS) // T0SZ
+    | (0b01 << 8) // IRGN0: write-back, write-allocate
+    | (0b01 << 10) // ORGN0: write-back, write-allocate
+    | (0b11 << 12) // SH0: inner shareable
+    | (0b00 << 14) // TG0: 4KiB
+    | ((64 - VA_BITS) << 16) // T1SZ
+    | (1 << 23) // EPD1: no TTBR1 walks
+    | (0b10 << 30) // TG1: 4KiB
+    | (0b000 << 32); // IPS: 32 bits
+
+/// Thread-safe (locking) wrapper around the kernel's page table.
+#[derive(Debug)]
+pub struct VMManager {
+    table: IrqSafeMutex<Option<KernelPageTable>>,
+    /// The base address of the kernel page table, or `0` until it is built.
+    /// It is read without locking: cores set up their MMU before their data
+    /// cache is on, and locks need the data cache.
+    baddr: AtomicUsize,
+}
+
+impl VMManager {
+    /// Returns an uninitialized `VMManager`.
+    ///
+    /// The virtual memory manager must be initialized by calling
+    /// `initialize()` after the memory allocator has been initialized.
+    pub const fn uninitialized() -> Self {
+        VMManager {
+            table: IrqSafeMutex::new(None),
+            baddr: AtomicUsize::new(0),
+        }
+    }
+
+    /// Builds the kernel page table and enables the MMU on the calling core.
+    pub fn initialize(&self) {
+        let table = KernelPageTable::new();
+        self.baddr.store(table.baddr().as_usize(), Ordering::Release);
+        *self.table.lock() = Some(table);
+        self.setup();
+    }
+
+    /// Programs the calling core's MMU with the kernel page table, turns on
+    /// address translation, and enables the core's data and instruction caches.
+    /// No lock is taken, so this is safe to call on a core whose data cache is
+    /// still off.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the VM manager is uninitialized or if the core does not
+    /// support a 4KiB translation granule.
+    pub fn setup(&self) {
+        let tgran4 = (aarch64::id_aa64mmfr0() >> 28) & 0xF;
+        assert!(tgran4 == 0, "4KiB translation granule unsupported");
+
+        let baddr = self.baddr();
+        unsafe {
+            aarch64::set_mair(MAIR);
+            aarch64::set_tcr(TCR);
+            aarch64::set_ttbr0(baddr.as_u64());
+            aarch64::isb();
+            aarch64::tlb_invalidate_all();
+            aarch64::set_sctlr(aarch64::sctlr() | aarch64::SCTLR_M);
+            aarch64::isb();
+            aarch64::cache::enable();
+        }
+    }
+
+    /// Returns a new, empty user address space that shares the kernel's
+    /// mappings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the VM manager is uninitialized.
+    pub fn user_page_table(&self) -> UserPageTable {
+        UserPageTable::new(self.table.lock().as_ref().expect("vmm uninitialized"))
+    }
+
+    /// Returns the base address of the kernel page table.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the VM manager is uninitialized.
+    pub fn baddr(&self) -> PhysicalAddr {
+        match self.baddr.load(Ordering::Acquire) {
+            0 => panic!("vmm uninitialized"),
+            baddr => baddr.into(),
+        }
+    }
+}
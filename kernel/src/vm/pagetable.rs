@@ -0,0 +1,509 @@
+use std::fmt;
+use std::ops::{Index, IndexMut};
+use std::slice;
+
+use alloc::allocator::{Alloc, Layout};
+use pi::common::IO_BASE;
+use vm::{PhysicalAddr, VirtualAddr, L1_BLOCK_SIZE, L2_BLOCK_SIZE, PAGE_SIZE};
+use vm::{USER_IMG_BASE, USER_MAX_VM_SIZE};
+use ALLOCATOR;
+
+/// The number of descriptors in a translation table with a 4KiB granule.
+pub const ENTRIES: usize = PAGE_SIZE / 8;
+
+/// The base address of the BCM2836 ARM-local peripherals (core timers, local
+/// interrupt controller, mailboxes). These live just above the first GiB.
+pub const LOCAL_BASE: usize = 0x4000_0000;
+
+/// A single aarch64 stage 1 translation table descriptor (ref: D4.3).
+///
+/// The same encoding is used for table descriptors (levels 0-2), block
+/// descriptors (levels 1-2) and page descriptors (level 3); the level at which
+/// an entry lives determines how bit 1 is interpreted.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct Entry(u64);
+
+impl Entry {
+    /// The descriptor is valid.
+    pub const VALID: u64 = 1 << 0;
+    /// At levels 0-2 the descriptor points to a table. At level 3 the
+    /// descriptor is a page descriptor. When clear at levels 1-2 the
+    /// descriptor is a block descriptor.
+    pub const TABLE: u64 = 1 << 1;
+    /// Alias of `TABLE` for level 3 descriptors.
+    pub const PAGE: u64 = 1 << 1;
+
+    /// `MAIR_EL1` index 0: normal, write-back, read/write-allocate memory.
+    pub const ATTR_NORMAL: u64 = 0 << 2;
+    /// `MAIR_EL1` index 1: device-nGnRE memory.
+    pub const ATTR_DEVICE: u64 = 1 << 2;
+    /// `MAIR_EL1` index 2: normal, non-cacheable memory.
+    pub const ATTR_NORMAL_NC: u64 = 2 << 2;
+
+    /// Read/write at EL1, no access at EL0.
+    pub const AP_EL1_RW: u64 = 0b00 << 6;
+    /// Read/write at EL1 and EL0.
+    pub const AP_RW: u64 = 0b01 << 6;
+    /// Read-only at EL1, no access at EL0.
+    pub const AP_EL1_RO: u64 = 0b10 << 6;
+    /// Read-only at EL1 and EL0.
+    pub const AP_RO: u64 = 0b11 << 6;
+
+    /// Outer shareable.
+    pub const SH_OUTER: u64 = 0b10 << 8;
+    /// Inner shareable.
+    pub const SH_INNER: u64 = 0b11 << 8;
+
+    /// Access flag. Accessing memory through a descriptor without this bit
+    /// set results in an access flag fault.
+    pub const AF: u64 = 1 << 10;
+    /// Not global: the translation is associated with the current ASID.
+    pub const NG: u64 = 1 << 11;
+    /// Privileged execute-never.
+    pub const PXN: u64 = 1 << 53;
+    /// Unprivileged execute-never.
+    pub const UXN: u64 = 1 << 54;
+    /// Software-defined bit: the mapped page was allocated by, and is freed
+    /// with, the page table holding this descriptor.
+    pub const SW_OWNED: u64 = 1 << 55;
+
+    /// Attributes for normal kernel memory: EL1 read/write/execute.
+    pub const KERNEL_NORMAL: u64 = Self::ATTR_NORMAL | Self::SH_INNER | Self::AF | Self::AP_EL1_RW | Self::UXN;
+    /// Attributes for kernel device memory: EL1 read/write, never executable.
+    pub const KERNEL_DEVICE: u64 = Self::ATTR_DEVICE | Self::SH_OUTER | Self::AF | Self::AP_EL1_RW | Self::PXN | Self::UXN;
+
+    /// Mask of the output address bits [47:12].
+    const ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+    /// Returns an invalid (faulting) descriptor.
+    pub const fn invalid() -> Entry {
+        Entry(0)
+    }
+
+    /// Returns a table descriptor pointing to the next level table at `addr`.
+    pub fn table(addr: PhysicalAddr) -> Entry {
+        Entry((addr.as_u64() & Self::ADDR_MASK) | Self::TABLE | Self::VALID)
+    }
+
+    /// Returns a level 1 or level 2 block descriptor mapping `addr` with the
+    /// attributes `attrs`.
+    pub fn block(addr: PhysicalAddr, attrs: u64) -> Entry {
+        Entry((addr.as_u64() & Self::ADDR_MASK) | (attrs & !Self::TABLE) | Self::VALID)
+    }
+
+    /// Returns a level 3 page descriptor mapping `addr` with the attributes
+    /// `attrs`.
+    pub fn page(addr: PhysicalAddr, attrs: u64) -> Entry {
+        Entry((addr.as_u64() & Self::ADDR_MASK) | attrs | Self::PAGE | Self::VALID)
+    }
+
+    /// Returns `true` if this descriptor is valid.
+    pub fn is_valid(&self) -> bool {
+        self.0 & Self::VALID != 0
+    }
+
+    /// Returns `true` if this descriptor is a valid table (levels 0-2) or
+    /// page (level 3) descriptor.
+    pub fn is_table(&self) -> bool {
+        self.is_valid() && self.0 & Self::TABLE != 0
+    }
+
+    /// Returns the output address of this descriptor.
+    pub fn addr(&self) -> PhysicalAddr {
+        ((self.0 & Self::ADDR_MASK) as usize).into()
+    }
+
+    /// Returns the raw value of this descriptor.
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Debug for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Entry({:#018x})", self.0)
+    }
+}
+
+/// A 4KiB aligned translation table of 512 descriptors.
+#[repr(C, align(4096))]
+pub struct Table {
+    entries: [Entry; ENTRIES],
+}
+
+impl Table {
+    /// Returns a newly allocated table with every descriptor invalid.
+    pub fn new() -> Box<Table> {
+        Box::new(Table {
+            entries: [Entry::invalid(); ENTRIES],
+        })
+    }
+
+    /// Returns the physical address of this table.
+    ///
+    /// Kernel memory is identity mapped, so this is the table's address.
+    pub fn addr(&self) -> PhysicalAddr {
+        (self as *const Table as usize).into()
+    }
+
+    /// Returns an iterator over the descriptors in this table.
+    pub fn iter(&self) -> slice::Iter<Entry> {
+        self.entries.iter()
+    }
+}
+
+impl Index<usize> for Table {
+    type Output = Entry;
+
+    fn index(&self, index: usize) -> &Entry {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for Table {
+    fn index_mut(&mut self, index: usize) -> &mut Entry {
+        &mut self.entries[index]
+    }
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Table").field("addr", &self.addr()).finish()
+    }
+}
+
+/// Returns the level 1 table index of `va`.
+pub fn l1_index(va: VirtualAddr) -> usize {
+    (va.as_usize() / L1_BLOCK_SIZE) % ENTRIES
+}
+
+/// Returns the level 2 table index of `va`.
+pub fn l2_index(va: VirtualAddr) -> usize {
+    (va.as_usize() / L2_BLOCK_SIZE) % ENTRIES
+}
+
+/// Returns the level 3 table index of `va`.
+pub fn l3_index(va: VirtualAddr) -> usize {
+    (va.as_usize() / PAGE_SIZE) % ENTRIES
+}
+
+/// Access permissions for a page mapped into a user address space.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PagePerm {
+    /// Readable and writeable.
+    RW,
+    /// Read-only.
+    RO,
+    /// Readable, writeable and executable.
+    RWX,
+    /// Readable and executable.
+    RX,
+}
+
+impl PagePerm {
+    /// Returns the permissions of a readable page that is also writable if
+    /// `write` and executable if `execute`.
+    pub fn new(write: bool, execute: bool) -> PagePerm {
+        match (write, execute) {
+            (true, true) => PagePerm::RWX,
+            (false, true) => PagePerm::RX,
+            (true, false) => PagePerm::RW,
+            (false, false) => PagePerm::RO,
+        }
+    }
+
+    /// Returns `true` if these permissions allow writing.
+    pub fn is_writable(self) -> bool {
+        self == PagePerm::RW || self == PagePerm::RWX
+    }
+
+    /// Returns `true` if these permissions allow executing.
+    pub fn is_executable(self) -> bool {
+        self == PagePerm::RX || self == PagePerm::RWX
+    }
+
+    /// Returns the permissions that allow whatever `self` or `other` allows.
+    pub fn union(self, other: PagePerm) -> PagePerm {
+        PagePerm::new(
+            self.is_writable() || other.is_writable(),
+            self.is_executable() || other.is_executable(),
+        )
+    }
+
+    /// Returns the descriptor attributes for a user page with these
+    /// permissions. User pages are never executable at EL1.
+    fn attrs(self) -> u64 {
+        let base = Entry::ATTR_NORMAL | Entry::SH_INNER | Entry::AF | Entry::NG | Entry::PXN;
+        match self {
+            PagePerm::RW => base | Entry::AP_RW | Entry::UXN,
+            PagePerm::RO => base | Entry::AP_RO | Entry::UXN,
+            PagePerm::RWX => base | Entry::AP_RW,
+            PagePerm::RX => base | Entry::AP_RO,
+        }
+    }
+}
+
+/// The kernel's identity mapping.
+///
+/// Translation starts at level 1 with a 32-bit input address space. The first
+/// GiB is mapped with 2MiB level 2 blocks: RAM below `IO_BASE` as normal
+/// memory and the peripheral window from `IO_BASE` as device memory. The
+/// second GiB, holding the ARM-local peripherals, is a single device block.
+#[derive(Debug)]
+pub struct KernelPageTable {
+    l1: Box<Table>,
+    l2: Box<Table>,
+}
+
+impl KernelPageTable {
+    /// Builds the kernel identity map.
+    pub fn new() -> KernelPageTable {
+        let mut l2 = Table::new();
+        for i in 0..ENTRIES {
+            let addr = i * L2_BLOCK_SIZE;
+            let attrs = if addr >= IO_BASE {
+                Entry::KERNEL_DEVICE
+            } else {
+                Entry::KERNEL_NORMAL
+            };
+            l2[i] = Entry::block(addr.into(), attrs);
+        }
+
+        let mut l1 = Table::new();
+        l1[0] = Entry::table(l2.addr());
+        l1[1] = Entry::block(LOCAL_BASE.into(), Entry::KERNEL_DEVICE);
+
+        KernelPageTable { l1, l2 }
+    }
+
+    /// Returns the base address of the level 1 table, suitable for `TTBR0`.
+    pub fn baddr(&self) -> PhysicalAddr {
+        self.l1.addr()
+    }
+
+    /// Returns the level 1 descriptor at `index`.
+    pub fn l1_entry(&self, index: usize) -> Entry {
+        self.l1[index]
+    }
+
+    /// Translates `va` to a physical address by walking the table. Returns
+    /// `None` if `va` is not mapped.
+    pub fn translate(&self, va: VirtualAddr) -> Option<PhysicalAddr> {
+        let l1_entry = self.l1[l1_index(va)];
+        if !l1_entry.is_valid() {
+            return None;
+        } else if !l1_entry.is_table() {
+            let offset = va.as_usize() % L1_BLOCK_SIZE;
+            return Some((l1_entry.addr().as_usize() + offset).into());
+        }
+
+        let l2_entry = self.l2[l2_index(va)];
+        if !l2_entry.is_valid() {
+            return None;
+        }
+
+        let offset = va.as_usize() % L2_BLOCK_SIZE;
+        Some((l2_entry.addr().as_usize() + offset).into())
+    }
+}
+
+/// The layout of a single page.
+fn page_layout() -> Layout {
+    unsafe { Layout::from_size_align_unchecked(PAGE_SIZE, PAGE_SIZE) }
+}
+
+/// A process's address space.
+///
+/// The level 1 table shares the kernel's level 1 descriptors, so the kernel
+/// stays mapped (EL1-only) while a user table is installed in `TTBR0`. The
+/// user region is the GiB starting at `USER_IMG_BASE`, mapped with 4KiB pages
+/// through a level 2 table and lazily allocated level 3 tables.
+pub struct UserPageTable {
+    l1: Box<Table>,
+    l2: Box<Table>,
+    l3: Vec<Option<Box<Table>>>,
+}
+
+impl UserPageTable {
+    /// Returns a new, empty user address space sharing `kernel`'s mappings.
+    pub fn new(kernel: &KernelPageTable) -> UserPageTable {
+        let l2 = Table::new();
+        let mut l1 = Table::new();
+        l1[0] = kernel.l1_entry(0);
+        l1[1] = kernel.l1_entry(1);
+        l1[l1_index(USER_IMG_BASE.into())] = Entry::table(l2.addr());
+
+        UserPageTable {
+            l1,
+            l2,
+            l3: (0..ENTRIES).map(|_| None).collect(),
+        }
+    }
+
+    /// Returns the base address of the level 1 table, suitable for `TTBR0`.
+    pub fn baddr(&self) -> PhysicalAddr {
+        self.l1.addr()
+    }
+
+    /// Returns `true` if `va` lies within the user region.
+    pub fn contains(va: VirtualAddr) -> bool {
+        va.as_usize() >= USER_IMG_BASE && va.as_usize() - USER_IMG_BASE < USER_MAX_VM_SIZE
+    }
+
+    /// Returns the level 3 table covering `va`, allocating it if necessary.
+    fn l3_table(&mut self, va: VirtualAddr) -> &mut Table {
+        let index = l2_index(va);
+        if self.l3[index].is_none() {
+            let table = Table::new();
+            self.l2[index] = Entry::table(table.addr());
+            self.l3[index] = Some(table);
+        }
+
+        self.l3[index].as_mut().unwrap()
+    }
+
+    fn set_page(&mut self, va: VirtualAddr, entry: Entry) {
+        if va.as_usize() % PAGE_SIZE != 0 || !UserPageTable::contains(va) {
+            panic!("UserPageTable: invalid user page address {:?}", va);
+        }
+
+        let l3 = self.l3_table(va);
+        let index = l3_index(va);
+        if l3[index].is_valid() {
+            panic!("UserPageTable: {:?} is already mapped", va);
+        }
+
+        l3[index] = entry;
+    }
+
+    /// Maps the page at virtual address `va` to the physical page at `pa`
+    /// with permissions `perm`. The page is not owned by this table.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `va` or `pa` is not page aligned, if `va` is outside of the
+    /// user region, or if `va` is already mapped.
+    pub fn map(&mut self, va: VirtualAddr, pa: PhysicalAddr, perm: PagePerm) {
+        assert!(pa.as_usize() % PAGE_SIZE == 0, "unaligned physical page");
+        self.set_page(va, Entry::page(pa, perm.attrs()));
+    }
+
+    /// Allocates a zeroed page, maps it at virtual address `va` with
+    /// permissions `perm` and returns the page as a slice. The page is freed
+    /// when this table is dropped.
+    ///
+    /// Returns `None` if a page could not be allocated.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `va` is not page aligned, is outside of the user region, or
+    /// is already mapped.
+    pub fn alloc(&mut self, va: VirtualAddr, perm: PagePerm) -> Option<&mut [u8]> {
+        let page = unsafe {
+            let page: *mut u8 = (&ALLOCATOR).alloc(page_layout()).ok()?;
+            page.write_bytes(0, PAGE_SIZE);
+            page
+        };
+
+        let pa = PhysicalAddr::from(page as usize);
+        self.set_page(va, Entry::page(pa, perm.attrs() | Entry::SW_OWNED));
+        Some(unsafe { slice::from_raw_parts_mut(page, PAGE_SIZE) })
+    }
+
+    /// Returns the valid page descriptor mapping `va`, if any.
+    fn page_entry(&self, va: VirtualAddr) -> Option<Entry> {
+        if !UserPageTable::contains(va) {
+            return None;
+        }
+
+        let l3 = self.l3[l2_index(va)].as_ref()?;
+        let entry = l3[l3_index(va)];
+        if entry.is_valid() {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Translates `va` to a physical address. Returns `None` if `va` is not
+    /// mapped in the user region.
+    pub fn translate(&self, va: VirtualAddr) -> Option<PhysicalAddr> {
+        let entry = self.page_entry(va)?;
+        Some((entry.addr().as_usize() + va.as_usize() % PAGE_SIZE).into())
+    }
+
+    /// Returns `true` if `va` is mapped in the user region and the page is
+    /// writable.
+    pub fn is_writable(&self, va: VirtualAddr) -> bool {
+        match self.page_entry(va) {
+            // AP[2] is the read-only bit.
+            Some(entry) => entry.bits() & Entry::AP_EL1_RO == 0,
+            None => false,
+        }
+    }
+}
+
+impl Drop for UserPageTable {
+    fn drop(&mut self) {
+        for l3 in self.l3.iter().filter_map(|l3| l3.as_ref()) {
+            for entry in l3.iter().filter(|e| e.is_valid() && e.bits() & Entry::SW_OWNED != 0) {
+                unsafe { (&ALLOCATOR).dealloc(entry.addr().as_usize() as *mut u8, page_layout()) }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for UserPageTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserPageTable")
+            .field("baddr", &self.baddr())
+            .field("l3_tables", &self.l3.iter().filter(|t| t.is_some()).count())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptor_encoding() {
+        let table = Entry::table(0x1234_5000.into());
+        assert_eq!(table.bits(), 0x1234_5003);
+        assert!(table.is_table());
+
+        let block = Entry::block(0x20_0000.into(), Entry::KERNEL_NORMAL | Entry::TABLE);
+        assert!(block.is_valid());
+        assert!(!block.is_table());
+        assert_eq!(block.addr().as_usize(), 0x20_0000);
+
+        let page = Entry::page(0x8000_1fff.into(), Entry::AP_RW | Entry::AF);
+        assert!(page.is_table());
+        assert_eq!(page.addr().as_usize(), 0x8000_1000);
+        assert_eq!(page.bits() & Entry::AP_RO, Entry::AP_RW);
+        assert!(!Entry::invalid().is_valid());
+    }
+
+    #[test]
+    fn perm_union() {
+        assert_eq!(PagePerm::RX.union(PagePerm::RW), PagePerm::RWX);
+        assert_eq!(PagePerm::RO.union(PagePerm::RW), PagePerm::RW);
+        assert_eq!(PagePerm::RX.union(PagePerm::RO), PagePerm::RX);
+        assert_eq!(PagePerm::RO.union(PagePerm::RO), PagePerm::RO);
+    }
+
+    #[test]
+    fn kernel_identity_map() {
+        let table = KernelPageTable::new();
+        for &addr in &[0x0, 0x80000, 0x1234_5678, IO_BASE, IO_BASE + 0x215040, LOCAL_BASE + 0x40] {
+            let pa = table.translate(addr.into()).expect("mapped");
+            assert_eq!(pa.as_usize(), addr);
+        }
+
+        assert_eq!(table.translate(0x8000_0000.into()), None);
+        assert_eq!(table.l1_entry(0).addr(), table.l2.addr());
+        assert_eq!(table.l2[l2_index(IO_BASE.into())].bits() & (0b111 << 2), Entry::ATTR_DEVICE);
+        assert_eq!(table.l2[0].bits() & (0b111 << 2), Entry::ATTR_NORMAL);
+    }
+}
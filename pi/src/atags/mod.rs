@@ -18,6 +18,22 @@ impl Atags {
             ptr: unsafe { &*(ATAG_BASE as *const raw::Atag) },
         }
     }
+
+    /// Returns the value of the last `name=<value>` argument on the kernel
+    /// command line, if any.
+    pub fn cmdline_arg(name: &str) -> Option<&'static str> {
+        let cmdline = Atags::get().filter_map(|atag| atag.cmd()).next()?;
+        cmdline
+            .split_whitespace()
+            .filter_map(|arg| {
+                let mut parts = arg.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if key == name => Some(value),
+                    _ => None,
+                }
+            })
+            .last()
+    }
 }
 
 impl Iterator for Atags {
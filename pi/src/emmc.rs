@@ -0,0 +1,607 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use common::IO_BASE;
+use timer;
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile};
+
+/// The base address of the `EMMC` (Arasan SDHCI) registers.
+const EMMC_BASE: usize = IO_BASE + 0x300000;
+
+/// The size of a block (sector) in bytes.
+pub const BLOCK_SIZE: usize = 512;
+
+/// The frequency of the clock feeding the EMMC controller.
+const BASE_CLOCK: u32 = 41_666_666;
+
+/// Clock used for card identification.
+const IDENTIFICATION_CLOCK: u32 = 400_000;
+
+/// Clock used for data transfer (default speed mode).
+const TRANSFER_CLOCK: u32 = 25_000_000;
+
+/// Timeouts, in microseconds.
+const RESET_TIMEOUT: u64 = 100_000;
+const COMMAND_TIMEOUT: u64 = 100_000;
+const DATA_TIMEOUT: u64 = 500_000;
+const OP_COND_TIMEOUT: u64 = 1_000_000;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    ARG2: Volatile<u32>,
+    BLKSIZECNT: Volatile<u32>,
+    ARG1: Volatile<u32>,
+    CMDTM: Volatile<u32>,
+    RESP: [ReadVolatile<u32>; 4],
+    DATA: Volatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONTROL0: Volatile<u32>,
+    CONTROL1: Volatile<u32>,
+    INTERRUPT: Volatile<u32>,
+    IRPT_MASK: Volatile<u32>,
+    IRPT_EN: Volatile<u32>,
+    CONTROL2: Volatile<u32>,
+    __r0: [Reserved<u32>; 4],
+    FORCE_IRPT: Volatile<u32>,
+    __r1: [Reserved<u32>; 7],
+    BOOT_TIMEOUT: Volatile<u32>,
+    DBG_SEL: Volatile<u32>,
+    __r2: [Reserved<u32>; 2],
+    EXRDFIFO_CFG: Volatile<u32>,
+    EXRDFIFO_EN: Volatile<u32>,
+    TUNE_STEP: Volatile<u32>,
+    TUNE_STEPS_STD: Volatile<u32>,
+    TUNE_STEPS_DDR: Volatile<u32>,
+    __r3: [Reserved<u32>; 23],
+    SPI_INT_SPT: Volatile<u32>,
+    __r4: [Reserved<u32>; 2],
+    SLOTISR_VER: ReadVolatile<u32>,
+}
+
+/// Bit fields of the `CMDTM` register.
+mod cmdtm {
+    pub const BLKCNT_EN: u32 = 1 << 1;
+    pub const AUTO_CMD12: u32 = 0b01 << 2;
+    pub const READ: u32 = 1 << 4;
+    pub const MULTI_BLOCK: u32 = 1 << 5;
+    pub const RSP_136: u32 = 0b01 << 16;
+    pub const RSP_48: u32 = 0b10 << 16;
+    pub const RSP_48_BUSY: u32 = 0b11 << 16;
+    pub const CRC_CHECK: u32 = 1 << 19;
+    pub const INDEX_CHECK: u32 = 1 << 20;
+    pub const DATA: u32 = 1 << 21;
+
+    /// Not part of the register: marks an application specific command that
+    /// must be preceded by `APP_CMD`.
+    pub const NEED_APP: u32 = 1 << 31;
+
+    pub const fn index(n: u32) -> u32 {
+        n << 24
+    }
+}
+
+/// Bit fields of the `STATUS` register.
+mod status {
+    pub const CMD_INHIBIT: u32 = 1 << 0;
+    pub const DAT_INHIBIT: u32 = 1 << 1;
+}
+
+/// Bit fields of the `CONTROL0` register.
+mod control0 {
+    pub const HCTL_DWIDTH: u32 = 1 << 1;
+}
+
+/// Bit fields of the `CONTROL1` register.
+mod control1 {
+    pub const CLK_INTLEN: u32 = 1 << 0;
+    pub const CLK_STABLE: u32 = 1 << 1;
+    pub const CLK_EN: u32 = 1 << 2;
+    pub const CLK_FREQ_MASK: u32 = 0xffc0;
+    pub const DATA_TOUNIT_MAX: u32 = 0xe << 16;
+    pub const SRST_HC: u32 = 1 << 24;
+}
+
+/// Bit fields of the `INTERRUPT` register.
+mod interrupt {
+    pub const CMD_DONE: u32 = 1 << 0;
+    pub const DATA_DONE: u32 = 1 << 1;
+    pub const WRITE_RDY: u32 = 1 << 4;
+    pub const READ_RDY: u32 = 1 << 5;
+    pub const ERR: u32 = 1 << 15;
+    pub const CTO_ERR: u32 = 1 << 16;
+    pub const CCRC_ERR: u32 = 1 << 17;
+    pub const CEND_ERR: u32 = 1 << 18;
+    pub const CBAD_ERR: u32 = 1 << 19;
+    pub const DTO_ERR: u32 = 1 << 20;
+    pub const DCRC_ERR: u32 = 1 << 21;
+    pub const DEND_ERR: u32 = 1 << 22;
+    pub const ACMD_ERR: u32 = 1 << 24;
+    pub const ERROR_MASK: u32 = 0xffff_0000 | ERR;
+}
+
+use self::cmdtm::index;
+
+const CMD_GO_IDLE: u32 = index(0);
+const CMD_ALL_SEND_CID: u32 = index(2) | cmdtm::RSP_136 | cmdtm::CRC_CHECK;
+const CMD_SEND_REL_ADDR: u32 = index(3) | cmdtm::RSP_48 | cmdtm::CRC_CHECK;
+const CMD_CARD_SELECT: u32 = index(7) | cmdtm::RSP_48_BUSY | cmdtm::CRC_CHECK;
+const CMD_SEND_IF_COND: u32 = index(8) | cmdtm::RSP_48 | cmdtm::CRC_CHECK;
+const CMD_SET_BLOCKLEN: u32 = index(16) | cmdtm::RSP_48 | cmdtm::CRC_CHECK;
+const CMD_READ_SINGLE: u32 = index(17) | cmdtm::RSP_48 | cmdtm::CRC_CHECK | cmdtm::DATA | cmdtm::READ;
+const CMD_READ_MULTI: u32 = index(18)
+    | cmdtm::RSP_48
+    | cmdtm::CRC_CHECK
+    | cmdtm::DATA
+    | cmdtm::READ
+    | cmdtm::MULTI_BLOCK
+    | cmdtm::BLKCNT_EN
+    | cmdtm::AUTO_CMD12;
+const CMD_WRITE_SINGLE: u32 = index(24) | cmdtm::RSP_48 | cmdtm::CRC_CHECK | cmdtm::DATA;
+const CMD_WRITE_MULTI: u32 = index(25)
+    | cmdtm::RSP_48
+    | cmdtm::CRC_CHECK
+    | cmdtm::DATA
+    | cmdtm::MULTI_BLOCK
+    | cmdtm::BLKCNT_EN
+    | cmdtm::AUTO_CMD12;
+const CMD_APP_CMD: u32 = index(55) | cmdtm::RSP_48 | cmdtm::CRC_CHECK;
+const ACMD_SET_BUS_WIDTH: u32 = index(6) | cmdtm::RSP_48 | cmdtm::CRC_CHECK | cmdtm::NEED_APP;
+const ACMD_SEND_OP_COND: u32 = index(41) | cmdtm::RSP_48 | cmdtm::NEED_APP;
+
+/// `SEND_IF_COND` argument: 2.7-3.6V and the check pattern `0xAA`.
+const IF_COND_PATTERN: u32 = 0x1AA;
+
+/// `SD_SEND_OP_COND` argument: host supports high capacity cards, 3.2-3.4V.
+const OP_COND_ARG: u32 = (1 << 30) | 0x00ff_8000;
+const OCR_POWERED_UP: u32 = 1 << 31;
+const OCR_HIGH_CAPACITY: u32 = 1 << 30;
+
+/// Errors reported by the EMMC controller or card.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The card did not respond to a command in time.
+    CommandTimeout,
+    /// The response to a command failed its CRC check.
+    CommandCrc,
+    /// The response to a command had an invalid end bit.
+    CommandEndBit,
+    /// The response to a command had the wrong command index.
+    CommandIndex,
+    /// A data transfer timed out on the card's side.
+    DataTimeout,
+    /// Data transferred failed its CRC check.
+    DataCrc,
+    /// Data transferred had an invalid end bit.
+    DataEndBit,
+    /// The automatic `STOP_TRANSMISSION` after a multi-block transfer failed.
+    AutoCmd12,
+    /// Some other error bits were set in the `INTERRUPT` register.
+    Other(u32),
+    /// The controller did not signal completion before the host's deadline.
+    Timeout,
+    /// The card rejected the host's voltage range or check pattern.
+    UnusableCard,
+    /// The block address or buffer size is invalid.
+    InvalidArgument,
+}
+
+impl Error {
+    /// Returns the error described by the error bits in `irpt`.
+    fn from_interrupt(irpt: u32) -> Error {
+        use self::interrupt::*;
+        match irpt {
+            irpt if irpt & CTO_ERR != 0 => Error::CommandTimeout,
+            irpt if irpt & CCRC_ERR != 0 => Error::CommandCrc,
+            irpt if irpt & CEND_ERR != 0 => Error::CommandEndBit,
+            irpt if irpt & CBAD_ERR != 0 => Error::CommandIndex,
+            irpt if irpt & DTO_ERR != 0 => Error::DataTimeout,
+            irpt if irpt & DCRC_ERR != 0 => Error::DataCrc,
+            irpt if irpt & DEND_ERR != 0 => Error::DataEndBit,
+            irpt if irpt & ACMD_ERR != 0 => Error::AutoCmd12,
+            irpt => Error::Other(irpt & ERROR_MASK),
+        }
+    }
+
+    /// Returns `true` if this error is a timeout on either side.
+    pub fn is_timeout(&self) -> bool {
+        match *self {
+            Error::CommandTimeout | Error::DataTimeout | Error::Timeout => true,
+            _ => false,
+        }
+    }
+}
+
+/// Returns the `CONTROL1` clock frequency bits that divide `base` down to at
+/// most `target` Hz using a 10-bit divided clock (`base / (2 * divisor)`).
+fn clock_divider(base: u32, target: u32) -> u32 {
+    let divisor = (base + 2 * target - 1) / (2 * target);
+    let divisor = if divisor > 0x3ff { 0x3ff } else { divisor };
+    ((divisor & 0xff) << 8) | (((divisor >> 8) & 0b11) << 6)
+}
+
+/// The Raspberry Pi's EMMC controller driving the SD card slot.
+pub struct Emmc {
+    registers: &'static mut Registers,
+    rca: u32,
+    high_capacity: bool,
+    /// Returns the current time in microseconds.
+    clock: fn() -> u64,
+    /// Whether clearing `INTERRUPT` leaves its bits set, as if the card
+    /// raised them again right away. Register mocks are plain memory and
+    /// cannot model write-1-to-clear.
+    sticky_interrupts: bool,
+}
+
+/// A clock for `Emmc::new_test()` that advances by a millisecond every time
+/// it is read, so that waits for a bit the test never sets time out.
+fn test_clock() -> u64 {
+    static TICKS: AtomicUsize = AtomicUsize::new(0);
+    TICKS.fetch_add(1, Ordering::Relaxed) as u64 * 1000
+}
+
+impl Emmc {
+    /// Resets the EMMC controller and identifies the inserted SD card: the
+    /// card is reset, its operating conditions are negotiated, it is given a
+    /// relative address, selected, and switched to a 4-bit bus at 25MHz.
+    ///
+    /// The firmware leaves GPIO pins 48-53 configured for the SD card, so
+    /// they are not touched.
+    pub fn new() -> Result<Emmc, Error> {
+        let mut emmc = Emmc::from_registers(EMMC_BASE as *mut u32);
+        emmc.initialize()?;
+        Ok(emmc)
+    }
+
+    /// Returns a handle to an EMMC controller whose registers live at
+    /// `stack_ptr` for testing. No card identification is performed and the
+    /// card is assumed to be high capacity. Time is read from a fake clock,
+    /// and bits set in `INTERRUPT` stay set until the test clears them.
+    pub fn new_test(stack_ptr: *mut u32) -> Emmc {
+        let mut emmc = Emmc::from_registers(stack_ptr);
+        emmc.high_capacity = true;
+        emmc.clock = test_clock;
+        emmc.sticky_interrupts = true;
+        emmc
+    }
+
+    fn from_registers(ptr: *mut u32) -> Emmc {
+        Emmc {
+            registers: unsafe { &mut *(ptr as *mut Registers) },
+            rca: 0,
+            high_capacity: false,
+            clock: timer::current_time,
+            sticky_interrupts: false,
+        }
+    }
+
+    /// Returns `true` if the card is block (rather than byte) addressed.
+    pub fn is_high_capacity(&self) -> bool {
+        self.high_capacity
+    }
+
+    fn initialize(&mut self) -> Result<(), Error> {
+        self.reset()?;
+        self.set_clock(IDENTIFICATION_CLOCK)?;
+
+        // Report every status bit in `INTERRUPT` but never raise an IRQ.
+        self.registers.IRPT_EN.write(0);
+        self.registers.IRPT_MASK.write(0xffff_ffff);
+
+        self.command(CMD_GO_IDLE, 0)?;
+        let v2 = match self.command(CMD_SEND_IF_COND, IF_COND_PATTERN) {
+            Ok(resp) if resp & 0xfff == IF_COND_PATTERN => true,
+            Ok(_) => return Err(Error::UnusableCard),
+            Err(Error::CommandTimeout) => false,
+            Err(e) => return Err(e),
+        };
+
+        let arg = if v2 { OP_COND_ARG } else { OP_COND_ARG & !OCR_HIGH_CAPACITY };
+        let deadline = (self.clock)() + OP_COND_TIMEOUT;
+        let ocr = loop {
+            let ocr = self.command(ACMD_SEND_OP_COND, arg)?;
+            if ocr & OCR_POWERED_UP != 0 {
+                break ocr;
+            } else if (self.clock)() > deadline {
+                return Err(Error::Timeout);
+            }
+
+            timer::spin_sleep_us(400);
+        };
+        self.high_capacity = ocr & OCR_HIGH_CAPACITY != 0;
+
+        self.command(CMD_ALL_SEND_CID, 0)?;
+        self.rca = self.command(CMD_SEND_REL_ADDR, 0)? & 0xffff_0000;
+
+        self.set_clock(TRANSFER_CLOCK)?;
+        self.command(CMD_CARD_SELECT, self.rca)?;
+
+        if !self.high_capacity {
+            self.command(CMD_SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+        }
+
+        self.command(ACMD_SET_BUS_WIDTH, 0b10)?;
+        self.registers.CONTROL0.or_mask(control0::HCTL_DWIDTH);
+        Ok(())
+    }
+
+    /// Resets the host controller and enables its internal clock.
+    fn reset(&mut self) -> Result<(), Error> {
+        self.registers.CONTROL0.write(0);
+        self.registers.CONTROL1.or_mask(control1::SRST_HC);
+        self.wait_for(RESET_TIMEOUT, |regs| regs.CONTROL1.read() & control1::SRST_HC == 0)?;
+
+        self.registers
+            .CONTROL1
+            .or_mask(control1::CLK_INTLEN | control1::DATA_TOUNIT_MAX);
+        timer::spin_sleep_us(10);
+        Ok(())
+    }
+
+    /// Switches the SD clock to at most `hz` Hz.
+    fn set_clock(&mut self, hz: u32) -> Result<(), Error> {
+        let inhibit = status::CMD_INHIBIT | status::DAT_INHIBIT;
+        self.wait_for(COMMAND_TIMEOUT, |regs| regs.STATUS.read() & inhibit == 0)?;
+
+        self.registers.CONTROL1.and_mask(!control1::CLK_EN);
+        timer::spin_sleep_us(10);
+
+        let control1 = self.registers.CONTROL1.read() & !control1::CLK_FREQ_MASK;
+        self.registers
+            .CONTROL1
+            .write(control1 | clock_divider(BASE_CLOCK, hz));
+        timer::spin_sleep_us(10);
+
+        self.registers.CONTROL1.or_mask(control1::CLK_EN);
+        self.wait_for(RESET_TIMEOUT, |regs| regs.CONTROL1.read() & control1::CLK_STABLE != 0)
+    }
+
+    /// Spins until `done` returns `true`, for at most `timeout` microseconds.
+    ///
+    /// The timer is only consulted once `done` has returned `false`.
+    fn wait_for<F>(&self, timeout: u64, done: F) -> Result<(), Error>
+    where
+        F: Fn(&Registers) -> bool,
+    {
+        let mut deadline = None;
+        loop {
+            if done(&*self.registers) {
+                return Ok(());
+            }
+
+            let deadline = *deadline.get_or_insert_with(|| (self.clock)() + timeout);
+            if (self.clock)() > deadline {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    /// Waits until every bit in `mask` is set in `INTERRUPT`, then clears
+    /// them. Any error bit aborts the wait and is reported as an `Error`.
+    fn wait_interrupt(&mut self, mask: u32, timeout: u64) -> Result<(), Error> {
+        let mut deadline = None;
+        loop {
+            let irpt = self.registers.INTERRUPT.read();
+            if irpt & interrupt::ERROR_MASK != 0 {
+                self.clear_interrupts(irpt);
+                return Err(Error::from_interrupt(irpt));
+            } else if irpt & mask == mask {
+                self.clear_interrupts(mask);
+                return Ok(());
+            }
+
+            let deadline = *deadline.get_or_insert_with(|| (self.clock)() + timeout);
+            if (self.clock)() > deadline {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    /// Clears the bits in `mask` in `INTERRUPT`, which are write-1-to-clear.
+    fn clear_interrupts(&mut self, mask: u32) {
+        if !self.sticky_interrupts {
+            self.registers.INTERRUPT.write(mask);
+        }
+    }
+
+    /// Issues the command `cmd` with argument `arg`, preceded by `APP_CMD` if
+    /// `cmd` is an application specific command. Returns the first word of
+    /// the response.
+    fn command(&mut self, cmd: u32, arg: u32) -> Result<u32, Error> {
+        if cmd & cmdtm::NEED_APP != 0 {
+            let rca = self.rca;
+            self.command(CMD_APP_CMD, rca)?;
+        }
+
+        self.wait_for(COMMAND_TIMEOUT, |regs| regs.STATUS.read() & status::CMD_INHIBIT == 0)?;
+
+        let pending = self.registers.INTERRUPT.read();
+        self.clear_interrupts(pending);
+        self.registers.ARG1.write(arg);
+        self.registers.CMDTM.write(cmd & !cmdtm::NEED_APP);
+
+        self.wait_interrupt(interrupt::CMD_DONE, COMMAND_TIMEOUT)?;
+        Ok(self.registers.RESP[0].read())
+    }
+
+    /// Prepares a transfer of the blocks starting at `block` into or out of a
+    /// buffer of `len` bytes and returns the number of blocks and the command
+    /// argument.
+    fn setup_transfer(&mut self, block: u32, len: usize) -> Result<(u32, u32), Error> {
+        let count = len / BLOCK_SIZE;
+        if count == 0 || count > 0xffff || len % BLOCK_SIZE != 0 {
+            return Err(Error::InvalidArgument);
+        }
+
+        let arg = if self.high_capacity {
+            block
+        } else {
+            block.checked_mul(BLOCK_SIZE as u32).ok_or(Error::InvalidArgument)?
+        };
+
+        let inhibit = status::DAT_INHIBIT;
+        self.wait_for(DATA_TIMEOUT, |regs| regs.STATUS.read() & inhibit == 0)?;
+        self.registers
+            .BLKSIZECNT
+            .write(((count as u32) << 16) | BLOCK_SIZE as u32);
+        Ok((count as u32, arg))
+    }
+
+    /// Reads `buf.len() / 512` consecutive blocks starting at block `block`
+    /// into `buf` using `READ_SINGLE_BLOCK` (CMD17) or `READ_MULTIPLE_BLOCK`
+    /// (CMD18).
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `buf` is empty, not a multiple of
+    /// the block size or longer than 65535 blocks.
+    pub fn read_blocks(&mut self, block: u32, buf: &mut [u8]) -> Result<(), Error> {
+        let (count, arg) = self.setup_transfer(block, buf.len())?;
+        let cmd = if count == 1 { CMD_READ_SINGLE } else { CMD_READ_MULTI };
+        self.command(cmd, arg)?;
+
+        for chunk in buf.chunks_mut(BLOCK_SIZE) {
+            self.wait_interrupt(interrupt::READ_RDY, DATA_TIMEOUT)?;
+            for word in chunk.chunks_mut(4) {
+                let data = self.registers.DATA.read();
+                word[0] = data as u8;
+                word[1] = (data >> 8) as u8;
+                word[2] = (data >> 16) as u8;
+                word[3] = (data >> 24) as u8;
+            }
+        }
+
+        self.wait_interrupt(interrupt::DATA_DONE, DATA_TIMEOUT)
+    }
+
+    /// Writes `buf` to `buf.len() / 512` consecutive blocks starting at block
+    /// `block` using `WRITE_BLOCK` (CMD24) or `WRITE_MULTIPLE_BLOCK` (CMD25).
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidArgument` if `buf` is empty, not a multiple of
+    /// the block size or longer than 65535 blocks.
+    pub fn write_blocks(&mut self, block: u32, buf: &[u8]) -> Result<(), Error> {
+        let (count, arg) = self.setup_transfer(block, buf.len())?;
+        let cmd = if count == 1 { CMD_WRITE_SINGLE } else { CMD_WRITE_MULTI };
+        self.command(cmd, arg)?;
+
+        for chunk in buf.chunks(BLOCK_SIZE) {
+            self.wait_interrupt(interrupt::WRITE_RDY, DATA_TIMEOUT)?;
+            for word in chunk.chunks(4) {
+                let data = (word[0] as u32)
+                    | ((word[1] as u32) << 8)
+                    | ((word[2] as u32) << 16)
+                    | ((word[3] as u32) << 24);
+                self.registers.DATA.write(data);
+            }
+        }
+
+        self.wait_interrupt(interrupt::DATA_DONE, DATA_TIMEOUT)
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ARG1: usize = 2;
+    const CMDTM: usize = 3;
+    const RESP0: usize = 4;
+    const DATA: usize = 8;
+    const INTERRUPT: usize = 12;
+
+    const READY: u32 = interrupt::CMD_DONE
+        | interrupt::DATA_DONE
+        | interrupt::READ_RDY
+        | interrupt::WRITE_RDY;
+
+    #[test]
+    fn register_layout() {
+        assert_eq!(::std::mem::size_of::<Registers>(), 0x100);
+    }
+
+    #[test]
+    fn clock_dividers() {
+        // 41.67MHz / (2 * 53) ~= 393KHz
+        assert_eq!(clock_divider(BASE_CLOCK, 400_000), 53 << 8);
+        // 41.67MHz / (2 * 1) ~= 20.8MHz
+        assert_eq!(clock_divider(BASE_CLOCK, 25_000_000), 1 << 8);
+        // divisor 0x3ff is split across bits [15:8] and [7:6]
+        assert_eq!(clock_divider(BASE_CLOCK, 1), (0xff << 8) | (0b11 << 6));
+    }
+
+    #[test]
+    fn command_writes_registers() {
+        let mut registers = [0u32; 64];
+        registers[INTERRUPT] = interrupt::CMD_DONE;
+        registers[RESP0] = 0x1AA;
+
+        let mut emmc = Emmc::new_test(&mut registers[0] as *mut u32);
+        assert_eq!(emmc.command(CMD_SEND_IF_COND, IF_COND_PATTERN), Ok(0x1AA));
+        assert_eq!(registers[ARG1], IF_COND_PATTERN);
+        assert_eq!(registers[CMDTM], 0x080a0000);
+    }
+
+    #[test]
+    fn command_errors() {
+        let mut registers = [0u32; 64];
+        registers[INTERRUPT] = interrupt::ERR | interrupt::CTO_ERR;
+
+        let mut emmc = Emmc::new_test(&mut registers[0] as *mut u32);
+        assert_eq!(emmc.command(CMD_GO_IDLE, 0), Err(Error::CommandTimeout));
+
+        registers[INTERRUPT] = interrupt::ERR | interrupt::DCRC_ERR;
+        assert_eq!(emmc.command(CMD_GO_IDLE, 0), Err(Error::DataCrc));
+        assert!(Error::CommandTimeout.is_timeout());
+        assert!(!Error::DataCrc.is_timeout());
+    }
+
+    #[test]
+    fn missing_interrupt_times_out() {
+        let mut registers = [0u32; 64];
+        let mut emmc = Emmc::new_test(&mut registers[0] as *mut u32);
+        assert_eq!(emmc.command(CMD_GO_IDLE, 0), Err(Error::Timeout));
+
+        registers[INTERRUPT] = interrupt::CMD_DONE;
+        let mut buf = [0u8; BLOCK_SIZE];
+        assert_eq!(emmc.read_blocks(0, &mut buf), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn read_blocks() {
+        let mut registers = [0u32; 64];
+        registers[INTERRUPT] = READY;
+        registers[DATA] = 0x04030201;
+
+        let mut emmc = Emmc::new_test(&mut registers[0] as *mut u32);
+        let mut buf = [0u8; 2 * BLOCK_SIZE];
+        emmc.read_blocks(7, &mut buf).expect("read");
+
+        assert_eq!(registers[ARG1], 7);
+        assert_eq!(registers[CMDTM], CMD_READ_MULTI);
+        assert_eq!(registers[1], (2 << 16) | 512);
+        for word in buf.chunks(4) {
+            assert_eq!(word, &[1, 2, 3, 4]);
+        }
+
+        let mut small = [0u8; 100];
+        assert_eq!(emmc.read_blocks(0, &mut small), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn write_blocks() {
+        let mut registers = [0u32; 64];
+        registers[INTERRUPT] = READY;
+
+        let mut emmc = Emmc::new_test(&mut registers[0] as *mut u32);
+        let mut buf = [0u8; BLOCK_SIZE];
+        buf[508..].copy_from_slice(&[0xef, 0xbe, 0xad, 0xde]);
+        emmc.write_blocks(3, &buf).expect("write");
+
+        assert_eq!(registers[ARG1], 3);
+        assert_eq!(registers[CMDTM], CMD_WRITE_SINGLE);
+        assert_eq!(registers[DATA], 0xdeadbeef);
+    }
+}
@@ -0,0 +1,159 @@
+/// The bit of `CNT*_CTL_EL0` that enables the timer.
+const CTL_ENABLE: u64 = 1 << 0;
+/// The bit of `CNT*_CTL_EL0` that masks the timer's interrupt.
+const CTL_IMASK: u64 = 1 << 1;
+/// The bit of `CNT*_CTL_EL0` that is set while the timer's condition is met.
+const CTL_ISTATUS: u64 = 1 << 2;
+
+/// One of the calling core's ARM generic timers. Each core has its own, and
+/// each raises its own interrupt on that core through the local interrupt
+/// controller. `init.S` gives EL1 and EL0 access to the physical timer and
+/// zeroes the virtual offset, so both count the same.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Timer {
+    /// The EL1 physical timer, `CNTP`.
+    Physical,
+    /// The virtual timer, `CNTV`.
+    Virtual,
+}
+
+impl Timer {
+    /// Returns the timer's counter.
+    pub fn counter(self) -> u64 {
+        let count: u64;
+        unsafe {
+            // The counter can be read ahead of earlier instructions.
+            match self {
+                Timer::Physical => asm!("isb
+                                         mrs $0, CNTPCT_EL0"
+                                        : "=r"(count) ::: "volatile"),
+                Timer::Virtual => asm!("isb
+                                        mrs $0, CNTVCT_EL0"
+                                       : "=r"(count) ::: "volatile"),
+            }
+        }
+        count
+    }
+
+    fn control(self) -> u64 {
+        let ctl: u64;
+        unsafe {
+            match self {
+                Timer::Physical => asm!("mrs $0, CNTP_CTL_EL0" : "=r"(ctl) ::: "volatile"),
+                Timer::Virtual => asm!("mrs $0, CNTV_CTL_EL0" : "=r"(ctl) ::: "volatile"),
+            }
+        }
+        ctl
+    }
+
+    fn set_control(self, ctl: u64) {
+        unsafe {
+            match self {
+                Timer::Physical => asm!("msr CNTP_CTL_EL0, $0
+                                         isb"
+                                        :: "r"(ctl) :: "volatile"),
+                Timer::Virtual => asm!("msr CNTV_CTL_EL0, $0
+                                        isb"
+                                       :: "r"(ctl) :: "volatile"),
+            }
+        }
+    }
+
+    /// Sets the timer to fire once its counter reaches `value` (`CVAL`).
+    /// Unlike the system timer's compare registers, a value that has already
+    /// passed fires right away.
+    pub fn set_compare(self, value: u64) {
+        unsafe {
+            match self {
+                Timer::Physical => asm!("msr CNTP_CVAL_EL0, $0" :: "r"(value) :: "volatile"),
+                Timer::Virtual => asm!("msr CNTV_CVAL_EL0, $0" :: "r"(value) :: "volatile"),
+            }
+        }
+    }
+
+    /// Sets the timer to fire `ticks` counter ticks from now (`TVAL`).
+    /// `TVAL` is signed, so `ticks` is capped at `i32::MAX`.
+    pub fn set_countdown(self, ticks: u32) {
+        let tval = ::core::cmp::min(ticks, i32::max_value() as u32) as u64;
+        unsafe {
+            match self {
+                Timer::Physical => asm!("msr CNTP_TVAL_EL0, $0" :: "r"(tval) :: "volatile"),
+                Timer::Virtual => asm!("msr CNTV_TVAL_EL0, $0" :: "r"(tval) :: "volatile"),
+            }
+        }
+    }
+
+    /// Enables the timer with its interrupt unmasked.
+    pub fn enable(self) {
+        self.set_control(CTL_ENABLE);
+    }
+
+    /// Disables the timer, which also deasserts its interrupt.
+    pub fn disable(self) {
+        self.set_control(0);
+    }
+
+    /// Masks or unmasks the timer's interrupt without stopping it.
+    pub fn set_masked(self, masked: bool) {
+        let ctl = self.control() & CTL_ENABLE;
+        self.set_control(if masked { ctl | CTL_IMASK } else { ctl });
+    }
+
+    /// Returns `true` if the timer is enabled and has fired.
+    pub fn is_pending(self) -> bool {
+        let ctl = self.control();
+        ctl & CTL_ENABLE != 0 && ctl & CTL_ISTATUS != 0
+    }
+
+    /// Sets the timer to fire `us` microseconds from now and enables it.
+    pub fn tick_in(self, us: u64) {
+        let deadline = self.counter().saturating_add(us_to_ticks(us));
+        self.set_compare(deadline);
+        self.enable();
+    }
+}
+
+/// Returns the frequency of the generic timers' counter in Hz, as set by the
+/// firmware (`CNTFRQ_EL0`). It is 19.2MHz on the Raspberry Pi 3.
+pub fn frequency() -> u64 {
+    let frequency: u64;
+    unsafe { asm!("mrs $0, CNTFRQ_EL0" : "=r"(frequency) ::: "volatile") };
+    frequency
+}
+
+/// Converts `us` microseconds to ticks of a counter of `frequency` Hz,
+/// rounding down and saturating.
+fn ticks_at(frequency: u64, us: u64) -> u64 {
+    (us / 1_000_000)
+        .saturating_mul(frequency)
+        .saturating_add((us % 1_000_000) * frequency / 1_000_000)
+}
+
+/// Converts `us` microseconds to counter ticks.
+pub fn us_to_ticks(us: u64) -> u64 {
+    ticks_at(frequency(), us)
+}
+
+/// Converts counter ticks to microseconds, rounding down.
+pub fn ticks_to_us(ticks: u64) -> u64 {
+    let frequency = frequency();
+    (ticks / frequency) * 1_000_000 + (ticks % frequency) * 1_000_000 / frequency
+}
+
+/// Returns the physical counter converted to microseconds.
+pub fn current_time() -> u64 {
+    ticks_to_us(Timer::Physical.counter())
+}
+
+#[cfg(feature = "std")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversion_saturates() {
+        assert_eq!(ticks_at(19_200_000, 1), 19);
+        assert_eq!(ticks_at(19_200_000, 1_500_000), 28_800_000);
+        assert_eq!(ticks_at(19_200_000, u64::max_value()), u64::max_value());
+    }
+}
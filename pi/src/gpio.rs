@@ -17,6 +17,39 @@ pub enum Function {
     Alt5 = 0b010,
 }
 
+/// An event that a GPIO input pin can detect. Detected events are recorded in
+/// the pin's event status and raise the GPIO interrupt of the pin's bank.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A rising edge, sampled with the system clock.
+    RisingEdge,
+    /// A falling edge, sampled with the system clock.
+    FallingEdge,
+    /// A high level. The event is detected again as soon as it is cleared
+    /// while the level is high.
+    HighLevel,
+    /// A low level. The event is detected again as soon as it is cleared
+    /// while the level is low.
+    LowLevel,
+    /// A rising edge, detected without sampling, so even very short pulses
+    /// are seen.
+    AsyncRisingEdge,
+    /// A falling edge, detected without sampling.
+    AsyncFallingEdge,
+}
+
+/// The pull-up/down resistor setting of a GPIO pin.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Pull {
+    None = 0b00,
+    Down = 0b01,
+    Up = 0b10,
+}
+
+/// The number of GPIO pins.
+pub const PIN_COUNT: usize = 54;
+
 #[repr(C)]
 #[allow(non_snake_case)]
 #[derive(Debug)]
@@ -90,21 +123,65 @@ impl<T> Gpio<T> {
     fn pin_mask(&self) -> u32 {
         1 << ((self.pin as u32) % 32)
     }
+
+    /// Returns this pin's number.
+    pub fn pin(&self) -> u8 {
+        self.pin
+    }
+
+    /// Sets this pin's pull-up/down resistor to `pull`. The setting is kept
+    /// across function changes and is usable in any state.
+    pub fn set_pull(&mut self, pull: Pull) {
+        let index = self.pin_index();
+        let mask = self.pin_mask();
+
+        // The sequence from page 101 of the BCM2837 documentation: set the
+        // control signal, wait for it to settle, clock it into the pin, wait
+        // again, then remove both.
+        self.registers.PUD.write(pull as u32);
+        wait_cycles(150);
+        self.registers.PUDCLK[index].write(mask);
+        wait_cycles(150);
+        self.registers.PUD.write(0);
+        self.registers.PUDCLK[index].write(0);
+    }
+
+    /// Returns this pin to the `Uninitialized` state, making it an input, its
+    /// reset function, so that it can be configured anew.
+    pub fn into_uninitialized(self) -> Gpio<Uninitialized> {
+        let mut gpio: Gpio<Uninitialized> = self.transition();
+        gpio.write_fsel(Function::Input);
+        gpio
+    }
+}
+
+/// Spins for at least `cycles` CPU cycles.
+#[inline]
+fn wait_cycles(cycles: u32) {
+    for _ in 0..cycles {
+        unsafe { asm!("nop" :::: "volatile") }
+    }
 }
 
 impl Gpio<Uninitialized> {
-    /// Returns a new `GPIO` structure for pin number `pin`.
+    /// Returns a new `GPIO` structure for pin number `pin`. Drivers should
+    /// take their pins from a `GpioBank` instead.
+    ///
+    /// # Safety
+    ///
+    /// No other `Gpio` for pin `pin` may be in use, including one taken from
+    /// a `GpioBank`.
     ///
     /// # Panics
     ///
     /// Panics if `pin` > `53`.
-    pub fn new(pin: u8) -> Gpio<Uninitialized> {
+    pub unsafe fn new(pin: u8) -> Gpio<Uninitialized> {
         if pin > 53 {
             panic!("Gpio::new(): pin {} exceeds maximum of 53", pin);
         }
 
         Gpio {
-            registers: unsafe { &mut *(GPIO_BASE as *mut Registers) },
+            registers: &mut *(GPIO_BASE as *mut Registers),
             pin: pin,
             _state: PhantomData,
         }
@@ -196,22 +273,142 @@ impl Gpio<Input> {
         let index = self.pin_index();
         let mask = self.pin_mask();
 
-        (self.registers.LEV[index].read() & mask) == 0
+        (self.registers.LEV[index].read() & mask) != 0
+    }
+}
+
+impl Gpio<Input> {
+    fn event_enable(&mut self, event: Event) -> &mut Volatile<u32> {
+        let index = self.pin_index();
+        match event {
+            Event::RisingEdge => &mut self.registers.REN[index],
+            Event::FallingEdge => &mut self.registers.FEN[index],
+            Event::HighLevel => &mut self.registers.HEN[index],
+            Event::LowLevel => &mut self.registers.LEN[index],
+            Event::AsyncRisingEdge => &mut self.registers.AREN[index],
+            Event::AsyncFallingEdge => &mut self.registers.AFEN[index],
+        }
+    }
+
+    /// Arms detection of `event` on this pin.
+    pub fn enable_event(&mut self, event: Event) {
+        let mask = self.pin_mask();
+        self.event_enable(event).or_mask(mask);
+    }
+
+    /// Disarms detection of `event` on this pin. An event that was already
+    /// detected stays recorded until it is cleared.
+    pub fn disable_event(&mut self, event: Event) {
+        let mask = self.pin_mask();
+        self.event_enable(event).and_mask(!mask);
+    }
+
+    /// Disarms detection of every event on this pin.
+    pub fn disable_events(&mut self) {
+        for &event in [
+            Event::RisingEdge,
+            Event::FallingEdge,
+            Event::HighLevel,
+            Event::LowLevel,
+            Event::AsyncRisingEdge,
+            Event::AsyncFallingEdge,
+        ].iter()
+        {
+            self.disable_event(event);
+        }
+    }
+
+    /// Returns `true` if an armed event was detected on this pin since its
+    /// event status was last cleared.
+    pub fn event_detected(&self) -> bool {
+        let index = self.pin_index();
+        let mask = self.pin_mask();
+
+        (self.registers.EDS[index].read() & mask) != 0
+    }
+
+    /// Clears this pin's event status.
+    pub fn clear_event(&mut self) {
+        let index = self.pin_index();
+        let mask = self.pin_mask();
+
+        // Writing a `1` clears the status; the other pins' are left alone.
+        self.registers.EDS[index].write(mask);
     }
 }
 
+/// Returns a bit mask of the pins on which an event was detected; bit `n` is
+/// set for pin `n`.
+pub fn detected_events() -> u64 {
+    let registers = unsafe { &*(GPIO_BASE as *const Registers) };
+    (registers.EDS[0].read() as u64) | ((registers.EDS[1].read() as u64) << 32)
+}
+
+/// Clears the event status of the pins in the bit mask `pins`.
+pub fn clear_events(pins: u64) {
+    let registers = unsafe { &mut *(GPIO_BASE as *mut Registers) };
+    registers.EDS[0].write(pins as u32);
+    registers.EDS[1].write((pins >> 32) as u32);
+}
+
 impl Gpio<Alt> {
+    /// Disables this pin's pull-up/down resistor. Equivalent to
+    /// `set_pull(Pull::None)`.
     pub fn disable_pull_up_down(&mut self) {
-        self.registers.PUD.write(0);
-        for _ in 0..150 {
-            unsafe { asm!("nop" :::: "volatile") }
+        self.set_pull(Pull::None);
+    }
+}
+
+/// The owner of the GPIO pins, which hands out each pin at most once so that
+/// two drivers cannot both configure the same pin.
+pub struct GpioBank {
+    registers: usize,
+    taken: u64,
+}
+
+impl GpioBank {
+    /// Returns a bank that owns every pin.
+    pub const fn new() -> GpioBank {
+        GpioBank {
+            registers: GPIO_BASE,
+            taken: 0,
+        }
+    }
+
+    /// Like `new()`, but for the registers at `stack_ptr`. See
+    /// `Gpio::new_test()`.
+    pub fn new_test(stack_ptr: *mut u32) -> GpioBank {
+        GpioBank {
+            registers: stack_ptr as usize,
+            taken: 0,
         }
-        let pin_mask = self.pin_mask();
-        self.registers.PUDCLK[0].or_mask(pin_mask);
-        for _ in 0..150 {
-            unsafe { asm!("nop" :::: "volatile") }
+    }
+
+    /// Takes pin number `pin` from the bank. Returns `None` if `pin` is not a
+    /// GPIO pin or was already taken and not released since.
+    pub fn take(&mut self, pin: u8) -> Option<Gpio<Uninitialized>> {
+        if pin as usize >= PIN_COUNT || self.taken & (1 << pin) != 0 {
+            return None;
         }
-        self.registers.PUDCLK[0].write(0);
+
+        self.taken |= 1 << pin;
+        Some(Gpio {
+            registers: unsafe { &mut *(self.registers as *mut Registers) },
+            pin: pin,
+            _state: PhantomData,
+        })
+    }
+
+    /// Returns `gpio`, in any state, to the bank so it can be taken again.
+    /// The pin is reset to an input first.
+    pub fn release<T>(&mut self, gpio: Gpio<T>) {
+        let gpio = gpio.into_uninitialized();
+        self.taken &= !(1 << gpio.pin);
+    }
+
+    /// Returns `true` if pin number `pin` is currently taken.
+    pub fn is_taken(&self, pin: u8) -> bool {
+        pin as usize >= PIN_COUNT || self.taken & (1 << pin) != 0
     }
 }
 
@@ -233,4 +430,78 @@ mod tests {
         gpio.clear();
         assert_eq!(registers[10], 0x00010000);
     }
+
+    #[test]
+    fn events() {
+        let mut registers: [u32; 41] = [0; 41];
+        let ptr: *mut u32 = &mut registers[0] as *mut u32;
+
+        let mut gpio = Gpio::new_test(ptr, 35).into_input();
+        gpio.enable_event(Event::RisingEdge);
+        gpio.enable_event(Event::AsyncFallingEdge);
+        assert_eq!(registers[20], 1 << 3);
+        assert_eq!(registers[35], 1 << 3);
+
+        gpio.disable_event(Event::RisingEdge);
+        assert_eq!(registers[20], 0);
+        assert_eq!(registers[35], 1 << 3);
+
+        gpio.disable_events();
+        assert_eq!(registers[35], 0);
+
+        registers[17] = 1 << 3;
+        assert!(gpio.event_detected());
+
+        // The status is cleared by writing a `1` to it.
+        registers[17] = 0;
+        gpio.clear_event();
+        assert_eq!(registers[17], 1 << 3);
+    }
+
+    #[test]
+    fn level() {
+        let mut registers: [u32; 41] = [0; 41];
+        let ptr: *mut u32 = &mut registers[0] as *mut u32;
+
+        let mut gpio = Gpio::new_test(ptr, 40).into_input();
+        assert!(!gpio.level());
+        registers[14] = 1 << 8;
+        assert!(gpio.level());
+    }
+
+    #[test]
+    fn pull_high_bank() {
+        let mut registers: [u32; 41] = [0; 41];
+        let ptr: *mut u32 = &mut registers[0] as *mut u32;
+
+        // The control signals are removed once the pull is set; clocking it
+        // into pin 40 must not disturb `PUDCLK[0]`.
+        registers[38] = 0xdead;
+        let mut gpio = Gpio::new_test(ptr, 40).into_output();
+        gpio.set_pull(Pull::Up);
+        assert_eq!(registers[37], 0);
+        assert_eq!(registers[38], 0xdead);
+        assert_eq!(registers[39], 0);
+
+        assert_eq!(registers[4], 0b001);
+        let gpio = gpio.into_uninitialized();
+        assert_eq!(gpio.pin(), 40);
+        assert_eq!(registers[4], 0);
+    }
+
+    #[test]
+    fn bank_hands_out_pins_once() {
+        let mut registers: [u32; 41] = [0; 41];
+        let mut bank = GpioBank::new_test(&mut registers[0] as *mut u32);
+
+        let gpio = bank.take(14).expect("pin 14");
+        assert!(bank.take(14).is_none());
+        assert!(bank.take(54).is_none());
+        assert!(bank.is_taken(14));
+
+        let gpio = gpio.into_output();
+        bank.release(gpio);
+        assert!(!bank.is_taken(14));
+        assert!(bank.take(14).is_some());
+    }
 }
@@ -4,17 +4,94 @@ use volatile::{ReadVolatile, Volatile};
 
 const INT_BASE: usize = IO_BASE + 0xB000 + 0x200;
 
+/// An interrupt source. Sources `0` through `63` are the GPU peripherals'
+/// interrupts; sources `64` through `71` are the ARM-local ones reported in
+/// the basic registers, numbered as `FIQ_CONTROL` numbers them.
 #[repr(u8)]
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Interrupt {
+    Timer0 = 0,
     Timer1 = 1,
+    Timer2 = 2,
     Timer3 = 3,
     Usb = 9,
+    /// The auxiliary peripherals, among them the mini UART.
+    Aux = 29,
+    I2cSpiSlave = 43,
+    Pwa0 = 45,
+    Pwa1 = 46,
+    Smi = 48,
     Gpio0 = 49,
     Gpio1 = 50,
     Gpio2 = 51,
     Gpio3 = 52,
+    I2c = 53,
+    Spi = 54,
+    Pcm = 55,
+    /// The PL011 UART.
     Uart = 57,
+    Emmc = 62,
+    ArmTimer = 64,
+    ArmMailbox = 65,
+    ArmDoorbell0 = 66,
+    ArmDoorbell1 = 67,
+    GpuHalted0 = 68,
+    GpuHalted1 = 69,
+    IllegalAccess1 = 70,
+    IllegalAccess0 = 71,
+}
+
+/// The number of interrupt sources.
+pub const MAX_INTERRUPTS: usize = 72;
+
+/// The first ARM-local interrupt source.
+const BASIC_BASE: u8 = 64;
+
+/// The bit of `FIQ_CONTROL` that enables the FIQ.
+const FIQ_ENABLE: u32 = 1 << 7;
+
+impl Interrupt {
+    /// Returns the interrupt with source number `number`, if it is one this
+    /// module knows of.
+    pub fn from_number(number: u8) -> Option<Interrupt> {
+        use self::Interrupt::*;
+
+        Some(match number {
+            0 => Timer0,
+            1 => Timer1,
+            2 => Timer2,
+            3 => Timer3,
+            9 => Usb,
+            29 => Aux,
+            43 => I2cSpiSlave,
+            45 => Pwa0,
+            46 => Pwa1,
+            48 => Smi,
+            49 => Gpio0,
+            50 => Gpio1,
+            51 => Gpio2,
+            52 => Gpio3,
+            53 => I2c,
+            54 => Spi,
+            55 => Pcm,
+            57 => Uart,
+            62 => Emmc,
+            64 => ArmTimer,
+            65 => ArmMailbox,
+            66 => ArmDoorbell0,
+            67 => ArmDoorbell1,
+            68 => GpuHalted0,
+            69 => GpuHalted1,
+            70 => IllegalAccess1,
+            71 => IllegalAccess0,
+            _ => return None,
+        })
+    }
+
+    /// Returns `true` if this is an ARM-local interrupt.
+    fn is_basic(self) -> bool {
+        self as u8 >= BASIC_BASE
+    }
 }
 
 #[repr(C)]
@@ -29,8 +106,49 @@ struct Registers {
     DISABLE_BASIC_IRQS: Volatile<u32>,
 }
 
-/// An interrupt controller. Used to enable and disable interrupts as well as to
-/// check if an interrupt is pending.
+/// An iterator over pending interrupts in order of their source numbers.
+/// Pending sources this module does not know of are skipped.
+#[derive(Debug, Clone)]
+pub struct Pending {
+    gpu: u64,
+    basic: u8,
+}
+
+impl Pending {
+    /// Returns an iterator over the GPU sources set in `gpu` and the
+    /// ARM-local sources set in `basic`.
+    fn new(gpu: u64, basic: u8) -> Pending {
+        Pending { gpu, basic }
+    }
+}
+
+impl Iterator for Pending {
+    type Item = Interrupt;
+
+    fn next(&mut self) -> Option<Interrupt> {
+        loop {
+            let number = if self.gpu != 0 {
+                let bit = self.gpu.trailing_zeros();
+                self.gpu &= !(1 << bit);
+                bit as u8
+            } else if self.basic != 0 {
+                let bit = self.basic.trailing_zeros();
+                self.basic &= !(1 << bit);
+                BASIC_BASE + bit as u8
+            } else {
+                return None;
+            };
+
+            if let Some(int) = Interrupt::from_number(number) {
+                return Some(int);
+            }
+        }
+    }
+}
+
+/// An interrupt controller. Used to enable and disable interrupts, to check
+/// which interrupts are pending, and to select the interrupt delivered as an
+/// FIQ.
 pub struct Controller {
     registers: &'static mut Registers,
 }
@@ -50,19 +168,100 @@ impl Controller {
 
     /// Enables the interrupt `int`.
     pub fn enable(&mut self, int: Interrupt) {
-        let (index, offset) = self.index_and_offset(int as u8);
-        self.registers.ENABLE_IRQS[index].write(1 << offset);
+        if int.is_basic() {
+            self.registers.ENABLE_BASIC_IRQS.write(1 << (int as u8 - BASIC_BASE));
+        } else {
+            let (index, offset) = self.index_and_offset(int as u8);
+            self.registers.ENABLE_IRQS[index].write(1 << offset);
+        }
     }
 
     /// Disables the interrupt `int`.
     pub fn disable(&mut self, int: Interrupt) {
-        let (index, offset) = self.index_and_offset(int as u8);
-        self.registers.DISABLE_IRQS[index].write(1 << offset);
+        if int.is_basic() {
+            self.registers.DISABLE_BASIC_IRQS.write(1 << (int as u8 - BASIC_BASE));
+        } else {
+            let (index, offset) = self.index_and_offset(int as u8);
+            self.registers.DISABLE_IRQS[index].write(1 << offset);
+        }
     }
 
     /// Returns `true` if `int` is pending. Otherwise, returns `false`.
     pub fn is_pending(&self, int: Interrupt) -> bool {
-        let (index, offset) = self.index_and_offset(int as u8);
-        (self.registers.IRQ_PENDING[index].read() & (1 << offset)) != 0
+        if int.is_basic() {
+            let offset = int as u8 - BASIC_BASE;
+            (self.registers.IRQ_BASIC_PENDING.read() & (1 << offset)) != 0
+        } else {
+            let (index, offset) = self.index_and_offset(int as u8);
+            (self.registers.IRQ_PENDING[index].read() & (1 << offset)) != 0
+        }
+    }
+
+    /// Returns an iterator over the interrupts that are pending now.
+    ///
+    /// The basic pending register also mirrors some GPU sources and only
+    /// flags the others, so the GPU sources are read from the two GPU pending
+    /// registers instead; each pending interrupt is reported once.
+    pub fn pending(&self) -> Pending {
+        let gpu = (self.registers.IRQ_PENDING[0].read() as u64)
+            | ((self.registers.IRQ_PENDING[1].read() as u64) << 32);
+        let basic = self.registers.IRQ_BASIC_PENDING.read() as u8;
+        Pending::new(gpu, basic)
+    }
+
+    /// Delivers `int` as an FIQ instead of an IRQ. Only one source can be
+    /// the FIQ source; selecting another replaces it. The interrupt should
+    /// not also be enabled as an IRQ.
+    pub fn enable_fiq(&mut self, int: Interrupt) {
+        self.registers.FIQ_CONTROL.write(FIQ_ENABLE | int as u32);
+    }
+
+    /// Stops delivering any interrupt as an FIQ.
+    pub fn disable_fiq(&mut self) {
+        self.registers.FIQ_CONTROL.write(0);
+    }
+
+    /// Returns the interrupt delivered as an FIQ, if any.
+    pub fn fiq(&self) -> Option<Interrupt> {
+        let control = self.registers.FIQ_CONTROL.read();
+        if control & FIQ_ENABLE == 0 {
+            return None;
+        }
+
+        Interrupt::from_number((control & 0x7f) as u8)
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_in_order() {
+        let gpu = (1 << Interrupt::Timer1 as u64) | (1 << 5) | (1 << Interrupt::Uart as u64);
+        let basic = 0b1000_0001;
+        let pending: Vec<Interrupt> = Pending::new(gpu, basic).collect();
+        assert_eq!(
+            pending,
+            vec![
+                Interrupt::Timer1,
+                Interrupt::Uart,
+                Interrupt::ArmTimer,
+                Interrupt::IllegalAccess0,
+            ]
+        );
+
+        assert_eq!(Pending::new(0, 0).next(), None);
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for number in 0..MAX_INTERRUPTS as u8 {
+            if let Some(int) = Interrupt::from_number(number) {
+                assert_eq!(int as u8, number);
+            }
+        }
+        assert_eq!(Interrupt::from_number(MAX_INTERRUPTS as u8), None);
     }
 }
@@ -15,7 +15,11 @@ extern crate volatile;
 
 pub mod timer;
 pub mod uart;
+pub mod pl011;
 pub mod gpio;
 pub mod common;
 pub mod atags;
 pub mod interrupt;
+pub mod generic_timer;
+pub mod local_interrupt;
+pub mod emmc;
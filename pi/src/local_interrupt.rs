@@ -0,0 +1,179 @@
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile};
+
+use generic_timer::Timer;
+
+/// The base address of the BCM2836 local peripherals, which route the
+/// interrupts that belong to each core.
+const LOCAL_BASE: usize = 0x4000_0000;
+
+/// The number of cores the local interrupt controller serves.
+const CORES: usize = 4;
+
+/// A per-core interrupt source. The values are the source's bit in the
+/// core's IRQ and FIQ source registers.
+#[repr(u8)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LocalInterrupt {
+    /// The secure physical generic timer.
+    CntPs = 0,
+    /// The non-secure physical generic timer, `Timer::Physical` at EL1.
+    CntPns = 1,
+    /// The hypervisor physical generic timer.
+    CntHp = 2,
+    /// The virtual generic timer, `Timer::Virtual`.
+    CntV = 3,
+    Mailbox0 = 4,
+    Mailbox1 = 5,
+    Mailbox2 = 6,
+    Mailbox3 = 7,
+    /// An interrupt of the GPU interrupt controller, `pi::interrupt`. Only
+    /// the core the GPU interrupts are routed to sees it.
+    Gpu = 8,
+    Pmu = 9,
+    AxiOutstanding = 10,
+    LocalTimer = 11,
+}
+
+/// The number of local interrupt sources.
+pub const MAX_LOCAL_INTERRUPTS: usize = 12;
+
+impl LocalInterrupt {
+    /// Returns the source that `timer` raises.
+    pub fn for_timer(timer: Timer) -> LocalInterrupt {
+        match timer {
+            Timer::Physical => LocalInterrupt::CntPns,
+            Timer::Virtual => LocalInterrupt::CntV,
+        }
+    }
+
+    /// Returns the source with bit `number`, if any.
+    pub fn from_number(number: u8) -> Option<LocalInterrupt> {
+        use self::LocalInterrupt::*;
+
+        Some(match number {
+            0 => CntPs,
+            1 => CntPns,
+            2 => CntHp,
+            3 => CntV,
+            4 => Mailbox0,
+            5 => Mailbox1,
+            6 => Mailbox2,
+            7 => Mailbox3,
+            8 => Gpu,
+            9 => Pmu,
+            10 => AxiOutstanding,
+            11 => LocalTimer,
+            _ => return None,
+        })
+    }
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CONTROL: Volatile<u32>,
+    __r0: Reserved<u32>,
+    CORE_TIMER_PRESCALER: Volatile<u32>,
+    GPU_INTERRUPT_ROUTING: Volatile<u32>,
+    PMU_ROUTING_SET: Volatile<u32>,
+    PMU_ROUTING_CLEAR: Volatile<u32>,
+    __r1: Reserved<u32>,
+    CORE_TIMER_LS: Volatile<u32>,
+    CORE_TIMER_MS: Volatile<u32>,
+    LOCAL_INTERRUPT_ROUTING: Volatile<u32>,
+    __r2: Reserved<u32>,
+    AXI_OUTSTANDING_COUNTERS: Volatile<u32>,
+    AXI_OUTSTANDING_IRQ: Volatile<u32>,
+    LOCAL_TIMER_CONTROL: Volatile<u32>,
+    LOCAL_TIMER_WRITE_FLAGS: Volatile<u32>,
+    __r3: Reserved<u32>,
+    CORE_TIMER_INTERRUPT_CONTROL: [Volatile<u32>; CORES],
+    CORE_MAILBOX_INTERRUPT_CONTROL: [Volatile<u32>; CORES],
+    CORE_IRQ_SOURCE: [ReadVolatile<u32>; CORES],
+    CORE_FIQ_SOURCE: [ReadVolatile<u32>; CORES],
+}
+
+/// An iterator over a core's pending local interrupts in order of their
+/// source bits.
+#[derive(Debug, Clone)]
+pub struct LocalPending {
+    bits: u32,
+}
+
+impl Iterator for LocalPending {
+    type Item = LocalInterrupt;
+
+    fn next(&mut self) -> Option<LocalInterrupt> {
+        while self.bits != 0 {
+            let bit = self.bits.trailing_zeros();
+            self.bits &= !(1 << bit);
+            if let Some(int) = LocalInterrupt::from_number(bit as u8) {
+                return Some(int);
+            }
+        }
+
+        None
+    }
+}
+
+/// The BCM2836 local interrupt controller, seen from one core. Used to route
+/// the core's generic timer interrupts to it and to check which of its
+/// interrupts are pending.
+pub struct LocalController {
+    core: usize,
+    registers: &'static mut Registers,
+}
+
+impl LocalController {
+    /// Returns a new handle to the local interrupt controller for core
+    /// `core`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `core` is not a core's index.
+    pub fn new(core: usize) -> LocalController {
+        assert!(core < CORES, "local interrupt controller: no core {}", core);
+        LocalController {
+            core,
+            registers: unsafe { &mut *(LOCAL_BASE as *mut Registers) },
+        }
+    }
+
+    /// Routes `timer`'s interrupt to this core as an IRQ.
+    pub fn enable_timer(&mut self, timer: Timer) {
+        let bit = LocalInterrupt::for_timer(timer) as u32;
+        self.registers.CORE_TIMER_INTERRUPT_CONTROL[self.core].or_mask(1 << bit);
+    }
+
+    /// Stops routing `timer`'s interrupt to this core.
+    pub fn disable_timer(&mut self, timer: Timer) {
+        let bit = LocalInterrupt::for_timer(timer) as u32;
+        self.registers.CORE_TIMER_INTERRUPT_CONTROL[self.core].and_mask(!(1 << bit));
+    }
+
+    /// Returns `true` if `int` is pending as an IRQ on this core.
+    pub fn is_pending(&self, int: LocalInterrupt) -> bool {
+        self.registers.CORE_IRQ_SOURCE[self.core].read() & (1 << int as u32) != 0
+    }
+
+    /// Returns an iterator over the interrupts pending as IRQs on this core.
+    pub fn pending(&self) -> LocalPending {
+        LocalPending {
+            bits: self.registers.CORE_IRQ_SOURCE[self.core].read(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pending_in_order() {
+        let pending = LocalPending { bits: (1 << 8) | (1 << 1) | (1 << 31) };
+        let pending: Vec<LocalInterrupt> = pending.collect();
+        assert_eq!(pending, vec![LocalInterrupt::CntPns, LocalInterrupt::Gpu]);
+    }
+}
@@ -0,0 +1,477 @@
+use core::fmt;
+
+use volatile::prelude::*;
+use volatile::{ReadVolatile, Reserved, Volatile, WriteVolatile};
+
+use common::IO_BASE;
+use gpio::{Function, Gpio, GpioBank, Pull, Uninitialized};
+use timer;
+
+/// The base address for the PL011 registers.
+const UART_REG_BASE: usize = IO_BASE + 0x201000;
+
+/// The frequency of the UART reference clock, which `config.txt` pins with
+/// `init_uart_clock`. Unlike the mini UART's, it does not change with the
+/// core clock.
+pub const DEFAULT_CLOCK: u32 = 48_000_000;
+
+/// The baud rate the UART is configured with by `Uart::new()`.
+pub const DEFAULT_BAUD: u32 = 115200;
+
+/// Enum representing bit fields of the `FR` register.
+#[repr(u32)]
+enum FrStatus {
+    Busy = 1 << 3,
+    RxEmpty = 1 << 4,
+    TxFull = 1 << 5,
+}
+
+/// Enum representing bit fields of the `LCRH` register.
+#[repr(u32)]
+enum LcrhSettings {
+    ParityEnable = 1 << 1,
+    EvenParity = 1 << 2,
+    TwoStopBits = 1 << 3,
+    EnableFifos = 1 << 4,
+}
+
+/// Enum representing bit fields of the `CR` register.
+#[repr(u32)]
+enum CrSettings {
+    Enable = 1,
+    EnableTx = 1 << 8,
+    EnableRx = 1 << 9,
+}
+
+/// Enum representing bit fields of the `IMSC`, `RIS`, `MIS` and `ICR`
+/// registers.
+#[repr(u32)]
+enum InterruptBits {
+    Rx = 1 << 4,
+    Tx = 1 << 5,
+    RxTimeout = 1 << 6,
+}
+
+/// The error bits of the `DR` register, which accompany each received byte.
+const DR_ERRORS: u32 = 0b1111 << 8;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    DR: Volatile<u32>,
+    RSRECR: Volatile<u32>,
+    __r0: [Reserved<u32>; 4],
+    FR: ReadVolatile<u32>,
+    __r1: Reserved<u32>,
+    ILPR: Volatile<u32>,
+    IBRD: Volatile<u32>,
+    FBRD: Volatile<u32>,
+    LCRH: Volatile<u32>,
+    CR: Volatile<u32>,
+    IFLS: Volatile<u32>,
+    IMSC: Volatile<u32>,
+    RIS: ReadVolatile<u32>,
+    MIS: ReadVolatile<u32>,
+    ICR: WriteVolatile<u32>,
+    DMACR: Volatile<u32>,
+}
+
+/// The parity bit sent with each byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+/// The number of stop bits sent after each byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    Two,
+}
+
+/// The line settings of a `Uart`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// The frequency of the UART reference clock in Hz.
+    pub clock: u32,
+    /// The baud rate.
+    pub baud: u32,
+    /// The number of data bits per byte, from 5 to 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for Config {
+    /// 115200 baud, 8 data bits, no parity and one stop bit.
+    fn default() -> Config {
+        Config {
+            clock: DEFAULT_CLOCK,
+            baud: DEFAULT_BAUD,
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+        }
+    }
+}
+
+impl Config {
+    /// Returns the integer and fractional (in 64ths) parts of the baud rate
+    /// divisor, `clock / (16 * baud)`, rounded to the nearest 64th.
+    fn divisors(&self) -> (u32, u32) {
+        let baud = self.baud as u64;
+        let divisor = (self.clock as u64 * 4 + baud / 2) / baud;
+        ((divisor >> 6) as u32, (divisor & 0x3f) as u32)
+    }
+
+    /// Returns the value of the `LCRH` register for these settings.
+    fn lcrh(&self) -> u32 {
+        let data_bits = match self.data_bits {
+            5...8 => self.data_bits as u32 - 5,
+            _ => panic!("pl011: unsupported data size: {}", self.data_bits),
+        };
+
+        let mut lcrh = (data_bits << 5) | LcrhSettings::EnableFifos as u32;
+        match self.parity {
+            Parity::None => {}
+            Parity::Odd => lcrh |= LcrhSettings::ParityEnable as u32,
+            Parity::Even => {
+                lcrh |= LcrhSettings::ParityEnable as u32 | LcrhSettings::EvenParity as u32
+            }
+        }
+
+        if self.stop_bits == StopBits::Two {
+            lcrh |= LcrhSettings::TwoStopBits as u32;
+        }
+
+        lcrh
+    }
+}
+
+/// An error reported with a received byte.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The byte did not end with a valid stop bit.
+    Framing,
+    /// The byte's parity did not match the configured parity.
+    Parity,
+    /// The line was held low for longer than a byte.
+    Break,
+    /// The receive FIFO was full and bytes were lost before this one.
+    Overrun,
+}
+
+impl Error {
+    /// Returns the error reported by the error bits of `dr`, if any. The
+    /// most severe error is reported if there are several.
+    fn from_dr(dr: u32) -> Option<Error> {
+        if dr & (1 << 10) != 0 {
+            Some(Error::Break)
+        } else if dr & (1 << 8) != 0 {
+            Some(Error::Framing)
+        } else if dr & (1 << 9) != 0 {
+            Some(Error::Parity)
+        } else if dr & (1 << 11) != 0 {
+            Some(Error::Overrun)
+        } else {
+            None
+        }
+    }
+}
+
+/// The Raspberry Pi's PL011 UART.
+pub struct Uart {
+    registers: &'static mut Registers,
+    timeout: Option<u32>,
+}
+
+impl Uart {
+    /// Initializes the UART with the default `Config`. See
+    /// `Uart::with_config()`.
+    pub fn new(bank: &mut GpioBank) -> Uart {
+        Uart::with_config(Config::default(), bank)
+    }
+
+    /// Initializes the UART by disabling it, waiting for any transmission to
+    /// finish, setting GPIO pins 14 and 15, taken from `bank`, to alternative
+    /// function 0 (TXD0/RXD0), programming the baud rate divisors and line settings from
+    /// `config`, and finally enabling the UART, its FIFOs, transmitter and
+    /// receiver.
+    ///
+    /// By default, reads will never time out. To set a read timeout, use
+    /// `set_read_timeout()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config` has an unsupported number of data bits or a baud
+    /// rate the clock cannot produce, or if GPIO pin 14 or 15 was already
+    /// taken from `bank`.
+    pub fn with_config(config: Config, bank: &mut GpioBank) -> Uart {
+        let gpio14 = bank.take(14).expect("pl011: GPIO pin 14 taken");
+        let gpio15 = bank.take(15).expect("pl011: GPIO pin 15 taken");
+        Uart::with_pins(config, gpio14, gpio15)
+    }
+
+    /// Like `with_config()`, but with GPIO pins 14 and 15 already taken from
+    /// their owner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `config` is unsupported, as `with_config()` does, or if the
+    /// pins are not pins 14 and 15.
+    pub fn with_pins(
+        config: Config,
+        gpio14: Gpio<Uninitialized>,
+        gpio15: Gpio<Uninitialized>,
+    ) -> Uart {
+        assert!(gpio14.pin() == 14 && gpio15.pin() == 15, "pl011: wrong pins");
+        let registers = unsafe { &mut *(UART_REG_BASE as *mut Registers) };
+
+        registers.CR.write(0);
+        while registers.FR.read() & (FrStatus::Busy as u32) != 0 {}
+
+        let mut gpio14 = gpio14.into_alt(Function::Alt0);
+        let mut gpio15 = gpio15.into_alt(Function::Alt0);
+        gpio14.set_pull(Pull::None);
+        gpio15.set_pull(Pull::None);
+
+        let (integer, fraction) = config.divisors();
+        if integer == 0 || integer > 0xffff {
+            panic!("pl011: unsupported baud rate: {}", config.baud);
+        }
+
+        // Mask and clear all interrupts.
+        registers.IMSC.write(0);
+        registers.ICR.write(0x7ff);
+
+        // The divisors only take effect when `LCRH` is written.
+        registers.IBRD.write(integer);
+        registers.FBRD.write(fraction);
+        registers.LCRH.write(config.lcrh());
+
+        registers.CR.write(
+            CrSettings::Enable as u32 | CrSettings::EnableTx as u32 | CrSettings::EnableRx as u32,
+        );
+
+        Uart {
+            registers,
+            timeout: None,
+        }
+    }
+
+    /// Set the read timeout to `milliseconds` milliseconds.
+    pub fn set_read_timeout(&mut self, milliseconds: u32) {
+        self.timeout = Some(milliseconds);
+    }
+
+    #[inline]
+    fn set_interrupt(&mut self, bits: u32, enabled: bool) {
+        if enabled {
+            self.registers.IMSC.or_mask(bits);
+        } else {
+            self.registers.IMSC.and_mask(!bits);
+        }
+    }
+
+    /// Enables or disables the receive interrupts, which are raised while
+    /// the receive FIFO is filling up or holds bytes that have not been read
+    /// for a while.
+    pub fn set_rx_interrupt(&mut self, enabled: bool) {
+        self.set_interrupt(InterruptBits::Rx as u32 | InterruptBits::RxTimeout as u32, enabled);
+    }
+
+    /// Enables or disables the transmit interrupt, which is raised while the
+    /// transmit FIFO is draining.
+    pub fn set_tx_interrupt(&mut self, enabled: bool) {
+        self.set_interrupt(InterruptBits::Tx as u32, enabled);
+    }
+
+    /// Clears all pending interrupts. The receive interrupts are raised again
+    /// while their condition holds.
+    pub fn clear_interrupts(&mut self) {
+        self.registers.ICR.write(0x7ff);
+    }
+
+    /// Returns `true` if there is space available in the transmit FIFO. If
+    /// this method returns `true`, a subsequent call to `write_byte` is
+    /// guaranteed to return immediately. This method does not block.
+    pub fn can_write(&self) -> bool {
+        self.registers.FR.read() & (FrStatus::TxFull as u32) == 0
+    }
+
+    /// Write the byte `byte`. This method blocks until there is space
+    /// available in the transmit FIFO.
+    pub fn write_byte(&mut self, byte: u8) {
+        while !self.can_write() {
+            timer::spin_sleep_us(10);
+        }
+        self.registers.DR.write(byte as u32);
+    }
+
+    /// Returns `true` if there is at least one byte ready to be read. If this
+    /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
+    /// return immediately. This method does not block.
+    pub fn has_byte(&self) -> bool {
+        self.registers.FR.read() & (FrStatus::RxEmpty as u32) == 0
+    }
+
+    /// Blocks until there is a byte ready to read. If a read timeout is set,
+    /// this method blocks for at most that amount of time. Otherwise, this
+    /// method blocks indefinitely until there is a byte to read.
+    ///
+    /// Returns `Ok(())` if a byte is ready to read. Returns `Err(())` if the
+    /// timeout expired while waiting for a byte to be ready.
+    pub fn wait_for_byte(&self) -> Result<(), ()> {
+        let deadline = self.timeout
+            .map(|milliseconds| timer::current_time() + milliseconds as u64 * 1000);
+
+        while !self.has_byte() {
+            if deadline.map_or(false, |deadline| timer::current_time() >= deadline) {
+                return Err(());
+            }
+
+            timer::spin_sleep_us(10);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a byte, blocking indefinitely until a byte is ready to be read.
+    /// If the byte was received with an error, it is discarded and the error
+    /// is returned instead.
+    pub fn try_read_byte(&mut self) -> Result<u8, Error> {
+        while !self.has_byte() {
+            timer::spin_sleep_us(10);
+        }
+
+        let dr = self.registers.DR.read();
+        if dr & DR_ERRORS != 0 {
+            self.registers.RSRECR.write(0);
+        }
+
+        match Error::from_dr(dr) {
+            Some(error) => Err(error),
+            None => Ok(dr as u8),
+        }
+    }
+
+    /// Reads a byte. Blocks indefinitely until a byte is ready to be read.
+    /// Bytes received with errors are skipped.
+    pub fn read_byte(&mut self) -> u8 {
+        loop {
+            match self.try_read_byte() {
+                Ok(byte) => return byte,
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+// A b'\r' byte should be written before writing any b'\n' byte.
+impl fmt::Write for Uart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            if b == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(b);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+mod uart_io {
+    use super::{FrStatus, Uart};
+    use std::io;
+    use timer;
+    use volatile::Readable;
+
+    // Like the mini UART's, reads wait at most the read timeout for the first
+    // byte and then read as many bytes as are available.
+    impl io::Read for Uart {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if let Err(_) = self.wait_for_byte() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "pl011: read timeout",
+                ));
+            }
+
+            let mut n = 0;
+            while n < buf.len() && self.has_byte() {
+                match self.try_read_byte() {
+                    Ok(byte) => {
+                        buf[n] = byte;
+                        n += 1;
+                    }
+                    Err(_) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "pl011: receive error",
+                        ))
+                    }
+                }
+            }
+
+            Ok(n)
+        }
+    }
+
+    impl io::Write for Uart {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            for b in buf {
+                self.write_byte(*b);
+            }
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            while (self.registers.FR.read() & (FrStatus::Busy as u32)) != 0 {
+                timer::spin_sleep_us(10);
+            }
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baud_divisors() {
+        let config = Config::default();
+        assert_eq!(config.divisors(), (26, 3));
+
+        let config = Config { clock: 3_000_000, baud: 9600, ..Config::default() };
+        assert_eq!(config.divisors(), (19, 34));
+    }
+
+    #[test]
+    fn line_settings() {
+        assert_eq!(Config::default().lcrh(), 0b0111_0000);
+
+        let config = Config {
+            data_bits: 7,
+            parity: Parity::Even,
+            stop_bits: StopBits::Two,
+            ..Config::default()
+        };
+        assert_eq!(config.lcrh(), 0b0101_1110);
+    }
+
+    #[test]
+    fn receive_errors() {
+        assert_eq!(Error::from_dr(b'a' as u32), None);
+        assert_eq!(Error::from_dr(1 << 8), Some(Error::Framing));
+        assert_eq!(Error::from_dr(1 << 11 | 1 << 9), Some(Error::Parity));
+        assert_eq!(Error::from_dr(0b1111 << 8), Some(Error::Break));
+    }
+}
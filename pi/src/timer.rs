@@ -1,4 +1,8 @@
+use core::cmp::max;
+use core::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
 use common::IO_BASE;
+use interrupt::Interrupt;
 use volatile::prelude::*;
 use volatile::{ReadVolatile, Volatile};
 
@@ -14,6 +18,75 @@ struct Registers {
     COMPARE: [Volatile<u32>; 4],
 }
 
+/// The shortest delay, in microseconds, that a compare channel is armed
+/// with. A channel only matches when the counter equals its compare value
+/// exactly, so a compare value that has already passed by the time it is
+/// written would not match until the counter wraps.
+const MIN_DELAY: u32 = 20;
+
+/// A compare channel of the system timer that is free for the ARM to use.
+/// Channels 0 and 2 are used by the GPU.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    One = 1,
+    Three = 3,
+}
+
+impl Channel {
+    /// Returns the interrupt raised when this channel matches.
+    pub fn interrupt(self) -> Interrupt {
+        match self {
+            Channel::One => Interrupt::Timer1,
+            Channel::Three => Interrupt::Timer3,
+        }
+    }
+
+    #[inline]
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    #[inline]
+    fn match_bit(self) -> u32 {
+        1 << (self as u32)
+    }
+}
+
+/// The channels handed out by `allocate_channel()`, as match bits.
+static ALLOCATED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Takes a free compare channel for exclusive use. Returns `None` if both are
+/// taken.
+pub fn allocate_channel() -> Option<Channel> {
+    for &channel in [Channel::One, Channel::Three].iter() {
+        let bit = channel.match_bit() as usize;
+        if ALLOCATED.fetch_or(bit, Ordering::AcqRel) & bit == 0 {
+            return Some(channel);
+        }
+    }
+
+    None
+}
+
+/// Returns `channel`, taken with `allocate_channel()`, so that it can be
+/// allocated again.
+pub fn release_channel(channel: Channel) {
+    ALLOCATED.fetch_and(!(channel.match_bit() as usize), Ordering::AcqRel);
+}
+
+/// Returns the compare value that follows `compare` by `period`
+/// microseconds, or, if that is less than `MIN_DELAY` ahead of `now` or has
+/// passed, the value `MIN_DELAY` after `now`. All values wrap.
+fn next_compare(compare: u32, period: u32, now: u32) -> u32 {
+    let next = compare.wrapping_add(period);
+    if (next.wrapping_sub(now) as i32) < MIN_DELAY as i32 {
+        now.wrapping_add(MIN_DELAY)
+    } else {
+        next
+    }
+}
+
 /// The Raspberry Pi ARM system timer.
 pub struct Timer {
     registers: &'static mut Registers,
@@ -30,20 +103,46 @@ impl Timer {
     /// Reads the system timer's counter and returns the 64-bit counter value.
     /// The returned value is the number of elapsed microseconds.
     pub fn read(&self) -> u64 {
-        ((self.registers.CHI.read() as u64) << 32) | (self.registers.CLO.read() as u64)
+        // The two halves are read separately, so `CLO` may wrap between the
+        // reads; read again if `CHI` changed meanwhile.
+        loop {
+            let hi = self.registers.CHI.read();
+            let lo = self.registers.CLO.read();
+            if self.registers.CHI.read() == hi {
+                return ((hi as u64) << 32) | (lo as u64);
+            }
+        }
     }
 
-    /// Sets up a match in timer 1 to occur `us` microseconds from now. If
-    /// interrupts for timer 1 are enabled and IRQs are unmasked, then a timer
-    /// interrupt will be issued in `us` microseconds.
-    pub fn tick_in(&mut self, us: u32) {
-        let future = self.registers.CLO.read().wrapping_add(us);
+    /// Arms `channel` to match `us` microseconds from now, at least
+    /// `MIN_DELAY` microseconds. If interrupts for the channel are enabled and
+    /// IRQs are unmasked, then its interrupt will be issued then.
+    pub fn arm(&mut self, channel: Channel, us: u32) {
+        let future = self.registers.CLO.read().wrapping_add(max(us, MIN_DELAY));
+        self.clear(channel);
+        self.registers.COMPARE[channel.index()].write(future);
+    }
 
-        // clear the timer 1 match detect status
-        self.registers.CS.write(1 << 1);
+    /// Rearms `channel` to match `period` microseconds after it last matched,
+    /// so that a periodic alarm does not drift with the time taken to handle
+    /// it. If that time has passed, the channel matches shortly instead.
+    pub fn rearm(&mut self, channel: Channel, period: u32) {
+        let compare = self.registers.COMPARE[channel.index()].read();
+        let next = next_compare(compare, period, self.registers.CLO.read());
+        self.clear(channel);
+        self.registers.COMPARE[channel.index()].write(next);
+    }
 
-        // set the compare value for timer 1
-        self.registers.COMPARE[1].write(future);
+    /// Clears `channel`'s match status, which deasserts its interrupt.
+    pub fn clear(&mut self, channel: Channel) {
+        // Writing a `1` clears the status; the other channels' are left
+        // alone.
+        self.registers.CS.write(channel.match_bit());
+    }
+
+    /// Returns `true` if `channel` has matched since its status was cleared.
+    pub fn is_matched(&self, channel: Channel) -> bool {
+        self.registers.CS.read() & channel.match_bit() != 0
     }
 }
 
@@ -63,9 +162,31 @@ pub fn spin_sleep_ms(ms: u64) {
     spin_sleep_us(ms * 1000)
 }
 
-/// Sets up a match in timer 1 to occur `us` microseconds from now. If
-/// interrupts for timer 1 are enabled and IRQs are unmasked, then a timer
-/// interrupt will be issued in `us` microseconds.
-pub fn tick_in(us: u32) {
-    Timer::new().tick_in(us);
+#[cfg(feature = "std")]
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn periodic_compare_values() {
+        assert_eq!(next_compare(1000, 500, 1100), 1500);
+        assert_eq!(next_compare(u32::max_value() - 99, 500, u32::max_value()), 400);
+
+        // A period that has already passed, or nearly so, restarts from now.
+        assert_eq!(next_compare(1000, 500, 1600), 1600 + MIN_DELAY);
+        assert_eq!(next_compare(1000, 500, 1495), 1495 + MIN_DELAY);
+    }
+
+    #[test]
+    fn channels_are_allocated_once() {
+        let first = allocate_channel().expect("first channel");
+        let second = allocate_channel().expect("second channel");
+        assert!(first != second);
+        assert_eq!(allocate_channel(), None);
+
+        release_channel(first);
+        assert_eq!(allocate_channel(), Some(first));
+        release_channel(first);
+        release_channel(second);
+    }
 }
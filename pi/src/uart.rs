@@ -4,7 +4,7 @@ use volatile::prelude::*;
 use volatile::{ReadVolatile, Volatile};
 
 use common::IO_BASE;
-use gpio::{Alt, Function, Gpio};
+use gpio::{Alt, Function, Gpio, GpioBank, Uninitialized};
 use timer;
 
 /// The base address for the `MU` registers.
@@ -31,6 +31,16 @@ enum CntlSettings {
     EnableTx = 1 << 1,
 }
 
+/// Enum representing bit fields of the `AUX_MU_IER_REG` register. The
+/// BCM2837 documentation has the two enable bits swapped and marks bits 3:2
+/// as unused, but interrupts are only raised while they are set.
+#[repr(u32)]
+enum IerSettings {
+    RxInterrupt = 1,
+    TxInterrupt = 1 << 1,
+    InterruptLines = 0b11 << 2,
+}
+
 #[repr(u32)]
 enum IirSettings {
     ClearRxFifo = 1 << 1,
@@ -67,9 +77,26 @@ impl MiniUart {
     ///
     /// By default, reads will never time out. To set a read timeout, use
     /// `set_read_timeout()`.
-    pub fn new() -> MiniUart {
-        let gpio14 = Gpio::new(14).into_alt(Function::Alt5);
-        let gpio15 = Gpio::new(15).into_alt(Function::Alt5);
+    ///
+    /// # Panics
+    ///
+    /// Panics if GPIO pin 14 or 15 was already taken from `bank`.
+    pub fn new(bank: &mut GpioBank) -> MiniUart {
+        let gpio14 = bank.take(14).expect("uart: GPIO pin 14 taken");
+        let gpio15 = bank.take(15).expect("uart: GPIO pin 15 taken");
+        MiniUart::with_pins(gpio14, gpio15)
+    }
+
+    /// Like `new()`, but with GPIO pins 14 and 15 already taken from their
+    /// owner.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pins are not pins 14 and 15.
+    pub fn with_pins(gpio14: Gpio<Uninitialized>, gpio15: Gpio<Uninitialized>) -> MiniUart {
+        assert!(gpio14.pin() == 14 && gpio15.pin() == 15, "uart: wrong pins");
+        let gpio14 = gpio14.into_alt(Function::Alt5);
+        let gpio15 = gpio15.into_alt(Function::Alt5);
         MiniUart::new_inner(AUX_ENABLES, MU_REG_BASE, gpio14, gpio15)
     }
 
@@ -147,6 +174,35 @@ impl MiniUart {
         self.registers.LSR.read() & (LsrStatus::TxAvailable as u32) == 0
     }
 
+    #[inline]
+    fn set_interrupt(&mut self, interrupt: IerSettings, enabled: bool) {
+        let ier = self.registers.IER.read() | IerSettings::InterruptLines as u32;
+        if enabled {
+            self.registers.IER.write(ier | interrupt as u32);
+        } else {
+            self.registers.IER.write(ier & !(interrupt as u32));
+        }
+    }
+
+    /// Enables or disables the receive interrupt, which is raised while there
+    /// is a byte ready to be read.
+    pub fn set_rx_interrupt(&mut self, enabled: bool) {
+        self.set_interrupt(IerSettings::RxInterrupt, enabled);
+    }
+
+    /// Enables or disables the transmit interrupt, which is raised while the
+    /// output FIFO is empty.
+    pub fn set_tx_interrupt(&mut self, enabled: bool) {
+        self.set_interrupt(IerSettings::TxInterrupt, enabled);
+    }
+
+    /// Returns `true` if there is space available in the output FIFO. If this
+    /// method returns `true`, a subsequent call to `write_byte` is guaranteed
+    /// to return immediately. This method does not block.
+    pub fn can_write(&self) -> bool {
+        !self.write_fifo_full()
+    }
+
     /// Write the byte `byte`. This method blocks until there is space available
     /// in the output FIFO.
     pub fn write_byte(&mut self, byte: u8) {
@@ -271,7 +327,7 @@ mod uart_io {
 
         // TODO figure out if flush should timeout
         fn flush(&mut self) -> io::Result<()> {
-            while (self.registers.LSR.read() & (LsrStatus::TxIdle as u32)) != 1 {
+            while (self.registers.LSR.read() & (LsrStatus::TxIdle as u32)) == 0 {
                 timer::spin_sleep_us(10);
             }
 